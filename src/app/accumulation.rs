@@ -0,0 +1,249 @@
+//"Long exposure" mode. Particles are drawn into a persistent hdr texture that is faded
+//by a constant factor every frame, so their paths build up into light paintings.
+
+use std::sync::mpsc;
+
+use anyhow::*;
+
+use super::{
+    camera::{CameraMatrix, FatCamera},
+    gpu::Gpu,
+    post::{fullscreen_pipeline, PostProcess},
+    texture::Texture,
+};
+
+#[derive(Debug, Copy, Clone)]
+pub struct AccumulationSettings {
+    //Fraction of the accumulated light kept each frame
+    pub decay: f32,
+    pub clear_on_camera_move: bool,
+}
+
+impl Default for AccumulationSettings {
+    fn default() -> Self {
+        AccumulationSettings {
+            decay: 0.97,
+            clear_on_camera_move: true,
+        }
+    }
+}
+
+pub struct Accumulation {
+    pub settings: AccumulationSettings,
+    //Multisampled with MSAA on. The particle pass then resolves it into the hdr texture.
+    pub texture: Texture,
+    pub sample_count: u32,
+    decay_pipeline: wgpu::RenderPipeline,
+    last_view: Option<CameraMatrix>,
+    needs_clear: bool,
+}
+
+impl Accumulation {
+    pub fn new(gpu: &Gpu, width: u32, height: u32) -> Accumulation {
+        Accumulation {
+            settings: AccumulationSettings::default(),
+            texture: Self::create_texture(gpu, width, height, 1),
+            sample_count: 1,
+            decay_pipeline: Self::build_decay_pipeline(gpu, 1),
+            last_view: None,
+            needs_clear: true,
+        }
+    }
+
+    fn build_decay_pipeline(gpu: &Gpu, sample_count: u32) -> wgpu::RenderPipeline {
+        let shader = gpu
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Accumulation Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    include_str!("../shaders/accumulation.wgsl").into(),
+                ),
+            });
+        let layout = gpu
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Accumulation Pipeline Layout"),
+                bind_group_layouts: &[],
+                push_constant_ranges: &[],
+            });
+        let decay = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Zero,
+            dst_factor: wgpu::BlendFactor::Constant,
+            operation: wgpu::BlendOperation::Add,
+        };
+        fullscreen_pipeline(
+            gpu,
+            &layout,
+            &shader,
+            "fs_decay",
+            PostProcess::HDR_FORMAT,
+            Some(wgpu::BlendState {
+                color: decay,
+                alpha: decay,
+            }),
+            sample_count,
+        )
+    }
+
+    fn create_texture(gpu: &Gpu, width: u32, height: u32, sample_count: u32) -> Texture {
+        if sample_count > 1 {
+            Texture::create_multisampled_target(
+                &gpu.device,
+                width,
+                height,
+                PostProcess::HDR_FORMAT,
+                sample_count,
+                "accumulation_texture",
+            )
+        } else {
+            Texture::create_render_target(
+                &gpu.device,
+                width,
+                height,
+                PostProcess::HDR_FORMAT,
+                "accumulation_texture",
+            )
+        }
+    }
+
+    pub fn resize(&mut self, gpu: &Gpu, width: u32, height: u32, sample_count: u32) {
+        if sample_count != self.sample_count {
+            self.decay_pipeline = Self::build_decay_pipeline(gpu, sample_count);
+            self.sample_count = sample_count;
+        }
+        self.texture = Self::create_texture(gpu, width, height, sample_count);
+        self.needs_clear = true;
+    }
+
+    pub fn clear(&mut self) {
+        self.needs_clear = true;
+    }
+
+    //Fades (or clears) the accumulated image, ready for this frame's particles to be added
+    pub fn decay(&mut self, encoder: &mut wgpu::CommandEncoder, fat_cam: &FatCamera) {
+        let view = fat_cam.matrices.view;
+        if self.settings.clear_on_camera_move && self.last_view != Some(view) {
+            self.needs_clear = true;
+        }
+        self.last_view = Some(view);
+
+        let load = if self.needs_clear {
+            wgpu::LoadOp::Clear(wgpu::Color::BLACK)
+        } else {
+            wgpu::LoadOp::Load
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Accumulation Decay Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.texture.view,
+                resolve_target: None,
+                ops: wgpu::Operations { load, store: true },
+            })],
+            depth_stencil_attachment: None,
+        });
+        if !self.needs_clear {
+            let d = self.settings.decay as f64;
+            render_pass.set_pipeline(&self.decay_pipeline);
+            render_pass.set_blend_constant(wgpu::Color {
+                r: d,
+                g: d,
+                b: d,
+                a: d,
+            });
+            render_pass.draw(0..3, 0..1);
+        }
+        self.needs_clear = false;
+    }
+}
+
+//Saves an hdr texture (the resolved accumulation) as a 16 bit per channel png. Values are
+//scaled by exposure and sRGB encoded but not tone mapped, so nothing below 1.0 gets crushed.
+pub fn export_png(gpu: &Gpu, texture: &Texture, path: &str, exposure: f32) -> Result<()> {
+    let size = texture.size;
+    let (width, height) = (size.width, size.height);
+    let unpadded_bytes_per_row = width * 8;
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+
+    let buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Accumulation Readback Buffer"),
+        size: (bytes_per_row * height) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = gpu
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Accumulation Export Encoder"),
+        });
+    encoder.copy_texture_to_buffer(
+        texture.texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(bytes_per_row),
+                rows_per_image: std::num::NonZeroU32::new(height),
+            },
+        },
+        size,
+    );
+    gpu.queue.submit([encoder.finish()]);
+
+    let slice = buffer.slice(..);
+    let (sender, receiver) = mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        sender.send(result).ok();
+    });
+    gpu.device.poll(wgpu::Maintain::Wait);
+    receiver.recv()??;
+
+    let mut pixels: Vec<u16> = Vec::with_capacity((width * height * 4) as usize);
+    {
+        let data = slice.get_mapped_range();
+        for row in 0..height {
+            let start = (row * bytes_per_row) as usize;
+            let row_data = &data[start..start + unpadded_bytes_per_row as usize];
+            for (i, half) in row_data.chunks_exact(2).enumerate() {
+                let value = f16_to_f32(u16::from_le_bytes([half[0], half[1]]));
+                let encoded = if i % 4 == 3 {
+                    1.0
+                } else {
+                    linear_to_srgb(value * exposure)
+                };
+                pixels.push((encoded.clamp(0.0, 1.0) * 65535.0).round() as u16);
+            }
+        }
+    }
+    buffer.unmap();
+
+    let image = image::ImageBuffer::<image::Rgba<u16>, _>::from_raw(width, height, pixels)
+        .context("accumulation image has the wrong size")?;
+    image.save(path)?;
+    Ok(())
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        31 => {
+            if mantissa == 0.0 {
+                sign * f32::INFINITY
+            } else {
+                f32::NAN
+            }
+        }
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
@@ -0,0 +1,90 @@
+//Flocking for Behaviour::Boids. Every particle steers away from, along with and towards
+//the particles within the perception radius, found through the spatial hash, see boids.wgsl.
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use super::gpu::Gpu;
+
+#[derive(Debug, Copy, Clone)]
+pub struct BoidSettings {
+    pub separation: f32,
+    pub alignment: f32,
+    pub cohesion: f32,
+    //Also the spatial hash cell size while boids run
+    pub perception_radius: f32,
+    pub max_speed: f32,
+    //Longest each of the steering accelerations can get
+    pub max_force: f32,
+    //0 flocks only, 1 follows the curl field like Behaviour::Field
+    pub field_mix: f32,
+}
+
+impl Default for BoidSettings {
+    fn default() -> Self {
+        BoidSettings {
+            separation: 1.5,
+            alignment: 1.0,
+            cohesion: 1.0,
+            perception_radius: 4.0,
+            max_speed: 30.0,
+            max_force: 60.0,
+            field_mix: 0.5,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct BoidParameters {
+    separation: f32,
+    alignment: f32,
+    cohesion: f32,
+    perception_radius: f32,
+    max_speed: f32,
+    max_force: f32,
+    field_mix: f32,
+    _padding: f32,
+}
+
+impl From<&BoidSettings> for BoidParameters {
+    fn from(settings: &BoidSettings) -> Self {
+        BoidParameters {
+            separation: settings.separation,
+            alignment: settings.alignment,
+            cohesion: settings.cohesion,
+            perception_radius: settings.perception_radius,
+            max_speed: settings.max_speed,
+            max_force: settings.max_force,
+            field_mix: settings.field_mix,
+            _padding: 0.0,
+        }
+    }
+}
+
+pub struct Boids {
+    pub settings: BoidSettings,
+    pub buffer: wgpu::Buffer,
+}
+
+impl Boids {
+    pub fn new(gpu: &Gpu) -> Boids {
+        let settings = BoidSettings::default();
+        let buffer = gpu
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Boid Parameters"),
+                contents: bytemuck::bytes_of(&BoidParameters::from(&settings)),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+        Boids { settings, buffer }
+    }
+
+    pub fn write(&self, gpu: &Gpu) {
+        gpu.queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::bytes_of(&BoidParameters::from(&self.settings)),
+        );
+    }
+}
@@ -8,7 +8,7 @@ use std::f32::consts::FRAC_PI_2;
 
 use super::FPSCamera;
 
-const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
+pub const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
 
 #[derive(Debug)]
 pub struct FPSCameraController {
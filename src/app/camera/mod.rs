@@ -2,12 +2,21 @@ pub mod controller;
 pub mod projection;
 use std::time::Duration;
 
-use cgmath::{InnerSpace, Matrix4, Point3, Rad, SquareMatrix, Vector3};
+use cgmath::{
+    EuclideanSpace, InnerSpace, Matrix3, Matrix4, Point3, Quaternion, Rad, SquareMatrix, Vector3,
+    Vector4,
+};
 use wgpu::util::DeviceExt;
 
-use self::{controller::FPSCameraController, projection::Projection};
+use self::{
+    controller::{FPSCameraController, SAFE_FRAC_PI_2},
+    projection::Projection,
+};
 
-use super::{gpu::Gpu, math::UVec2};
+use super::{
+    gpu::Gpu,
+    math::{FVec2, UVec2},
+};
 
 #[derive(Debug)]
 pub struct FPSCamera {
@@ -16,16 +25,51 @@ pub struct FPSCamera {
     pitch: Rad<f32>,
 }
 
+//Axis aligned views of the origin, like the numpad views of modelling tools
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ViewPreset {
+    //Looking down -z
+    Front,
+    //Looking down -x
+    Right,
+    //Looking down -y, with -z up on screen
+    Top,
+}
+
+impl ViewPreset {
+    //Yaw and pitch that look along the preset axis
+    fn angles(self) -> (cgmath::Deg<f32>, cgmath::Deg<f32>) {
+        match self {
+            ViewPreset::Front => (cgmath::Deg(-90.0), cgmath::Deg(0.0)),
+            ViewPreset::Right => (cgmath::Deg(180.0), cgmath::Deg(0.0)),
+            ViewPreset::Top => (cgmath::Deg(-90.0), cgmath::Deg(-90.0)),
+        }
+    }
+}
+
 pub struct CameraMatrices {
     pub view: CameraMatrix,
     pub projection: CameraMatrix,
     pub view_inverse: CameraMatrix,
+    pub projection_inverse: CameraMatrix,
+}
+
+impl CameraMatrices {
+    pub fn new(view: Matrix4<f32>, projection: Matrix4<f32>) -> CameraMatrices {
+        CameraMatrices {
+            view: CameraMatrix::from_matrix(view),
+            projection: CameraMatrix::from_matrix(projection),
+            view_inverse: CameraMatrix::from_matrix(view.invert().unwrap()),
+            projection_inverse: CameraMatrix::from_matrix(projection.invert().unwrap()),
+        }
+    }
 }
 
 pub struct CameraMatrixBuffers {
     pub view: wgpu::Buffer,
     pub projection: wgpu::Buffer,
     pub view_inverse: wgpu::Buffer,
+    pub projection_inverse: wgpu::Buffer,
 }
 
 pub struct FatCamera {
@@ -40,34 +84,55 @@ pub struct FatCamera {
 
 impl FatCamera {
     fn calc_camera_matrices(&self) -> CameraMatrices {
-        let view_matrix = CameraMatrix::from_camera(&self.camera);
-        CameraMatrices {
-            view: view_matrix,
-            projection: CameraMatrix::from_projection(&self.projection),
-            view_inverse: CameraMatrix::from_camera_inverse(&self.camera),
-        }
+        CameraMatrices::new(self.camera.calc_matrix(), self.projection.calc_matrix())
     }
     pub fn update_camera(&mut self, gpu: &Gpu) {
         self.controller
             .update_camera(&mut self.camera, Duration::from_secs_f32(1.0 / 60.0));
         self.matrices = self.calc_camera_matrices();
+        self.write_matrices(gpu, &self.matrices);
+    }
+
+    //Points the camera bind group at other matrices until the next update_camera. Offline
+    //renders use this to draw extra views through the usual pipelines.
+    pub fn write_matrices(&self, gpu: &Gpu, matrices: &CameraMatrices) {
         gpu.queue.write_buffer(
             &self.matrix_buffers.view,
             0,
-            bytemuck::cast_slice(&[self.matrices.view]),
+            bytemuck::cast_slice(&[matrices.view]),
         );
         gpu.queue.write_buffer(
             &self.matrix_buffers.projection,
             0,
-            bytemuck::cast_slice(&[self.matrices.projection]),
+            bytemuck::cast_slice(&[matrices.projection]),
         );
         gpu.queue.write_buffer(
             &self.matrix_buffers.view_inverse,
             0,
-            bytemuck::cast_slice(&[self.matrices.view_inverse]),
+            bytemuck::cast_slice(&[matrices.view_inverse]),
+        );
+        gpu.queue.write_buffer(
+            &self.matrix_buffers.projection_inverse,
+            0,
+            bytemuck::cast_slice(&[matrices.projection_inverse]),
         );
     }
 
+    //World space ray under a cursor position in pixels, from the near plane through the far
+    //plane. Works for both projections, orthographic rays start off the camera position.
+    pub fn cursor_ray(&self, cursor: FVec2, size: UVec2) -> (Point3<f32>, Vector3<f32>) {
+        let ndc_x = cursor.x / size.x.max(1) as f32 * 2.0 - 1.0;
+        let ndc_y = 1.0 - cursor.y / size.y.max(1) as f32 * 2.0;
+        let inverse = Matrix4::from(self.matrices.view_inverse.mat)
+            * Matrix4::from(self.matrices.projection_inverse.mat);
+        let unproject = |depth: f32| {
+            let p = inverse * Vector4::new(ndc_x, ndc_y, depth, 1.0);
+            Point3::from_homogeneous(p)
+        };
+        let (near, far) = (unproject(0.0), unproject(1.0));
+        (near, (far - near).normalize())
+    }
+
     fn create_matrix_buffers(gpu: &Gpu, matrices: &CameraMatrices) -> CameraMatrixBuffers {
         let view_buffer = gpu
             .device
@@ -90,10 +155,18 @@ impl FatCamera {
                     contents: bytemuck::cast_slice(&[matrices.view_inverse]),
                     usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
                 });
+        let projection_inverse_buffer =
+            gpu.device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Camera Projection Inverse Matrix Buffer"),
+                    contents: bytemuck::cast_slice(&[matrices.projection_inverse]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                });
         CameraMatrixBuffers {
             view: view_buffer,
             projection: projection_buffer,
             view_inverse: view_inverse_buffer,
+            projection_inverse: projection_inverse_buffer,
         }
     }
 
@@ -104,9 +177,10 @@ impl FatCamera {
         sensitivity: f32,
         fovy: cgmath::Deg<f32>,
         position: Point3<f32>,
+        (znear, zfar): (f32, f32),
     ) -> FatCamera {
         let camera = FPSCamera::new(position, cgmath::Deg(-90.0), cgmath::Deg(0.0));
-        let projection = Projection::new(size.x, size.y, fovy, 0.0001, 100000.0);
+        let projection = Projection::new(size.x, size.y, fovy, znear, zfar);
         let controller = FPSCameraController::new(speed, sensitivity);
         let bind_group_layout =
             gpu.device
@@ -114,7 +188,9 @@ impl FatCamera {
                     entries: &[
                         wgpu::BindGroupLayoutEntry {
                             binding: 0,
-                            visibility: wgpu::ShaderStages::VERTEX,
+                            visibility: wgpu::ShaderStages::VERTEX
+                                | wgpu::ShaderStages::FRAGMENT
+                                | wgpu::ShaderStages::COMPUTE,
                             ty: wgpu::BindingType::Buffer {
                                 ty: wgpu::BufferBindingType::Uniform,
                                 has_dynamic_offset: false,
@@ -124,7 +200,9 @@ impl FatCamera {
                         },
                         wgpu::BindGroupLayoutEntry {
                             binding: 1,
-                            visibility: wgpu::ShaderStages::VERTEX,
+                            visibility: wgpu::ShaderStages::VERTEX
+                                | wgpu::ShaderStages::FRAGMENT
+                                | wgpu::ShaderStages::COMPUTE,
                             ty: wgpu::BindingType::Buffer {
                                 ty: wgpu::BufferBindingType::Uniform,
                                 has_dynamic_offset: false,
@@ -134,7 +212,21 @@ impl FatCamera {
                         },
                         wgpu::BindGroupLayoutEntry {
                             binding: 2,
-                            visibility: wgpu::ShaderStages::VERTEX,
+                            visibility: wgpu::ShaderStages::VERTEX
+                                | wgpu::ShaderStages::FRAGMENT
+                                | wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 3,
+                            visibility: wgpu::ShaderStages::VERTEX
+                                | wgpu::ShaderStages::FRAGMENT
+                                | wgpu::ShaderStages::COMPUTE,
                             ty: wgpu::BindingType::Buffer {
                                 ty: wgpu::BufferBindingType::Uniform,
                                 has_dynamic_offset: false,
@@ -150,6 +242,7 @@ impl FatCamera {
             view: CameraMatrix::from_camera(&camera),
             projection: CameraMatrix::from_projection(&projection),
             view_inverse: CameraMatrix::from_camera_inverse(&camera),
+            projection_inverse: CameraMatrix::from_projection_inverse(&projection),
         };
         let matrix_buffers = FatCamera::create_matrix_buffers(&gpu, &matrices);
         let camera_bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -167,6 +260,10 @@ impl FatCamera {
                     binding: 2,
                     resource: matrix_buffers.view_inverse.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: matrix_buffers.projection_inverse.as_entire_binding(),
+                },
             ],
             label: Some("camera_bind_group"),
         });
@@ -184,7 +281,7 @@ impl FatCamera {
 
 #[repr(C)]
 // This is so we can store this in a buffer
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraMatrix {
     // We can't use cgmath with bytemuck directly so we'll have
     // to convert the Matrix4 into a 4x4 f32 array
@@ -198,6 +295,10 @@ impl CameraMatrix {
             mat: cgmath::Matrix4::identity().into(),
         }
     }
+    pub fn from_matrix(mat: Matrix4<f32>) -> CameraMatrix {
+        CameraMatrix { mat: mat.into() }
+    }
+
     pub fn from_camera(camera: &FPSCamera) -> CameraMatrix {
         CameraMatrix {
             mat: (camera.calc_matrix()).into(),
@@ -210,6 +311,11 @@ impl CameraMatrix {
         }
     }
 
+    pub fn from_projection_inverse(projection: &Projection) -> CameraMatrix {
+        let inv = projection.calc_matrix().invert().unwrap();
+        CameraMatrix { mat: inv.into() }
+    }
+
     pub fn from_camera_inverse(camera: &FPSCamera) -> CameraMatrix {
         let view_matrix = camera.calc_matrix();
         let inv = view_matrix.invert().unwrap();
@@ -229,12 +335,40 @@ impl FPSCamera {
             pitch: pitch.into(),
         }
     }
-    pub fn calc_matrix(&self) -> Matrix4<f32> {
+    pub fn direction(&self) -> Vector3<f32> {
         let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
         let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
+        Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize()
+    }
+
+    //Turns camera space, x right, y up and looking down -z, into world space
+    pub fn rotation(&self) -> Quaternion<f32> {
+        let direction = self.direction();
+        let right = direction.cross(Vector3::unit_y()).normalize();
+        Quaternion::from(Matrix3::from_cols(
+            right,
+            right.cross(direction),
+            -direction,
+        ))
+    }
+
+    //Moves the camera distance away from the origin, looking at it along the preset axis
+    pub fn set_view_preset(&mut self, preset: ViewPreset, distance: f32) {
+        let (yaw, pitch) = preset.angles();
+        self.yaw = yaw.into();
+        self.pitch = Rad(Rad::from(pitch).0.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2));
+        self.position = Point3::origin() - self.direction() * distance;
+    }
 
-        let direction =
-            Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize();
+    //Mirrors the camera through the origin, so a preset view shows the other side
+    pub fn flip_view(&mut self) {
+        self.position = Point3::from_vec(-self.position.to_vec());
+        self.yaw += Rad(std::f32::consts::PI);
+        self.pitch = -self.pitch;
+    }
+
+    pub fn calc_matrix(&self) -> Matrix4<f32> {
+        let direction = self.direction();
         let up: Vector3<f32> = Vector3::unit_y();
 
         let mat = Matrix4::look_to_rh(self.position, direction, up);
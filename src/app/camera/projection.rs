@@ -1,4 +1,4 @@
-use cgmath::{perspective, Matrix4, Rad};
+use cgmath::{ortho, perspective, Matrix4, Rad};
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
@@ -6,28 +6,90 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     0.0, 0.0, 0.5, 0.0,
     0.0, 0.0, 0.5, 1.0,
 );
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ProjectionMode {
+    Perspective,
+    Orthographic,
+}
+
+impl ProjectionMode {
+    pub fn next(self) -> ProjectionMode {
+        match self {
+            ProjectionMode::Perspective => ProjectionMode::Orthographic,
+            ProjectionMode::Orthographic => ProjectionMode::Perspective,
+        }
+    }
+}
+
 pub struct Projection {
+    pub mode: ProjectionMode,
     aspect: f32,
     fovy: Rad<f32>,
-    znear: f32,
-    zfar: f32,
+    //Half the height of the orthographic view in world units
+    pub ortho_height: f32,
+    pub znear: f32,
+    pub zfar: f32,
 }
 
 impl Projection {
     pub fn new<F: Into<Rad<f32>>>(width: u32, height: u32, fovy: F, znear: f32, zfar: f32) -> Self {
         Self {
+            mode: ProjectionMode::Perspective,
             aspect: width as f32 / height as f32,
             fovy: fovy.into(),
+            ortho_height: 100.0,
             znear,
             zfar,
         }
     }
 
+    //Clip planes that keep a scene of the given half size in view from a few extents away,
+    //without wasting depth precision on a tiny near plane
+    pub fn clip_planes_for_extent(extent: f32) -> (f32, f32) {
+        (extent * 0.001, extent * 10.0)
+    }
+
     pub fn resize(&mut self, width: u32, height: u32) {
         self.aspect = width as f32 / height as f32;
     }
 
+    //Same clip planes with another field of view and aspect, for offline views
+    pub fn with_view<F: Into<Rad<f32>>>(&self, width: u32, height: u32, fovy: F) -> Projection {
+        Projection::new(width, height, fovy, self.znear, self.zfar)
+    }
+
+    //Switching to orthographic keeps things at focus_distance the same size on screen
+    pub fn set_mode(&mut self, mode: ProjectionMode, focus_distance: f32) {
+        if mode == ProjectionMode::Orthographic && self.mode == ProjectionMode::Perspective {
+            self.ortho_height = focus_distance * (self.fovy.0 * 0.5).tan();
+        }
+        self.mode = mode;
+    }
+
+    //Scroll up zooms in
+    pub fn zoom(&mut self, scroll: f32) {
+        self.ortho_height = (self.ortho_height * 0.9f32.powf(scroll)).clamp(0.1, 100000.0);
+    }
+
     pub fn calc_matrix(&self) -> Matrix4<f32> {
-        OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar)
+        match self.mode {
+            ProjectionMode::Perspective => {
+                OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar)
+            }
+            ProjectionMode::Orthographic => {
+                let (half_width, half_height) =
+                    (self.ortho_height * self.aspect, self.ortho_height);
+                OPENGL_TO_WGPU_MATRIX
+                    * ortho(
+                        -half_width,
+                        half_width,
+                        -half_height,
+                        half_height,
+                        self.znear,
+                        self.zfar,
+                    )
+            }
+        }
     }
 }
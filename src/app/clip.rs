@@ -0,0 +1,200 @@
+//Regions that hide or dim particles, to look inside the cloud. The renderer, the trails and
+//the volume splat test every particle against the region, the helpers draw it as a wireframe.
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::{InnerSpace, Vector3};
+
+const CIRCLE_SEGMENTS: usize = 48;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ClipShape {
+    Off,
+    //Hides the side the normal points to
+    Plane,
+    //Shows only a layer thickness thick around the plane
+    Slab,
+    //Axis aligned box, shows the inside
+    Box,
+    Sphere,
+}
+
+impl ClipShape {
+    pub fn next(self) -> ClipShape {
+        match self {
+            ClipShape::Off => ClipShape::Plane,
+            ClipShape::Plane => ClipShape::Slab,
+            ClipShape::Slab => ClipShape::Box,
+            ClipShape::Box => ClipShape::Sphere,
+            ClipShape::Sphere => ClipShape::Off,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ClipSettings {
+    pub shape: ClipShape,
+    pub center: Vector3<f32>,
+    //Plane and slab orientation, also the direction nudge moves the region in
+    pub normal: Vector3<f32>,
+    pub half_size: Vector3<f32>,
+    pub radius: f32,
+    pub thickness: f32,
+    //Show what's outside the region instead
+    pub invert: bool,
+    //Alpha kept by clipped particles, 0 hides them
+    pub dim: f32,
+    pub gizmo: bool,
+}
+
+impl Default for ClipSettings {
+    fn default() -> Self {
+        ClipSettings {
+            shape: ClipShape::Off,
+            center: Vector3::new(0.0, 0.0, 0.0),
+            normal: Vector3::unit_z(),
+            half_size: Vector3::new(50.0, 50.0, 50.0),
+            radius: 50.0,
+            thickness: 4.0,
+            invert: false,
+            dim: 0.0,
+            gizmo: true,
+        }
+    }
+}
+
+impl ClipSettings {
+    //Most vertices gizmo_lines returns, for the sphere
+    pub const MAX_GIZMO_VERTICES: usize = CIRCLE_SEGMENTS * 2 * 3;
+
+    //Moves the region along its normal
+    pub fn nudge(&mut self, distance: f32) {
+        self.center += self.normal * distance;
+    }
+
+    //Grows the size of the current shape
+    pub fn scale(&mut self, factor: f32) {
+        match self.shape {
+            ClipShape::Slab => self.thickness *= factor,
+            ClipShape::Box => self.half_size *= factor,
+            ClipShape::Sphere => self.radius *= factor,
+            ClipShape::Off | ClipShape::Plane => {}
+        }
+    }
+
+    //The size scale changes, for printing
+    pub fn size_description(&self) -> String {
+        match self.shape {
+            ClipShape::Slab => format!("Clip thickness: {}", self.thickness),
+            ClipShape::Box => format!("Clip half size: {:?}", self.half_size),
+            ClipShape::Sphere => format!("Clip radius: {}", self.radius),
+            ClipShape::Off | ClipShape::Plane => format!("{:?} clip has no size", self.shape),
+        }
+    }
+
+    //Cycles the normal through the x, y and z axes
+    pub fn next_axis(&mut self) {
+        self.normal = if self.normal == Vector3::unit_x() {
+            Vector3::unit_y()
+        } else if self.normal == Vector3::unit_y() {
+            Vector3::unit_z()
+        } else {
+            Vector3::unit_x()
+        };
+    }
+
+    //Two directions spanning the plane
+    fn plane_axes(&self) -> (Vector3<f32>, Vector3<f32>) {
+        let pick = if self.normal.y.abs() < 0.9 {
+            Vector3::unit_y()
+        } else {
+            Vector3::unit_x()
+        };
+        let u = self.normal.cross(pick).normalize();
+        (u, self.normal.cross(u))
+    }
+
+    //Outline of the region as line list vertex pairs. Planes are drawn half_extent across.
+    pub fn gizmo_lines(&self, half_extent: f32) -> Vec<[f32; 3]> {
+        let mut lines = Vec::new();
+        let mut square = |center: Vector3<f32>, u: Vector3<f32>, v: Vector3<f32>| {
+            let corners = [u + v, u - v, -u - v, -u + v];
+            for i in 0..4 {
+                lines.push((center + corners[i]).into());
+                lines.push((center + corners[(i + 1) % 4]).into());
+            }
+        };
+        let (u, v) = self.plane_axes();
+        let (u, v) = (u * half_extent, v * half_extent);
+        match self.shape {
+            ClipShape::Off => {}
+            ClipShape::Plane => {
+                square(self.center, u, v);
+                lines.push(self.center.into());
+                lines.push((self.center + self.normal * half_extent * 0.2).into());
+            }
+            ClipShape::Slab => {
+                let offset = self.normal * self.thickness * 0.5;
+                square(self.center + offset, u, v);
+                square(self.center - offset, u, v);
+            }
+            ClipShape::Box => {
+                let h = self.half_size;
+                square(
+                    self.center + Vector3::unit_z() * h.z,
+                    Vector3::unit_x() * h.x,
+                    Vector3::unit_y() * h.y,
+                );
+                square(
+                    self.center - Vector3::unit_z() * h.z,
+                    Vector3::unit_x() * h.x,
+                    Vector3::unit_y() * h.y,
+                );
+                for (sx, sy) in [(1.0, 1.0), (1.0, -1.0), (-1.0, -1.0), (-1.0, 1.0)] {
+                    let corner = self.center + Vector3::new(h.x * sx, h.y * sy, 0.0);
+                    lines.push((corner + Vector3::unit_z() * h.z).into());
+                    lines.push((corner - Vector3::unit_z() * h.z).into());
+                }
+            }
+            ClipShape::Sphere => {
+                let axes = [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()];
+                for i in 0..3 {
+                    let (a, b) = (axes[i] * self.radius, axes[(i + 1) % 3] * self.radius);
+                    for s in 0..CIRCLE_SEGMENTS {
+                        for step in [s, s + 1] {
+                            let angle =
+                                step as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+                            lines.push((self.center + a * angle.cos() + b * angle.sin()).into());
+                        }
+                    }
+                }
+            }
+        }
+        lines
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct ClipParameters {
+    center: [f32; 4],
+    normal: [f32; 4],
+    half_size: [f32; 4],
+    shape: u32,
+    invert: u32,
+    dim: f32,
+    thickness: f32,
+}
+
+impl From<&ClipSettings> for ClipParameters {
+    fn from(settings: &ClipSettings) -> Self {
+        ClipParameters {
+            center: settings.center.extend(settings.radius).into(),
+            normal: settings.normal.normalize().extend(0.0).into(),
+            half_size: settings.half_size.extend(0.0).into(),
+            shape: settings.shape as u32,
+            invert: settings.invert as u32,
+            dim: settings.dim,
+            thickness: settings.thickness,
+        }
+    }
+}
@@ -0,0 +1,439 @@
+//Solid obstacles the particles flow around, as signed distance functions combined in list
+//order. Particles that end a step inside or touching the combined shape are pushed back out
+//along its gradient and lose or reflect the velocity going into it.
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::{ElementWise, InnerSpace, Vector3};
+use wgpu::util::DeviceExt;
+
+use super::{gpu::Gpu, mesh_sdf::MeshSdf};
+
+//Storage is allocated for this many, the rest of the list is ignored
+pub const MAX_COLLIDERS: usize = 32;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColliderShape {
+    //size.x is the radius
+    Sphere,
+    //Axis aligned, size is the half size
+    Box,
+    //Along axis, size.x is the radius and size.y the half length of the core segment
+    Capsule,
+    //Around axis, size.x is the ring radius and size.y the tube radius
+    Torus,
+    //Through position facing axis, everything behind it is solid
+    Plane,
+    //The baked mesh sdf, its -1 to 1 grid scaled to size.x
+    Mesh,
+}
+
+impl ColliderShape {
+    pub fn next(self) -> ColliderShape {
+        match self {
+            ColliderShape::Sphere => ColliderShape::Box,
+            ColliderShape::Box => ColliderShape::Capsule,
+            ColliderShape::Capsule => ColliderShape::Torus,
+            ColliderShape::Torus => ColliderShape::Plane,
+            ColliderShape::Plane => ColliderShape::Mesh,
+            ColliderShape::Mesh => ColliderShape::Sphere,
+        }
+    }
+}
+
+//How a collider combines with the ones before it
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColliderOperation {
+    Union,
+    //Carves the collider out of what's before it
+    Subtract,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Collider {
+    pub shape: ColliderShape,
+    pub operation: ColliderOperation,
+    pub position: Vector3<f32>,
+    pub axis: Vector3<f32>,
+    pub size: Vector3<f32>,
+}
+
+impl Collider {
+    //A collider of the given shape around scale across, along +y
+    pub fn new(shape: ColliderShape, position: Vector3<f32>, scale: f32) -> Collider {
+        let size = match shape {
+            ColliderShape::Sphere | ColliderShape::Mesh | ColliderShape::Plane => {
+                Vector3::new(scale, 0.0, 0.0)
+            }
+            ColliderShape::Box => Vector3::new(scale, scale, scale),
+            ColliderShape::Capsule => Vector3::new(scale * 0.4, scale * 0.6, 0.0),
+            ColliderShape::Torus => Vector3::new(scale * 0.7, scale * 0.25, 0.0),
+        };
+        Collider {
+            shape,
+            operation: ColliderOperation::Union,
+            position,
+            axis: Vector3::unit_y(),
+            size,
+        }
+    }
+
+    //Signed distance from p, like collider_distance in sim.wgsl. Only the tests check
+    //distance_gradient and baked meshes against it.
+    #[cfg(test)]
+    pub fn distance(&self, p: Vector3<f32>, mesh: Option<&MeshSdf>) -> f32 {
+        let d = p - self.position;
+        let axis = self.axis.normalize();
+        match self.shape {
+            ColliderShape::Sphere => d.magnitude() - self.size.x,
+            ColliderShape::Box => {
+                let q = Vector3::new(d.x.abs(), d.y.abs(), d.z.abs()) - self.size;
+                let outside = Vector3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0));
+                outside.magnitude() + q.x.max(q.y).max(q.z).min(0.0)
+            }
+            ColliderShape::Capsule => {
+                let t = d.dot(axis).clamp(-self.size.y, self.size.y);
+                (d - axis * t).magnitude() - self.size.x
+            }
+            ColliderShape::Torus => {
+                let height = d.dot(axis);
+                let radial = (d - axis * height).magnitude();
+                cgmath::Vector2::new(radial - self.size.x, height).magnitude() - self.size.y
+            }
+            ColliderShape::Plane => d.dot(axis),
+            ColliderShape::Mesh => match mesh {
+                Some(mesh) => mesh.distance(d / self.size.x) * self.size.x,
+                None => f32::MAX,
+            },
+        }
+    }
+
+    //Signed distance and its gradient worked out from the shape, like obstacle_distance in
+    //sim.wgsl. Baked meshes have no analytic form, so they give None.
+    pub fn distance_gradient(&self, p: Vector3<f32>) -> Option<(f32, Vector3<f32>)> {
+        let d = p - self.position;
+        let axis = self.axis.normalize();
+        let unit = |v: Vector3<f32>| {
+            if v.magnitude2() > 1e-12 {
+                v.normalize()
+            } else {
+                axis
+            }
+        };
+        let result = match self.shape {
+            ColliderShape::Sphere => (d.magnitude() - self.size.x, unit(d)),
+            ColliderShape::Box => {
+                let q = Vector3::new(d.x.abs(), d.y.abs(), d.z.abs()) - self.size;
+                let sign = Vector3::new(d.x.signum(), d.y.signum(), d.z.signum());
+                let inside = q.x.max(q.y).max(q.z);
+                if inside > 0.0 {
+                    let outside = Vector3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0));
+                    let length = outside.magnitude();
+                    (length, outside.mul_element_wise(sign) / length)
+                } else if q.x >= q.y && q.x >= q.z {
+                    (inside, Vector3::new(sign.x, 0.0, 0.0))
+                } else if q.y >= q.z {
+                    (inside, Vector3::new(0.0, sign.y, 0.0))
+                } else {
+                    (inside, Vector3::new(0.0, 0.0, sign.z))
+                }
+            }
+            ColliderShape::Capsule => {
+                let t = d.dot(axis).clamp(-self.size.y, self.size.y);
+                let from_core = d - axis * t;
+                (from_core.magnitude() - self.size.x, unit(from_core))
+            }
+            ColliderShape::Torus => {
+                let ring = unit(d - axis * d.dot(axis)) * self.size.x;
+                let from_core = d - ring;
+                (from_core.magnitude() - self.size.y, unit(from_core))
+            }
+            ColliderShape::Plane => (d.dot(axis), axis),
+            ColliderShape::Mesh => return None,
+        };
+        Some(result)
+    }
+}
+
+//Distance and gradient of the analytic colliders combined in order, None if there are
+//none. What the field's potential is ramped down with, see FieldParameters::bounded_sample.
+pub fn obstacle_distance(colliders: &[Collider], p: Vector3<f32>) -> Option<(f32, Vector3<f32>)> {
+    let mut nearest: Option<(f32, Vector3<f32>)> = None;
+    for collider in colliders.iter().take(MAX_COLLIDERS) {
+        let (d, g) = match collider.distance_gradient(p) {
+            Some(sample) => sample,
+            None => continue,
+        };
+        let (d, g) = match collider.operation {
+            ColliderOperation::Union => (d, g),
+            ColliderOperation::Subtract => (-d, -g),
+        };
+        nearest = match (nearest, collider.operation) {
+            (None, ColliderOperation::Union) => Some((d, g)),
+            //Nothing to carve out of yet
+            (None, ColliderOperation::Subtract) => None,
+            (Some(n), ColliderOperation::Union) if n.0 <= d => Some(n),
+            (Some(n), ColliderOperation::Subtract) if n.0 >= d => Some(n),
+            _ => Some((d, g)),
+        };
+    }
+    nearest
+}
+
+//Response to particles hitting a collider
+#[derive(Debug, Copy, Clone)]
+pub struct CollisionSettings {
+    //Particles closer to a surface than this count as touching it
+    pub margin: f32,
+    //0 removes the velocity into the surface so particles slide along it, 1 reflects it
+    pub restitution: f32,
+}
+
+impl Default for CollisionSettings {
+    fn default() -> Self {
+        CollisionSettings {
+            margin: 1.0,
+            restitution: 0.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct ColliderParameters {
+    position: [f32; 4],
+    axis: [f32; 4],
+    size: [f32; 4],
+    shape: u32,
+    operation: u32,
+    _padding: [u32; 2],
+}
+
+impl From<&Collider> for ColliderParameters {
+    fn from(collider: &Collider) -> Self {
+        ColliderParameters {
+            position: collider.position.extend(0.0).into(),
+            axis: collider.axis.normalize().extend(0.0).into(),
+            size: collider.size.extend(0.0).into(),
+            shape: collider.shape as u32,
+            operation: collider.operation as u32,
+            _padding: [0; 2],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct CollidersHeader {
+    count: u32,
+    margin: f32,
+    restitution: f32,
+    mesh_resolution: u32,
+}
+
+pub struct Colliders {
+    pub colliders: Vec<Collider>,
+    pub settings: CollisionSettings,
+    //The cpu copy of the baked mesh, if there is one
+    pub mesh: Option<MeshSdf>,
+    pub buffer: wgpu::Buffer,
+    pub mesh_view: wgpu::TextureView,
+}
+
+impl Colliders {
+    pub fn new(gpu: &Gpu) -> Colliders {
+        let buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Collider Buffer"),
+            size: (std::mem::size_of::<CollidersHeader>()
+                + MAX_COLLIDERS * std::mem::size_of::<ColliderParameters>())
+                as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        //Stand in until a mesh is baked
+        let empty = MeshSdf {
+            resolution: 1,
+            distances: vec![f32::MAX],
+        };
+        Colliders {
+            colliders: Vec::new(),
+            settings: CollisionSettings::default(),
+            mesh: None,
+            buffer,
+            mesh_view: Self::create_mesh_texture(gpu, &empty),
+        }
+    }
+
+    fn create_mesh_texture(gpu: &Gpu, mesh: &MeshSdf) -> wgpu::TextureView {
+        let texture = gpu.device.create_texture_with_data(
+            &gpu.queue,
+            &wgpu::TextureDescriptor {
+                label: Some("Mesh SDF Texture"),
+                size: wgpu::Extent3d {
+                    width: mesh.resolution,
+                    height: mesh.resolution,
+                    depth_or_array_layers: mesh.resolution,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D3,
+                format: wgpu::TextureFormat::R32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            },
+            bytemuck::cast_slice(&mesh.distances),
+        );
+        texture.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    //Swaps in a baked mesh. The texture view changes, so bind groups using it need rebuilding.
+    pub fn set_mesh(&mut self, gpu: &Gpu, mesh: MeshSdf) {
+        self.mesh_view = Self::create_mesh_texture(gpu, &mesh);
+        self.mesh = Some(mesh);
+    }
+
+    pub fn write(&self, gpu: &Gpu) {
+        let count = self.colliders.len().min(MAX_COLLIDERS);
+        let header = CollidersHeader {
+            count: count as u32,
+            margin: self.settings.margin,
+            restitution: self.settings.restitution,
+            mesh_resolution: self.mesh.as_ref().map_or(0, |mesh| mesh.resolution),
+        };
+        let mut contents = bytemuck::bytes_of(&header).to_vec();
+        for collider in &self.colliders[..count] {
+            contents.extend_from_slice(bytemuck::bytes_of(&ColliderParameters::from(collider)));
+        }
+        gpu.queue.write_buffer(&self.buffer, 0, &contents);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::app::mesh_sdf::TriangleMesh;
+
+    fn random_point(rng: &mut impl Rng, extent: f32) -> Vector3<f32> {
+        Vector3::new(
+            rng.gen_range(-extent..extent),
+            rng.gen_range(-extent..extent),
+            rng.gen_range(-extent..extent),
+        )
+    }
+
+    #[test]
+    fn distance_gradient_matches_distance() {
+        let mut rng = StdRng::seed_from_u64(46);
+        let h = 1e-2;
+        let shapes = [
+            ColliderShape::Sphere,
+            ColliderShape::Box,
+            ColliderShape::Capsule,
+            ColliderShape::Torus,
+            ColliderShape::Plane,
+        ];
+        for shape in shapes {
+            let mut collider = Collider::new(shape, Vector3::new(3.0, -2.0, 1.0), 10.0);
+            collider.axis = Vector3::new(1.0, 2.0, -0.5).normalize();
+            if shape == ColliderShape::Box {
+                collider.size = Vector3::new(10.0, 6.0, 4.0);
+            }
+            let mut mismatched = 0;
+            for _ in 0..1000 {
+                let p = collider.position + random_point(&mut rng, 12.0);
+                let distance = collider.distance(p, None);
+                let (analytic, gradient) = collider.distance_gradient(p).unwrap();
+                assert!(
+                    (distance - analytic).abs() < 1e-3,
+                    "{:?} at {:?}: {} against {}",
+                    shape,
+                    p,
+                    distance,
+                    analytic
+                );
+                let difference = |axis: Vector3<f32>| {
+                    (collider.distance(p + axis * h, None) - collider.distance(p - axis * h, None))
+                        / (2.0 * h)
+                };
+                let reference = Vector3::new(
+                    difference(Vector3::unit_x()),
+                    difference(Vector3::unit_y()),
+                    difference(Vector3::unit_z()),
+                );
+                //Differences straddling a crease, like the inside of a box, don't count
+                if (gradient - reference).magnitude() > 1e-2 {
+                    mismatched += 1;
+                }
+            }
+            assert!(mismatched < 20, "{:?}: {} gradients off", shape, mismatched);
+        }
+    }
+
+    //Cube from -half_size to half_size, two triangles a face
+    fn cube(half_size: f32) -> TriangleMesh {
+        let vertices = (0..8)
+            .map(|i| {
+                let corner = |bit| if i & bit == 0 { -half_size } else { half_size };
+                Vector3::new(corner(1), corner(2), corner(4))
+            })
+            .collect();
+        let faces = [
+            [0, 2, 6, 4],
+            [1, 5, 7, 3],
+            [0, 4, 5, 1],
+            [2, 3, 7, 6],
+            [0, 1, 3, 2],
+            [4, 6, 7, 5],
+        ];
+        let triangles = faces
+            .iter()
+            .flat_map(|&[a, b, c, d]| [[a, b, c], [a, c, d]])
+            .collect();
+        TriangleMesh {
+            vertices,
+            triangles,
+        }
+    }
+
+    #[test]
+    fn baked_cube_matches_box() {
+        let mesh = MeshSdf::bake(&cube(0.5), 32);
+        let scale = 20.0;
+        let position = Vector3::new(5.0, 0.0, -5.0);
+        let baked = Collider::new(ColliderShape::Mesh, position, scale);
+        let analytic = Collider::new(ColliderShape::Box, position, scale * 0.5);
+
+        assert!(mesh.distance(Vector3::new(0.0, 0.0, 0.0)) < -0.45);
+        assert!(mesh.distance(Vector3::new(0.9, 0.1, -0.2)) > 0.35);
+
+        let mut rng = StdRng::seed_from_u64(45);
+        //Trilinear interpolation rounds off the edges and corners by up to about a cell
+        let tolerance = mesh.cell_size() * scale;
+        for _ in 0..1000 {
+            let p = position + random_point(&mut rng, scale);
+            let exact = analytic.distance(p, None);
+            let distance = baked.distance(p, Some(&mesh));
+            assert!(
+                (distance - exact).abs() < tolerance,
+                "{:?}: baked {} against {}",
+                p,
+                distance,
+                exact
+            );
+            //Further than that from the surface the sign has to be right
+            if exact.abs() > tolerance {
+                assert_eq!(distance < 0.0, exact < 0.0, "{:?}", p);
+            }
+        }
+        //Near the middle of a face the distance is exact up to rounding
+        for offset in [-0.05, -0.02, 0.02, 0.05] {
+            let p = Vector3::new(0.5 + offset, 0.1, -0.05);
+            assert!(
+                (mesh.distance(p) - offset).abs() < 0.01,
+                "{} at {}",
+                mesh.distance(p),
+                offset
+            );
+        }
+    }
+}
@@ -0,0 +1,663 @@
+//CPU copy of the vector field in sim.wgsl. Slow, but easy to inspect: the field visualiser
+//traces streamlines through it and it's the reference to check the shader against, like
+//the divergence check in the field benchmark.
+
+use cgmath::{ElementWise, InnerSpace, Matrix, Matrix3, SquareMatrix, Vector3};
+use rand::Rng;
+
+use super::colliders::{obstacle_distance, Collider};
+
+const F3: f32 = 0.3333333;
+const G3: f32 = 0.1666667;
+//Corner spin speeds come in steps of 1 / SPIN_STEPS, see corner_gradient in sim.wgsl
+const SPIN_STEPS: f32 = 64.0;
+//Field time after which every corner has done a whole number of turns
+pub const FIELD_PERIOD: f32 = SPIN_STEPS * std::f32::consts::TAU;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FractalMode {
+    Standard,
+    //Absolute value of every octave, puffy rounded shapes
+    Billow,
+    //Squared inverted absolute value, sharp creases
+    Ridged,
+}
+
+impl FractalMode {
+    pub fn next(self) -> FractalMode {
+        match self {
+            FractalMode::Standard => FractalMode::Billow,
+            FractalMode::Billow => FractalMode::Ridged,
+            FractalMode::Ridged => FractalMode::Standard,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FractalParameters {
+    //1 is plain simplex noise
+    pub octaves: u32,
+    //Frequency factor from one octave to the next
+    pub lacunarity: f32,
+    //Amplitude factor from one octave to the next
+    pub gain: f32,
+    pub mode: FractalMode,
+    //Turns every octave after the first to hide grid aligned artifacts
+    pub rotate_octaves: bool,
+    //How far another noise displaces the sample positions, in noise space. 0 is off.
+    pub warp_strength: f32,
+    //Frequency of the displacing noise relative to the field's
+    pub warp_scale: f32,
+}
+
+impl Default for FractalParameters {
+    fn default() -> Self {
+        FractalParameters {
+            octaves: 1,
+            lacunarity: 2.0,
+            gain: 0.5,
+            mode: FractalMode::Standard,
+            rotate_octaves: true,
+            warp_strength: 0.0,
+            warp_scale: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FieldParameters {
+    pub noise_scale: f32,
+    pub speed_multiplier: f32,
+    pub curl_multiplier: f32,
+    pub constant_force: Vector3<f32>,
+    //0 moves with the potential itself, 1 with its curl
+    pub curl_mix: f32,
+    //How fast the field evolves, in radians the noise gradients turn per second on average.
+    //0 keeps the field still.
+    pub time_multiplier: f32,
+    //Accumulated by advance, so changing the speed doesn't jump the field. Wraps at
+    //FIELD_PERIOD to keep its precision.
+    pub time: f32,
+    pub fractal: FractalParameters,
+    //How quickly particle velocities follow the flow, per second. Lower values give the
+    //particles momentum, so the force stack can push them off the flow. At EXACT_RESPONSE the
+    //velocity is replaced every step and the forces never build up.
+    pub field_response: f32,
+    //Distance from the analytic colliders over which the potential ramps down to nothing, so
+    //the flow slides along them instead of into them. 0 ignores the colliders.
+    pub boundary_width: f32,
+}
+
+impl Default for FieldParameters {
+    fn default() -> Self {
+        FieldParameters {
+            noise_scale: 0.008,
+            speed_multiplier: 40.0,
+            curl_multiplier: 40.0,
+            constant_force: Vector3::new(0.0, 0.0, 0.0),
+            curl_mix: 1.0,
+            time_multiplier: 0.0,
+            time: 0.0,
+            fractal: FractalParameters::default(),
+            field_response: 10.0,
+            boundary_width: 20.0,
+        }
+    }
+}
+
+fn fract(x: f32) -> f32 {
+    x - x.floor()
+}
+
+fn floor3(v: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(v.x.floor(), v.y.floor(), v.z.floor())
+}
+
+fn step3(edge: f32, v: Vector3<f32>) -> Vector3<f32> {
+    v.map(|x| if x >= edge { 1.0 } else { 0.0 })
+}
+
+fn random3(c: Vector3<f32>) -> Vector3<f32> {
+    let mut j = 4096.0 * c.dot(Vector3::new(17.0, 59.4, 15.0)).sin();
+    let z = fract(512.0 * j);
+    j *= 0.125;
+    let x = fract(512.0 * j);
+    j *= 0.125;
+    let y = fract(512.0 * j);
+    Vector3::new(x, y, z) - Vector3::new(0.5, 0.5, 0.5)
+}
+
+//Corner gradient spun around a random axis at its own speed, like corner_gradient in
+//sim.wgsl
+fn corner_gradient(c: Vector3<f32>, time: f32) -> Vector3<f32> {
+    let g = random3(c);
+    let turns = (SPIN_STEPS * (1.0 + random3(c + Vector3::new(71.0, 71.0, 71.0)).x)).floor();
+    let angle = time * turns / SPIN_STEPS;
+    let axis = (random3(c + Vector3::new(113.0, 113.0, 113.0))
+        + Vector3::new(0.0001, 0.0001, 0.0001))
+    .normalize();
+    g * angle.cos() + axis.cross(g) * angle.sin() + axis * axis.dot(g) * (1.0 - angle.cos())
+}
+
+//Value and analytic gradient, same as simplex3d_grad in sim.wgsl
+pub fn simplex3d_grad(p: Vector3<f32>, time: f32) -> (f32, Vector3<f32>) {
+    let s = floor3(p + Vector3::new(1.0, 1.0, 1.0) * p.dot(Vector3::new(F3, F3, F3)));
+    let x = p - s + Vector3::new(1.0, 1.0, 1.0) * s.dot(Vector3::new(G3, G3, G3));
+
+    let e = step3(0.0, x - Vector3::new(x.y, x.z, x.x));
+    let e_zxy = Vector3::new(e.z, e.x, e.y);
+    let one = Vector3::new(1.0, 1.0, 1.0);
+    let i1 = e.mul_element_wise(one - e_zxy);
+    let i2 = one - e_zxy.mul_element_wise(one - e);
+
+    let x1 = x - i1 + one * G3;
+    let x2 = x - i2 + one * 2.0 * G3;
+    let x3 = x - one + one * 3.0 * G3;
+
+    let corners = [(s, x), (s + i1, x1), (s + i2, x2), (s + one, x3)];
+    let mut value = 0.0;
+    let mut gradient = Vector3::new(0.0, 0.0, 0.0);
+    for (corner, offset) in corners {
+        let g = corner_gradient(corner, time);
+        let w = (0.6 - offset.dot(offset)).max(0.0);
+        let d = g.dot(offset);
+        value += w.powi(4) * d;
+        //The gradient of w^4 * dot(g, x) is w^4 * g - 8 * w^3 * dot(g, x) * x
+        gradient += g * w.powi(4) - offset * (8.0 * w.powi(3) * d);
+    }
+    (value * 52.0, gradient * 52.0)
+}
+
+//rot1, rot2 and rot3 in sim.wgsl, the shader's vectors are the columns
+fn octave_rotation(octave: u32, rotate: bool) -> Matrix3<f32> {
+    if octave == 0 || !rotate {
+        return Matrix3::identity();
+    }
+    match (octave - 1) % 3 {
+        0 => Matrix3::new(-0.37, 0.36, 0.85, -0.14, -0.93, 0.34, 0.92, 0.01, 0.4),
+        1 => Matrix3::new(-0.55, -0.39, 0.74, 0.33, -0.91, -0.24, 0.77, 0.12, 0.63),
+        _ => Matrix3::new(-0.71, 0.52, -0.47, -0.08, -0.72, -0.68, -0.7, -0.45, 0.56),
+    }
+}
+
+//Same octaves as fractal_grad in sim.wgsl
+pub fn fractal_grad(
+    m: Vector3<f32>,
+    time: f32,
+    fractal: &FractalParameters,
+) -> (f32, Vector3<f32>) {
+    let mut value = 0.0;
+    let mut gradient = Vector3::new(0.0, 0.0, 0.0);
+    let mut total = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    for octave in 0..fractal.octaves.max(1) {
+        let rotation = octave_rotation(octave, fractal.rotate_octaves);
+        //m * rotation in the shader is a row vector times the matrix
+        let (n, g) = simplex3d_grad(rotation.transpose() * m * frequency, time);
+        let (n, g) = match fractal.mode {
+            FractalMode::Standard => (n, g),
+            FractalMode::Billow => (n.abs(), g * n.signum()),
+            FractalMode::Ridged => {
+                let ridge = 1.0 - n.abs();
+                (ridge * ridge, g * (-2.0 * ridge * n.signum()))
+            }
+        };
+        value += n * amplitude;
+        gradient += rotation * g * frequency * amplitude;
+        total += amplitude;
+        amplitude *= fractal.gain;
+        frequency *= fractal.lacunarity;
+    }
+    (value / total, gradient / total)
+}
+
+//How far a sample of the field is from divergence free
+#[derive(Debug, Copy, Clone)]
+pub struct DivergenceReport {
+    //Divergence relative to the size of the derivatives that should cancel in it, 0 is
+    //perfectly divergence free and 1 means no cancellation at all. The noise kernels reach a
+    //little past their tetrahedron, so the field has faint seams where the differences blow
+    //up. The median ignores those, the max doesn't. With several octaves the differences get
+    //small against f32 rounding at the noise offsets, expect around 1e-2.
+    pub median_relative: f32,
+    pub max_relative: f32,
+    //Largest absolute divergence, in velocity per unit distance
+    pub max_absolute: f32,
+}
+
+//Bridson's smooth ramp from -1 to 1 over -1 < r < 1, and its derivative
+fn boundary_ramp(r: f32) -> (f32, f32) {
+    if r.abs() >= 1.0 {
+        return (r.signum(), 0.0);
+    }
+    let r2 = r * r;
+    (
+        r * (15.0 - 10.0 * r2 + 3.0 * r2 * r2) / 8.0,
+        15.0 / 8.0 * (1.0 - r2) * (1.0 - r2),
+    )
+}
+
+//How well the flow keeps out of the colliders
+#[derive(Debug, Copy, Clone)]
+pub struct BoundaryReport {
+    //Surface points sampled, points where the analytic distance isn't exact get skipped
+    pub samples: usize,
+    //Velocity through the surface relative to the speed along it, 0 is perfectly tangent
+    pub median_normal: f32,
+    pub max_normal: f32,
+    //Divergence of the ramped curl close to the surfaces, like DivergenceReport
+    pub median_divergence: f32,
+}
+
+impl FieldParameters {
+    //Response that replaces the velocity with the flow every step, 1 / DT in sim.wgsl
+    pub const EXACT_RESPONSE: f32 = 1.0 / 0.016;
+
+    //Evolution speed Numpad0 starts a still field at
+    pub const EVOLVING_TIME_MULTIPLIER: f32 = 0.25;
+
+    //Moves the field on by dt seconds at the current speed
+    pub fn advance(&mut self, dt: f32) {
+        self.time = (self.time + dt * self.time_multiplier).rem_euclid(FIELD_PERIOD);
+    }
+
+    //Displaced noise space position, and the matrix taking gradients there back to pos,
+    //like warp_domain in sim.wgsl
+    pub fn warp_domain(&self, pos: Vector3<f32>) -> (Vector3<f32>, Matrix3<f32>) {
+        let f = &self.fractal;
+        if f.warp_strength == 0.0 {
+            return (pos, Matrix3::identity());
+        }
+        let q = pos * f.warp_scale;
+        let (x, gx) = simplex3d_grad(q + Vector3::new(0.0, 1000.0, 0.0), self.time);
+        let (y, gy) = simplex3d_grad(q + Vector3::new(0.0, 2000.0, 0.0), self.time);
+        let (z, gz) = simplex3d_grad(q + Vector3::new(0.0, 0.0, 1000.0), self.time);
+        let k = f.warp_strength * f.warp_scale;
+        (
+            pos + Vector3::new(x, y, z) * f.warp_strength,
+            Matrix3::identity() + Matrix3::from_cols(gx, gy, gz) * k,
+        )
+    }
+
+    //Potential and its curl from analytic gradients, like sample_field in sim.wgsl. The curl
+    //is negated, the sign the forward differences it replaced always had.
+    pub fn sample(&self, p: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+        let (pos, gradient_transform) = self.warp_domain(p * self.noise_scale);
+        let noise = |offset| {
+            let (n, g) = fractal_grad(pos + offset, self.time, &self.fractal);
+            (n, gradient_transform * g)
+        };
+        let (x, gx) = noise(Vector3::new(0.0, 0.0, 0.0));
+        let (y, gy) = noise(Vector3::new(1000.0, 0.0, 0.0));
+        let (z, gz) = noise(Vector3::new(2000.0, 0.0, 0.0));
+        let potential = (Vector3::new(x, y, z) + self.constant_force) * self.speed_multiplier;
+        let derivative_scale = self.noise_scale * self.speed_multiplier * self.curl_multiplier;
+        let curl = Vector3::new(gy.z - gz.y, gz.x - gx.z, gx.y - gy.x) * derivative_scale;
+        (potential, curl)
+    }
+
+    pub fn curl(&self, p: Vector3<f32>) -> Vector3<f32> {
+        self.sample(p).1
+    }
+
+    //sample with the potential multiplied by a ramp of the distance to the obstacles,
+    //following Bridson et al. The curl of the ramped potential is still a curl, so still
+    //divergence free, and where the ramp is 0 on a surface only the ramp's gradient times the
+    //potential is left, which is perpendicular to the surface normal. Like sample_field in
+    //sim.wgsl.
+    pub fn bounded_sample(
+        &self,
+        p: Vector3<f32>,
+        obstacles: &[Collider],
+    ) -> (Vector3<f32>, Vector3<f32>) {
+        let (potential, curl) = self.sample(p);
+        if self.boundary_width <= 0.0 {
+            return (potential, curl);
+        }
+        let (distance, normal) = match obstacle_distance(obstacles, p) {
+            Some(nearest) => nearest,
+            None => return (potential, curl),
+        };
+        let (ramp, slope) = boundary_ramp(distance / self.boundary_width);
+        //The potential the curl is taken of, with the curl multiplier folded in
+        let curled = potential * self.curl_multiplier;
+        let ramp_gradient = normal * (slope / self.boundary_width);
+        (potential, curl * ramp - ramp_gradient.cross(curled))
+    }
+
+    //Velocity a particle at p moves with, flowing around the obstacles
+    pub fn bounded_velocity(&self, p: Vector3<f32>, obstacles: &[Collider]) -> Vector3<f32> {
+        let (potential, curl) = self.bounded_sample(p, obstacles);
+        potential + (curl - potential) * self.curl_mix
+    }
+
+    //Samples the flow on the surfaces of the obstacles. Random points are pulled onto the
+    //combined surface along its gradient, then the velocity through the surface is compared
+    //with the speed along it.
+    pub fn check_boundary(
+        &self,
+        rng: &mut impl Rng,
+        obstacles: &[Collider],
+        samples: usize,
+        extent: f32,
+    ) -> BoundaryReport {
+        let mut normal_ratios = Vec::with_capacity(samples);
+        let mut divergences = Vec::with_capacity(samples);
+        let h = 0.01 / self.noise_scale;
+        for _ in 0..samples * 4 {
+            if normal_ratios.len() >= samples {
+                break;
+            }
+            let mut p = Vector3::new(
+                rng.gen_range(-extent..extent),
+                rng.gen_range(-extent..extent),
+                rng.gen_range(-extent..extent),
+            );
+            for _ in 0..8 {
+                match obstacle_distance(obstacles, p) {
+                    Some((distance, normal)) => p -= normal * distance,
+                    None => break,
+                }
+            }
+            let (distance, normal) = match obstacle_distance(obstacles, p) {
+                Some(nearest) if nearest.0.abs() < 1e-3 => nearest,
+                _ => continue,
+            };
+            let curl = self.bounded_sample(p, obstacles).1;
+            let through = curl.dot(normal).abs();
+            let along = (curl - normal * curl.dot(normal)).magnitude();
+            if along > 0.0 {
+                normal_ratios.push(through / along);
+            }
+            //Just off the surface, where the ramp is changing fastest
+            let q = p + normal * (self.boundary_width * 0.5 - distance);
+            let axes = [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()];
+            let terms = [0, 1, 2].map(|i| {
+                let ahead = self.bounded_sample(q + axes[i] * h, obstacles).1[i];
+                let behind = self.bounded_sample(q - axes[i] * h, obstacles).1[i];
+                (ahead - behind) / (2.0 * h)
+            });
+            let scale: f32 = terms.iter().map(|term| term.abs()).sum();
+            if scale > 0.0 {
+                divergences.push(terms.iter().sum::<f32>().abs() / scale);
+            }
+        }
+        normal_ratios.sort_by(f32::total_cmp);
+        divergences.sort_by(f32::total_cmp);
+        let median = |values: &Vec<f32>| values.get(values.len() / 2).copied().unwrap_or(0.0);
+        BoundaryReport {
+            samples: normal_ratios.len(),
+            median_normal: median(&normal_ratios),
+            max_normal: normal_ratios.last().copied().unwrap_or(0.0),
+            median_divergence: median(&divergences),
+        }
+    }
+
+    //Central difference divergence of the curl at p, and the sum of the magnitudes of the
+    //three terms in it
+    pub fn divergence(&self, p: Vector3<f32>, h: f32) -> (f32, f32) {
+        let axes = [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()];
+        let terms = [0, 1, 2]
+            .map(|i| (self.curl(p + axes[i] * h)[i] - self.curl(p - axes[i] * h)[i]) / (2.0 * h));
+        (
+            terms.iter().sum(),
+            terms.iter().map(|term| term.abs()).sum(),
+        )
+    }
+
+    //Divergence of the curl at samples random points within extent of the origin
+    pub fn check_divergence(
+        &self,
+        rng: &mut impl Rng,
+        samples: usize,
+        extent: f32,
+    ) -> DivergenceReport {
+        //Small against the finest octave's features, which are about 1 / noise_scale across
+        //divided by its frequency
+        let finest = self
+            .fractal
+            .lacunarity
+            .powi(self.fractal.octaves.max(1) as i32 - 1)
+            .max(1.0);
+        let h = 0.01 / (self.noise_scale * finest);
+        let mut relative = Vec::with_capacity(samples);
+        let mut max_absolute: f32 = 0.0;
+        for _ in 0..samples {
+            let p = Vector3::new(
+                rng.gen_range(-extent..extent),
+                rng.gen_range(-extent..extent),
+                rng.gen_range(-extent..extent),
+            );
+            let (divergence, scale) = self.divergence(p, h);
+            relative.push(if scale > 0.0 {
+                divergence.abs() / scale
+            } else {
+                0.0
+            });
+            max_absolute = max_absolute.max(divergence.abs());
+        }
+        relative.sort_by(f32::total_cmp);
+        DivergenceReport {
+            median_relative: relative.get(samples / 2).copied().unwrap_or(0.0),
+            max_relative: relative.last().copied().unwrap_or(0.0),
+            max_absolute,
+        }
+    }
+
+    //Runge Kutta streamline through seed, steps points each way, ordered from the upstream
+    //end to the downstream one. Stops early where the field vanishes.
+    pub fn trace_streamline(
+        &self,
+        seed: Vector3<f32>,
+        step: f32,
+        steps: u32,
+        obstacles: &[Collider],
+    ) -> Vec<Vector3<f32>> {
+        let direction = |p: Vector3<f32>, sign: f32| {
+            let v = self.bounded_velocity(p, obstacles);
+            if v.magnitude2() < 1e-12 {
+                None
+            } else {
+                Some(v.normalize() * sign)
+            }
+        };
+        let mut halves = [Vec::new(), Vec::new()];
+        for (half, sign) in halves.iter_mut().zip([-1.0, 1.0]) {
+            let mut p = seed;
+            for _ in 0..steps {
+                let k1 = match direction(p, sign) {
+                    Some(k) => k,
+                    None => break,
+                };
+                let k2 = direction(p + k1 * step * 0.5, sign).unwrap_or(k1);
+                let k3 = direction(p + k2 * step * 0.5, sign).unwrap_or(k2);
+                let k4 = direction(p + k3 * step, sign).unwrap_or(k3);
+                p += (k1 + k2 * 2.0 + k3 * 2.0 + k4) * (step / 6.0);
+                half.push(p);
+            }
+        }
+        let [mut upstream, downstream] = halves;
+        upstream.reverse();
+        upstream.push(seed);
+        upstream.extend(downstream);
+        upstream
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::app::colliders::ColliderShape;
+
+    //Negated curl of the potential from central differences, the reference for the analytic one
+    fn curl_finite_difference(field: &FieldParameters, p: Vector3<f32>, h: f32) -> Vector3<f32> {
+        let potential = |q| field.sample(q).0 * field.curl_multiplier;
+        let derivative =
+            |axis: Vector3<f32>| (potential(p + axis * h) - potential(p - axis * h)) / (2.0 * h);
+        let (dx, dy, dz) = (
+            derivative(Vector3::unit_x()),
+            derivative(Vector3::unit_y()),
+            derivative(Vector3::unit_z()),
+        );
+        Vector3::new(dz.y - dy.z, dx.z - dz.x, dy.x - dx.y)
+    }
+
+    #[test]
+    fn curl_is_divergence_free() {
+        //Seams in the noise spoil the max, see DivergenceReport
+        for (octaves, tolerance) in [(1, 5e-3), (3, 3e-2)] {
+            let mut field = FieldParameters::default();
+            field.fractal.octaves = octaves;
+            let report = field.check_divergence(&mut StdRng::seed_from_u64(40), 2000, 500.0);
+            assert!(
+                report.median_relative < tolerance,
+                "{} octaves: {:?}",
+                octaves,
+                report
+            );
+        }
+    }
+
+    #[test]
+    fn curl_matches_finite_difference() {
+        let field = FieldParameters::default();
+        let mut rng = StdRng::seed_from_u64(40);
+        let h = 0.01 / field.noise_scale;
+        let mut errors: Vec<f32> = (0..1000)
+            .map(|_| {
+                let p = Vector3::new(
+                    rng.gen_range(-500.0..500.0),
+                    rng.gen_range(-500.0..500.0),
+                    rng.gen_range(-500.0..500.0),
+                );
+                let reference = curl_finite_difference(&field, p, h);
+                (field.curl(p) - reference).magnitude() / reference.magnitude().max(1e-6)
+            })
+            .collect();
+        errors.sort_by(f32::total_cmp);
+        assert!(errors[500] < 1e-2, "median error {}", errors[500]);
+        assert!(errors[950] < 5e-2, "95th percentile error {}", errors[950]);
+    }
+
+    //Fractal noise at the warped p and its gradient with respect to p, as sample_field
+    //combines them
+    fn warped_noise(field: &FieldParameters, p: Vector3<f32>) -> (f32, Vector3<f32>) {
+        let (pos, gradient_transform) = field.warp_domain(p);
+        let (n, g) = fractal_grad(pos, field.time, &field.fractal);
+        (n, gradient_transform * g)
+    }
+
+    #[test]
+    fn fractal_gradient_matches_finite_difference() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let h = 1e-3;
+        for mode in [
+            FractalMode::Standard,
+            FractalMode::Billow,
+            FractalMode::Ridged,
+        ] {
+            for warp_strength in [0.0, 0.5] {
+                let mut field = FieldParameters::default();
+                field.time = 0.7;
+                field.fractal = FractalParameters {
+                    octaves: 3,
+                    mode,
+                    warp_strength,
+                    ..FractalParameters::default()
+                };
+                let mut errors: Vec<f32> = (0..1000)
+                    .map(|_| {
+                        let p = Vector3::new(
+                            rng.gen_range(-4.0..4.0),
+                            rng.gen_range(-4.0..4.0),
+                            rng.gen_range(-4.0..4.0),
+                        );
+                        let difference = |axis: Vector3<f32>| {
+                            (warped_noise(&field, p + axis * h).0
+                                - warped_noise(&field, p - axis * h).0)
+                                / (2.0 * h)
+                        };
+                        let reference = Vector3::new(
+                            difference(Vector3::unit_x()),
+                            difference(Vector3::unit_y()),
+                            difference(Vector3::unit_z()),
+                        );
+                        let gradient = warped_noise(&field, p).1;
+                        (gradient - reference).magnitude() / reference.magnitude().max(1e-3)
+                    })
+                    .collect();
+                errors.sort_by(f32::total_cmp);
+                //Billow and ridged have creases with no derivative, and the warp samples noise
+                //1000 units out where f32 rounding shows in the differences, so only most points
+                //have to agree closely
+                assert!(
+                    errors[500] < 2e-2 && errors[900] < 6e-2,
+                    "{:?} with warp {}: median error {}, 90th percentile {}",
+                    mode,
+                    warp_strength,
+                    errors[500],
+                    errors[900]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn field_repeats_only_after_its_period() {
+        let mut rng = StdRng::seed_from_u64(41);
+        let time = 0.4;
+        let mut turned = 0;
+        for _ in 0..200 {
+            let p = Vector3::new(
+                rng.gen_range(-4.0..4.0),
+                rng.gen_range(-4.0..4.0),
+                rng.gen_range(-4.0..4.0),
+            );
+            let (n, g) = simplex3d_grad(p, time);
+            let (wrapped, wrapped_gradient) = simplex3d_grad(p, time + FIELD_PERIOD);
+            assert!(
+                (n - wrapped).abs() < 1e-3 && (g - wrapped_gradient).magnitude() < 1e-2,
+                "{:?}: {} against {} after a period",
+                p,
+                n,
+                wrapped
+            );
+            //Corners all turning at the same speed would bring the noise back after one turn
+            if (n - simplex3d_grad(p, time + std::f32::consts::TAU).0).abs() > 1e-2 {
+                turned += 1;
+            }
+        }
+        assert!(
+            turned > 150,
+            "only {} of 200 points changed after a turn",
+            turned
+        );
+    }
+
+    #[test]
+    fn flow_is_tangent_to_colliders() {
+        let colliders = [
+            Collider::new(ColliderShape::Sphere, Vector3::new(-60.0, 10.0, 0.0), 40.0),
+            Collider::new(ColliderShape::Box, Vector3::new(70.0, -20.0, 30.0), 35.0),
+        ];
+        let mut field = FieldParameters::default();
+        for obstacles in [&colliders[..1], &colliders[1..], &colliders[..]] {
+            let report =
+                field.check_boundary(&mut StdRng::seed_from_u64(46), obstacles, 500, 150.0);
+            assert!(report.samples > 250, "{:?}", report);
+            assert!(
+                report.median_normal < 1e-4 && report.max_normal < 1e-3,
+                "{:?}: {:?}",
+                obstacles,
+                report
+            );
+        }
+        //Without the ramp the flow goes straight through
+        field.boundary_width = 0.0;
+        let report = field.check_boundary(&mut StdRng::seed_from_u64(46), &colliders, 500, 150.0);
+        assert!(report.median_normal > 0.1, "{:?}", report);
+    }
+}
@@ -0,0 +1,164 @@
+//Times the analytic curl against the finite difference one it replaced, on the gpu with the
+//real shader code. Waits for the gpu, so it stalls the frame it runs in.
+
+use std::time::{Duration, Instant};
+
+use super::gpu::Gpu;
+
+const GROUP_SIZE: u32 = 64;
+
+#[derive(Debug, Copy, Clone)]
+pub struct CurlTiming {
+    pub total: Duration,
+    //Curl evaluations per second
+    pub throughput: f64,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct FieldBenchmarkReport {
+    pub samples: u32,
+    pub iterations: u32,
+    pub analytic: CurlTiming,
+    pub finite_difference: CurlTiming,
+}
+
+impl FieldBenchmarkReport {
+    pub fn speedup(&self) -> f64 {
+        self.finite_difference.total.as_secs_f64() / self.analytic.total.as_secs_f64()
+    }
+}
+
+pub struct FieldBenchmark {
+    samples: u32,
+    bind_group: wgpu::BindGroup,
+    analytic_pipeline: wgpu::ComputePipeline,
+    finite_difference_pipeline: wgpu::ComputePipeline,
+}
+
+impl FieldBenchmark {
+    //Takes the simulation's field parameter layout, the curl reads its parameters from it
+    pub fn new(
+        gpu: &Gpu,
+        params_bind_group_layout: &wgpu::BindGroupLayout,
+        samples: u32,
+    ) -> FieldBenchmark {
+        let output_buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Field Benchmark Buffer"),
+            size: samples.max(1) as u64 * 16,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let bind_group_layout =
+            gpu.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    }],
+                    label: Some("field_benchmark_bind_group_layout"),
+                });
+        let bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 2,
+                resource: output_buffer.as_entire_binding(),
+            }],
+            label: Some("field_benchmark_bind_group"),
+        });
+        let shader = gpu
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Field Benchmark Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    format!(
+                        "{}\n{}",
+                        include_str!("../shaders/sim.wgsl"),
+                        include_str!("../shaders/field_benchmark.wgsl")
+                    )
+                    .into(),
+                ),
+            });
+        let layout = gpu
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Field Benchmark Pipeline Layout"),
+                bind_group_layouts: &[&bind_group_layout, params_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let pipeline = |entry_point| {
+            gpu.device
+                .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some(entry_point),
+                    layout: Some(&layout),
+                    module: &shader,
+                    entry_point,
+                })
+        };
+        FieldBenchmark {
+            samples,
+            bind_group,
+            analytic_pipeline: pipeline("benchmark_analytic"),
+            finite_difference_pipeline: pipeline("benchmark_finite_difference"),
+        }
+    }
+
+    fn time(
+        &self,
+        gpu: &Gpu,
+        params_bind_group: &wgpu::BindGroup,
+        pipeline: &wgpu::ComputePipeline,
+        iterations: u32,
+    ) -> CurlTiming {
+        let mut encoder = gpu
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Field Benchmark Encoder"),
+            });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Field Benchmark"),
+            });
+            compute_pass.set_pipeline(pipeline);
+            compute_pass.set_bind_group(0, &self.bind_group, &[]);
+            compute_pass.set_bind_group(1, params_bind_group, &[]);
+            for _ in 0..iterations {
+                compute_pass.dispatch_workgroups(self.samples.div_ceil(GROUP_SIZE), 1, 1);
+            }
+        }
+        //Nothing else in flight, so only the benchmark gets timed
+        gpu.device.poll(wgpu::Maintain::Wait);
+        let start = Instant::now();
+        gpu.queue.submit([encoder.finish()]);
+        gpu.device.poll(wgpu::Maintain::Wait);
+        let total = start.elapsed();
+        CurlTiming {
+            total,
+            throughput: self.samples as f64 * iterations as f64 / total.as_secs_f64(),
+        }
+    }
+
+    pub fn run(
+        &self,
+        gpu: &Gpu,
+        params_bind_group: &wgpu::BindGroup,
+        iterations: u32,
+    ) -> FieldBenchmarkReport {
+        let (analytic, finite_difference) =
+            (&self.analytic_pipeline, &self.finite_difference_pipeline);
+        //One untimed round each, so first use costs stay out of the numbers
+        self.time(gpu, params_bind_group, analytic, 1);
+        self.time(gpu, params_bind_group, finite_difference, 1);
+        FieldBenchmarkReport {
+            samples: self.samples,
+            iterations,
+            analytic: self.time(gpu, params_bind_group, analytic, iterations),
+            finite_difference: self.time(gpu, params_bind_group, finite_difference, iterations),
+        }
+    }
+}
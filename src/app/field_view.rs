@@ -0,0 +1,702 @@
+//Makes the vector field visible instead of only its effect on the particles. Arrows on a
+//grid and a line integral convolution image on a plane are sampled on the gpu from the live
+//field, streamlines are traced through the cpu reference field in field.rs.
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::{InnerSpace, Vector3};
+use wgpu::util::DeviceExt;
+
+use super::{
+    camera::FatCamera, colliders::Collider, field::FieldParameters, gpu::Gpu, post::PostProcess,
+    texture::Texture,
+};
+
+const ARROW_VERTICES: u32 = 6;
+const ARROW_GROUP_SIZE: u32 = 64;
+const PLANE_GROUP_SIZE: u32 = 8;
+const LIC_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+//Tracing is slow, so an evolving field only gets its streamlines traced again once its
+//time moved on by this much
+const RETRACE_TIME: f32 = 0.1;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FieldPlane {
+    XY,
+    XZ,
+    YZ,
+}
+
+impl FieldPlane {
+    pub fn next(self) -> FieldPlane {
+        match self {
+            FieldPlane::XY => FieldPlane::XZ,
+            FieldPlane::XZ => FieldPlane::YZ,
+            FieldPlane::YZ => FieldPlane::XY,
+        }
+    }
+
+    //Normal, then the two in plane axes
+    pub fn axes(self) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+        match self {
+            FieldPlane::XY => (Vector3::unit_z(), Vector3::unit_x(), Vector3::unit_y()),
+            FieldPlane::XZ => (Vector3::unit_y(), Vector3::unit_x(), Vector3::unit_z()),
+            FieldPlane::YZ => (Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FieldViewSettings {
+    pub arrows: bool,
+    pub streamlines: bool,
+    pub lic: bool,
+    //Arrows along each axis of the bounds
+    pub grid_resolution: u32,
+    //Speed at the top of the colour ramp, and of full length arrows
+    pub speed_scale: f32,
+    //Plane for the lic image and the streamline seeds
+    pub plane: FieldPlane,
+    //Distance of the plane from the origin along its normal
+    pub plane_offset: f32,
+    pub lic_resolution: u32,
+    //Texels followed each way from every lic texel
+    pub lic_steps: u32,
+    pub lic_alpha: f32,
+    //Streamline seeds along each side of the plane
+    pub seeds: u32,
+    pub streamline_steps: u32,
+    pub streamline_step: f32,
+}
+
+impl Default for FieldViewSettings {
+    fn default() -> Self {
+        FieldViewSettings {
+            arrows: false,
+            streamlines: false,
+            lic: false,
+            grid_resolution: 12,
+            speed_scale: 50.0,
+            plane: FieldPlane::XY,
+            plane_offset: 0.0,
+            lic_resolution: 512,
+            lic_steps: 20,
+            lic_alpha: 0.85,
+            seeds: 8,
+            streamline_steps: 200,
+            streamline_step: 2.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct FieldViewParameters {
+    grid_origin: [f32; 4],
+    plane_center: [f32; 4],
+    plane_u: [f32; 4],
+    plane_v: [f32; 4],
+    grid_resolution: u32,
+    lic_resolution: u32,
+    lic_steps: u32,
+    speed_scale: f32,
+    lic_alpha: f32,
+    _padding: [f32; 3],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct FieldLineVertex {
+    position: [f32; 4],
+    color: [f32; 4],
+}
+
+//Blue through green to red, the same ramp as speed_color in field_sample.wgsl
+pub fn speed_color(speed: f32, speed_scale: f32) -> [f32; 3] {
+    let mix = |a: [f32; 3], b: [f32; 3], t: f32| [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t);
+    let t = (speed / speed_scale).clamp(0.0, 1.0);
+    if t < 0.5 {
+        mix([0.1, 0.2, 1.0], [0.1, 1.0, 0.2], t * 2.0)
+    } else {
+        mix([0.1, 1.0, 0.2], [1.0, 0.15, 0.1], t * 2.0 - 1.0)
+    }
+}
+
+pub struct FieldView {
+    pub settings: FieldViewSettings,
+    //Half size of the bounds the arrows fill and the plane spans
+    extent: f32,
+    params_buffer: wgpu::Buffer,
+    compute_bind_group_layout: wgpu::BindGroupLayout,
+    compute_bind_group: wgpu::BindGroup,
+    render_bind_group_layout: wgpu::BindGroupLayout,
+    render_bind_group: wgpu::BindGroup,
+    arrow_buffer: wgpu::Buffer,
+    plane_vectors_buffer: wgpu::Buffer,
+    lic_texture: Texture,
+    streamline_buffer: wgpu::Buffer,
+    streamline_vertices: u32,
+    //What the streamlines were last traced for
+    traced: Option<(FieldViewSettings, FieldParameters, Vec<Collider>)>,
+    //Grid and lic resolution the buffers were made for
+    allocated: (u32, u32),
+    arrows_pipeline: wgpu::ComputePipeline,
+    sample_plane_pipeline: wgpu::ComputePipeline,
+    convolve_pipeline: wgpu::ComputePipeline,
+    line_pipeline: wgpu::RenderPipeline,
+    plane_pipeline: wgpu::RenderPipeline,
+}
+
+impl FieldView {
+    //params_bind_group_layout is the simulation's, the field reads its parameters from it
+    pub fn new(
+        gpu: &Gpu,
+        fat_cam: &FatCamera,
+        params_bind_group_layout: &wgpu::BindGroupLayout,
+        extent: f32,
+    ) -> FieldView {
+        let settings = FieldViewSettings::default();
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        //Starts at binding 2, the sampling shader is appended to sim.wgsl
+        let compute_bind_group_layout =
+            gpu.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        storage_entry(3),
+                        storage_entry(4),
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 5,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::StorageTexture {
+                                access: wgpu::StorageTextureAccess::WriteOnly,
+                                format: LIC_FORMAT,
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                            },
+                            count: None,
+                        },
+                    ],
+                    label: Some("field_view_compute_bind_group_layout"),
+                });
+        let render_bind_group_layout =
+            gpu.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Texture {
+                                multisampled: false,
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                            count: None,
+                        },
+                    ],
+                    label: Some("field_view_render_bind_group_layout"),
+                });
+        let params_buffer = gpu
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Field View Params Buffer"),
+                contents: bytemuck::bytes_of(&FieldViewParameters::zeroed()),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+
+        let (arrow_buffer, plane_vectors_buffer, lic_texture) =
+            Self::create_resources(gpu, settings.grid_resolution, settings.lic_resolution);
+        let (compute_bind_group, render_bind_group) = Self::create_bind_groups(
+            gpu,
+            &compute_bind_group_layout,
+            &render_bind_group_layout,
+            &params_buffer,
+            &arrow_buffer,
+            &plane_vectors_buffer,
+            &lic_texture,
+        );
+        let streamline_buffer = Self::create_line_buffer(gpu, &[]);
+
+        let compute_shader = gpu
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Field Sample Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    format!(
+                        "{}\n{}",
+                        include_str!("../shaders/sim.wgsl"),
+                        include_str!("../shaders/field_sample.wgsl")
+                    )
+                    .into(),
+                ),
+            });
+        let compute_layout = gpu
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Field Sample Pipeline Layout"),
+                bind_group_layouts: &[&compute_bind_group_layout, params_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let compute_pipeline = |entry_point| {
+            gpu.device
+                .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some(entry_point),
+                    layout: Some(&compute_layout),
+                    module: &compute_shader,
+                    entry_point,
+                })
+        };
+        let arrows_pipeline = compute_pipeline("sample_arrows");
+        let sample_plane_pipeline = compute_pipeline("sample_plane");
+        let convolve_pipeline = compute_pipeline("convolve_plane");
+
+        let (line_pipeline, plane_pipeline) =
+            Self::build_render_pipelines(gpu, fat_cam, &render_bind_group_layout, 1);
+
+        FieldView {
+            settings,
+            extent,
+            params_buffer,
+            compute_bind_group_layout,
+            compute_bind_group,
+            render_bind_group_layout,
+            render_bind_group,
+            arrow_buffer,
+            plane_vectors_buffer,
+            lic_texture,
+            streamline_buffer,
+            streamline_vertices: 0,
+            traced: None,
+            allocated: (settings.grid_resolution, settings.lic_resolution),
+            arrows_pipeline,
+            sample_plane_pipeline,
+            convolve_pipeline,
+            line_pipeline,
+            plane_pipeline,
+        }
+    }
+
+    fn create_resources(
+        gpu: &Gpu,
+        grid_resolution: u32,
+        lic_resolution: u32,
+    ) -> (wgpu::Buffer, wgpu::Buffer, Texture) {
+        let arrows = grid_resolution as u64 * grid_resolution as u64 * grid_resolution as u64;
+        let arrow_buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Field Arrow Buffer"),
+            size: (arrows * ARROW_VERTICES as u64 * std::mem::size_of::<FieldLineVertex>() as u64)
+                .max(32),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        });
+        let plane_vectors_buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Field Plane Vectors Buffer"),
+            size: (lic_resolution as u64 * lic_resolution as u64 * 16).max(16),
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let size = wgpu::Extent3d {
+            width: lic_resolution.max(1),
+            height: lic_resolution.max(1),
+            depth_or_array_layers: 1,
+        };
+        let texture = gpu.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("lic_texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: LIC_FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = gpu.device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let lic_texture = Texture {
+            texture,
+            view,
+            sampler,
+            size,
+        };
+        (arrow_buffer, plane_vectors_buffer, lic_texture)
+    }
+
+    fn create_bind_groups(
+        gpu: &Gpu,
+        compute_layout: &wgpu::BindGroupLayout,
+        render_layout: &wgpu::BindGroupLayout,
+        params_buffer: &wgpu::Buffer,
+        arrow_buffer: &wgpu::Buffer,
+        plane_vectors_buffer: &wgpu::Buffer,
+        lic_texture: &Texture,
+    ) -> (wgpu::BindGroup, wgpu::BindGroup) {
+        let compute = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: compute_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: arrow_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: plane_vectors_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&lic_texture.view),
+                },
+            ],
+            label: Some("field_view_compute_bind_group"),
+        });
+        let render = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: render_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&lic_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&lic_texture.sampler),
+                },
+            ],
+            label: Some("field_view_render_bind_group"),
+        });
+        (compute, render)
+    }
+
+    fn create_line_buffer(gpu: &Gpu, vertices: &[FieldLineVertex]) -> wgpu::Buffer {
+        let placeholder = [FieldLineVertex::zeroed()];
+        gpu.device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Streamline Buffer"),
+                contents: bytemuck::cast_slice(if vertices.is_empty() {
+                    &placeholder
+                } else {
+                    vertices
+                }),
+                usage: wgpu::BufferUsages::VERTEX,
+            })
+    }
+
+    //Line and lic plane pipelines for targets with the given sample count
+    fn build_render_pipelines(
+        gpu: &Gpu,
+        fat_cam: &FatCamera,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        let shader = gpu
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Field View Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/field_view.wgsl").into()),
+            });
+        let layout = gpu
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Field View Pipeline Layout"),
+                bind_group_layouts: &[bind_group_layout, &fat_cam.bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let line_layout = wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<FieldLineVertex>() as u64,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4],
+        };
+        let build = |vs_entry_point, fs_entry_point, topology, buffers, blend| {
+            gpu.device
+                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some(fs_entry_point),
+                    layout: Some(&layout),
+                    vertex: wgpu::VertexState {
+                        module: &shader,
+                        entry_point: vs_entry_point,
+                        buffers,
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &shader,
+                        entry_point: fs_entry_point,
+                        targets: &[Some(wgpu::ColorTargetState {
+                            format: PostProcess::HDR_FORMAT,
+                            blend,
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })],
+                    }),
+                    primitive: wgpu::PrimitiveState {
+                        topology,
+                        ..Default::default()
+                    },
+                    depth_stencil: Some(wgpu::DepthStencilState {
+                        format: Texture::DEPTH_FORMAT,
+                        depth_write_enabled: false,
+                        depth_compare: wgpu::CompareFunction::LessEqual,
+                        stencil: wgpu::StencilState::default(),
+                        bias: wgpu::DepthBiasState::default(),
+                    }),
+                    multisample: wgpu::MultisampleState {
+                        count: sample_count,
+                        ..Default::default()
+                    },
+                    multiview: None,
+                })
+        };
+        (
+            build(
+                "vs_lines",
+                "fs_lines",
+                wgpu::PrimitiveTopology::LineList,
+                &[line_layout],
+                None,
+            ),
+            build(
+                "vs_plane",
+                "fs_plane",
+                wgpu::PrimitiveTopology::TriangleList,
+                &[],
+                Some(wgpu::BlendState::ALPHA_BLENDING),
+            ),
+        )
+    }
+
+    pub fn set_sample_count(&mut self, gpu: &Gpu, fat_cam: &FatCamera, sample_count: u32) {
+        let (line_pipeline, plane_pipeline) = Self::build_render_pipelines(
+            gpu,
+            fat_cam,
+            &self.render_bind_group_layout,
+            sample_count,
+        );
+        self.line_pipeline = line_pipeline;
+        self.plane_pipeline = plane_pipeline;
+    }
+
+    //Plane centre and in plane axes
+    fn plane(&self) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+        let (normal, u, v) = self.settings.plane.axes();
+        (normal * self.settings.plane_offset, u, v)
+    }
+
+    fn trace_streamlines(&mut self, gpu: &Gpu, field: &FieldParameters, obstacles: &[Collider]) {
+        let s = self.settings;
+        let (center, u, v) = self.plane();
+        let mut vertices = Vec::new();
+        for i in 0..s.seeds {
+            for j in 0..s.seeds {
+                let a = ((i as f32 + 0.5) / s.seeds as f32 * 2.0 - 1.0) * self.extent * 0.9;
+                let b = ((j as f32 + 0.5) / s.seeds as f32 * 2.0 - 1.0) * self.extent * 0.9;
+                let line = field.trace_streamline(
+                    center + u * a + v * b,
+                    s.streamline_step,
+                    s.streamline_steps,
+                    obstacles,
+                );
+                let colored: Vec<FieldLineVertex> = line
+                    .iter()
+                    .map(|&p| {
+                        let speed = field.bounded_velocity(p, obstacles).magnitude();
+                        let color = speed_color(speed, s.speed_scale);
+                        FieldLineVertex {
+                            position: p.extend(1.0).into(),
+                            color: [color[0], color[1], color[2], 1.0],
+                        }
+                    })
+                    .collect();
+                for pair in colored.windows(2) {
+                    vertices.extend_from_slice(pair);
+                }
+            }
+        }
+        self.streamline_buffer = Self::create_line_buffer(gpu, &vertices);
+        self.streamline_vertices = vertices.len() as u32;
+    }
+
+    fn needs_retrace(&self, field: &FieldParameters, obstacles: &[Collider]) -> bool {
+        match &self.traced {
+            None => true,
+            Some((settings, traced, traced_obstacles)) => {
+                *settings != self.settings
+                    || traced_obstacles.as_slice() != obstacles
+                    || FieldParameters {
+                        time: field.time,
+                        ..*traced
+                    } != *field
+                    || (field.time - traced.time).abs() > RETRACE_TIME
+            }
+        }
+    }
+
+    //Resamples whatever is enabled. Streamlines only get traced again when the settings, the
+    //field or the obstacles changed, the gpu samples are redone every frame.
+    pub fn update(
+        &mut self,
+        gpu: &Gpu,
+        encoder: &mut wgpu::CommandEncoder,
+        field: &FieldParameters,
+        obstacles: &[Collider],
+        params_bind_group: &wgpu::BindGroup,
+    ) {
+        let s = self.settings;
+        let wanted = (s.grid_resolution.max(1), s.lic_resolution.max(1));
+        if wanted != self.allocated {
+            let (arrow_buffer, plane_vectors_buffer, lic_texture) =
+                Self::create_resources(gpu, wanted.0, wanted.1);
+            let (compute_bind_group, render_bind_group) = Self::create_bind_groups(
+                gpu,
+                &self.compute_bind_group_layout,
+                &self.render_bind_group_layout,
+                &self.params_buffer,
+                &arrow_buffer,
+                &plane_vectors_buffer,
+                &lic_texture,
+            );
+            self.arrow_buffer = arrow_buffer;
+            self.plane_vectors_buffer = plane_vectors_buffer;
+            self.lic_texture = lic_texture;
+            self.compute_bind_group = compute_bind_group;
+            self.render_bind_group = render_bind_group;
+            self.allocated = wanted;
+        }
+
+        if s.streamlines && self.needs_retrace(field, obstacles) {
+            self.trace_streamlines(gpu, field, obstacles);
+            self.traced = Some((s, *field, obstacles.to_vec()));
+        }
+
+        let (center, u, v) = self.plane();
+        let (grid_resolution, lic_resolution) = self.allocated;
+        let params = FieldViewParameters {
+            grid_origin: [
+                -self.extent,
+                -self.extent,
+                -self.extent,
+                self.extent * 2.0 / grid_resolution as f32,
+            ],
+            plane_center: center.extend(self.extent).into(),
+            plane_u: u.extend(0.0).into(),
+            plane_v: v.extend(0.0).into(),
+            grid_resolution,
+            lic_resolution,
+            lic_steps: s.lic_steps,
+            speed_scale: s.speed_scale,
+            lic_alpha: s.lic_alpha,
+            _padding: [0.0; 3],
+        };
+        gpu.queue
+            .write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+
+        if !s.arrows && !s.lic {
+            return;
+        }
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Field Sample"),
+        });
+        compute_pass.set_bind_group(0, &self.compute_bind_group, &[]);
+        compute_pass.set_bind_group(1, params_bind_group, &[]);
+        if s.arrows {
+            let cells = grid_resolution * grid_resolution * grid_resolution;
+            compute_pass.set_pipeline(&self.arrows_pipeline);
+            compute_pass.dispatch_workgroups(cells.div_ceil(ARROW_GROUP_SIZE), 1, 1);
+        }
+        if s.lic {
+            let groups = lic_resolution.div_ceil(PLANE_GROUP_SIZE);
+            compute_pass.set_pipeline(&self.sample_plane_pipeline);
+            compute_pass.dispatch_workgroups(groups, groups, 1);
+            compute_pass.set_pipeline(&self.convolve_pipeline);
+            compute_pass.dispatch_workgroups(groups, groups, 1);
+        }
+    }
+
+    //Draws the enabled visualisations into target, tested against the scene depth
+    pub fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        fat_cam: &FatCamera,
+        target: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+    ) {
+        let s = &self.settings;
+        if !s.arrows && !s.streamlines && !s.lic {
+            return;
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Field View Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: None,
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_bind_group(0, &self.render_bind_group, &[]);
+        render_pass.set_bind_group(1, &fat_cam.bind_group, &[]);
+        render_pass.set_pipeline(&self.line_pipeline);
+        if s.arrows {
+            let (grid_resolution, _) = self.allocated;
+            let vertices = grid_resolution * grid_resolution * grid_resolution * ARROW_VERTICES;
+            render_pass.set_vertex_buffer(0, self.arrow_buffer.slice(..));
+            render_pass.draw(0..vertices, 0..1);
+        }
+        if s.streamlines && self.streamline_vertices > 0 {
+            render_pass.set_vertex_buffer(0, self.streamline_buffer.slice(..));
+            render_pass.draw(0..self.streamline_vertices, 0..1);
+        }
+        if s.lic {
+            render_pass.set_pipeline(&self.plane_pipeline);
+            render_pass.draw(0..6, 0..1);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//Smoothed particle hydrodynamics for Behaviour::Fluid, after Müller et al. 2003. Densities
+//come from the poly6 kernel, pressure forces from the spiky kernel's gradient and
+//viscosity from its laplacian, with Becker and Teschner's cohesion as surface tension.
+//Particles stay in a box around the origin, see fluid.wgsl.
+
+use std::f32::consts::PI;
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::{InnerSpace, Vector3};
+use wgpu::util::DeviceExt;
+
+use super::gpu::Gpu;
+
+#[derive(Debug, Copy, Clone)]
+pub struct FluidSettings {
+    //Kernel radius h, also the spatial hash cell size while the fluid runs
+    pub smoothing_radius: f32,
+    pub rest_density: f32,
+    pub particle_mass: f32,
+    //Pressure per unit of density above rest density, negative pressures are dropped
+    pub stiffness: f32,
+    pub viscosity: f32,
+    pub surface_tension: f32,
+    pub gravity: Vector3<f32>,
+    //Half size of the container box
+    pub container: Vector3<f32>,
+    //Fraction of the velocity into a wall that bounces back
+    pub wall_restitution: f32,
+    //Particles are slowed to this, it bounds the time step
+    pub max_speed: f32,
+    //Largest step a frame takes, stable_time_step may cut it down
+    pub time_step: f32,
+}
+
+impl Default for FluidSettings {
+    fn default() -> Self {
+        FluidSettings {
+            smoothing_radius: 2.0,
+            rest_density: 1.0,
+            particle_mass: 1.0,
+            stiffness: 1000.0,
+            viscosity: 0.5,
+            surface_tension: 1.0,
+            gravity: Vector3::new(0.0, -10.0, 0.0),
+            container: Vector3::new(100.0, 100.0, 50.0),
+            wall_restitution: 0.2,
+            max_speed: 40.0,
+            time_step: 0.016,
+        }
+    }
+}
+
+impl FluidSettings {
+    //Speed pressure waves travel at, with pressure = stiffness * (density - rest_density)
+    pub fn sound_speed(&self) -> f32 {
+        self.stiffness.max(0.0).sqrt()
+    }
+
+    //time_step clamped so that neither pressure waves nor the fastest particles cross more
+    //than 0.4 of the kernel radius a step (CFL), viscosity doesn't overshoot and gravity
+    //doesn't move a resting particle more than a quarter of it
+    pub fn stable_time_step(&self) -> f32 {
+        let h = self.smoothing_radius;
+        let cfl = 0.4 * h / (self.sound_speed() + self.max_speed).max(1e-6);
+        let kinematic_viscosity = self.viscosity / self.rest_density.max(1e-6);
+        let viscous = 0.125 * h * h / kinematic_viscosity.max(1e-6);
+        let acceleration = 0.25 * (h / self.gravity.magnitude().max(1e-6)).sqrt();
+        self.time_step.min(cfl).min(viscous).min(acceleration)
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct FluidParameters {
+    //Wall restitution in w
+    container: [f32; 4],
+    gravity: [f32; 4],
+    smoothing_radius: f32,
+    rest_density: f32,
+    particle_mass: f32,
+    stiffness: f32,
+    viscosity: f32,
+    surface_tension: f32,
+    time_step: f32,
+    max_speed: f32,
+    //Kernel normalisations, worked out here since h^9 gets out of f32 range quickly
+    poly6: f32,
+    spiky_gradient: f32,
+    viscosity_laplacian: f32,
+    _padding: f32,
+}
+
+impl From<&FluidSettings> for FluidParameters {
+    fn from(settings: &FluidSettings) -> Self {
+        let h = settings.smoothing_radius as f64;
+        let pi = PI as f64;
+        FluidParameters {
+            container: settings.container.extend(settings.wall_restitution).into(),
+            gravity: settings.gravity.extend(0.0).into(),
+            smoothing_radius: settings.smoothing_radius,
+            rest_density: settings.rest_density,
+            particle_mass: settings.particle_mass,
+            stiffness: settings.stiffness,
+            viscosity: settings.viscosity,
+            surface_tension: settings.surface_tension,
+            time_step: settings.stable_time_step(),
+            max_speed: settings.max_speed,
+            poly6: (315.0 / (64.0 * pi * h.powi(9))) as f32,
+            spiky_gradient: (45.0 / (pi * h.powi(6))) as f32,
+            viscosity_laplacian: (45.0 / (pi * h.powi(6))) as f32,
+            _padding: 0.0,
+        }
+    }
+}
+
+pub struct Fluid {
+    pub settings: FluidSettings,
+    pub buffer: wgpu::Buffer,
+    //Per particle density, written by the first pass and read by the second
+    pub densities: wgpu::Buffer,
+}
+
+impl Fluid {
+    pub fn new(gpu: &Gpu, num_particles: usize) -> Fluid {
+        let settings = FluidSettings::default();
+        let buffer = gpu
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Fluid Parameters"),
+                contents: bytemuck::bytes_of(&FluidParameters::from(&settings)),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+        let densities = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Fluid Densities"),
+            size: (num_particles.max(1) * 4) as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        Fluid {
+            settings,
+            buffer,
+            densities,
+        }
+    }
+
+    pub fn write(&self, gpu: &Gpu) {
+        gpu.queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::bytes_of(&FluidParameters::from(&self.settings)),
+        );
+    }
+}
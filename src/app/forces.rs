@@ -0,0 +1,191 @@
+//Force primitives layered on top of the noise flow. The list is uploaded every frame and
+//the simulation sums the accelerations of all of them for every particle.
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::{InnerSpace, Quaternion, Rotation, Vector3};
+
+use super::gpu::Gpu;
+
+//Storage is allocated for this many, the rest of the list is ignored
+pub const MAX_FORCES: usize = 32;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ForceKind {
+    //Pulls towards position, pushes away with a negative strength
+    Attractor,
+    //Spins around the line through position along axis
+    LineVortex,
+    //Smoke ring: flows around a ring of the given radius around axis
+    ToroidalVortex,
+    //Drags particles towards the velocity axis * strength
+    Wind,
+    //Slows particles down by strength per second
+    Drag,
+    //Constant acceleration along axis
+    Gravity,
+    //Curl noise acceleration with features radius across
+    Turbulence,
+    //Pulls towards the ray from position along axis, pushes away with a negative strength
+    RayAttractor,
+}
+
+impl ForceKind {
+    pub const ALL: [ForceKind; 8] = [
+        ForceKind::Attractor,
+        ForceKind::LineVortex,
+        ForceKind::ToroidalVortex,
+        ForceKind::Wind,
+        ForceKind::Drag,
+        ForceKind::Gravity,
+        ForceKind::Turbulence,
+        ForceKind::RayAttractor,
+    ];
+}
+
+//Where a force acts, centred on its position
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ForceRegion {
+    Everywhere,
+    //Axis aligned, region_size is the half size
+    Box,
+    //region_size.x is the radius
+    Sphere,
+}
+
+impl ForceRegion {
+    pub fn next(self) -> ForceRegion {
+        match self {
+            ForceRegion::Everywhere => ForceRegion::Box,
+            ForceRegion::Box => ForceRegion::Sphere,
+            ForceRegion::Sphere => ForceRegion::Everywhere,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Force {
+    pub kind: ForceKind,
+    pub position: Vector3<f32>,
+    pub axis: Vector3<f32>,
+    pub strength: f32,
+    //Distance the strength has halved at for attractors and line vortices, ring radius for
+    //toroidal vortices, feature size for turbulence
+    pub radius: f32,
+    //Exponent of the falloff, 2 falls off with the square of the distance beyond radius
+    pub falloff: f32,
+    pub region: ForceRegion,
+    pub region_size: Vector3<f32>,
+    //Frame the force is evaluated in: offsets from position are rotated back and divided by
+    //scale, the acceleration is rotated forward. axis and region_size are in this frame.
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Force {
+    //A force of the given kind with settings that show up well in the default field
+    pub fn new(kind: ForceKind, position: Vector3<f32>, axis: Vector3<f32>) -> Force {
+        let (strength, radius, region) = match kind {
+            ForceKind::Attractor => (200.0, 50.0, ForceRegion::Everywhere),
+            ForceKind::LineVortex => (300.0, 40.0, ForceRegion::Everywhere),
+            ForceKind::ToroidalVortex => (300.0, 60.0, ForceRegion::Everywhere),
+            ForceKind::Wind => (40.0, 0.0, ForceRegion::Everywhere),
+            ForceKind::Drag => (0.5, 0.0, ForceRegion::Everywhere),
+            ForceKind::Gravity => (30.0, 0.0, ForceRegion::Everywhere),
+            ForceKind::Turbulence => (400.0, 20.0, ForceRegion::Sphere),
+            ForceKind::RayAttractor => (200.0, 30.0, ForceRegion::Everywhere),
+        };
+        Force {
+            kind,
+            position,
+            axis: axis.normalize(),
+            strength,
+            radius,
+            falloff: 2.0,
+            region,
+            region_size: Vector3::new(80.0, 80.0, 80.0),
+            rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    //Turns the force's frame to rotation, keeping the axis pointing the same way in the world
+    pub fn orient(&mut self, rotation: Quaternion<f32>) {
+        let axis = self.rotation.rotate_vector(self.axis);
+        self.rotation = rotation.normalize();
+        self.axis = self.rotation.invert().rotate_vector(axis);
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct ForceParameters {
+    //Strength in w
+    position: [f32; 4],
+    //Radius in w
+    axis: [f32; 4],
+    //Falloff in w
+    region_size: [f32; 4],
+    //Vector part in xyz
+    rotation: [f32; 4],
+    scale: [f32; 4],
+    kind: u32,
+    region: u32,
+    _padding: [u32; 2],
+}
+
+impl From<&Force> for ForceParameters {
+    fn from(force: &Force) -> Self {
+        let rotation = force.rotation.normalize();
+        ForceParameters {
+            position: force.position.extend(force.strength).into(),
+            axis: force.axis.normalize().extend(force.radius).into(),
+            region_size: force.region_size.extend(force.falloff).into(),
+            rotation: rotation.v.extend(rotation.s).into(),
+            scale: force.scale.extend(0.0).into(),
+            kind: force.kind as u32,
+            region: force.region as u32,
+            _padding: [0; 2],
+        }
+    }
+}
+
+pub struct ForceStack {
+    pub forces: Vec<Force>,
+    //Held by the mouse for as long as it drags, goes ahead of the stack so it's never dropped
+    pub mouse: Option<Force>,
+    pub buffer: wgpu::Buffer,
+}
+
+impl ForceStack {
+    //Header of the storage buffer, the force count padded to the array's alignment
+    const HEADER_SIZE: usize = 16;
+
+    pub fn new(gpu: &Gpu) -> ForceStack {
+        let buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Force Buffer"),
+            size: (Self::HEADER_SIZE + MAX_FORCES * std::mem::size_of::<ForceParameters>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        ForceStack {
+            forces: Vec::new(),
+            mouse: None,
+            buffer,
+        }
+    }
+
+    //Forces that reach the gpu
+    pub fn active(&self) -> impl Iterator<Item = &Force> {
+        self.mouse.iter().chain(&self.forces).take(MAX_FORCES)
+    }
+
+    pub fn write(&self, gpu: &Gpu) {
+        let count = self.active().count();
+        let mut contents = vec![0u8; Self::HEADER_SIZE];
+        contents[..4].copy_from_slice(bytemuck::bytes_of(&(count as u32)));
+        for force in self.active() {
+            contents.extend_from_slice(bytemuck::bytes_of(&ForceParameters::from(force)));
+        }
+        gpu.queue.write_buffer(&self.buffer, 0, &contents);
+    }
+}
@@ -0,0 +1,468 @@
+//Orientation aids drawn behind and around the particles: background, ground grid,
+//an axis gizmo at the origin and the simulation bounds.
+
+use std::path::Path;
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use super::{
+    camera::FatCamera,
+    clip::{ClipSettings, ClipShape},
+    gpu::Gpu,
+    post::PostProcess,
+    texture::Texture,
+};
+
+const GIZMO_SIZE: f32 = 50.0;
+const GIZMO_VERTICES: u32 = 6;
+const BOUNDS_VERTICES: u32 = 24;
+const CLIP_COLOR: [f32; 3] = [1.0, 0.8, 0.1];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BackgroundMode {
+    Solid,
+    //Vertical gradient from background_bottom to background_top
+    Gradient,
+    //Equirectangular image wrapped around the camera
+    Equirect,
+}
+
+impl BackgroundMode {
+    pub fn next(self) -> BackgroundMode {
+        match self {
+            BackgroundMode::Solid => BackgroundMode::Gradient,
+            BackgroundMode::Gradient => BackgroundMode::Equirect,
+            BackgroundMode::Equirect => BackgroundMode::Solid,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct HelperSettings {
+    pub grid: bool,
+    pub gizmo: bool,
+    pub bounds: bool,
+    pub background: BackgroundMode,
+    //Solid backgrounds use the top colour
+    pub background_top: [f32; 3],
+    pub background_bottom: [f32; 3],
+    pub grid_spacing: f32,
+    pub grid_height: f32,
+    pub grid_fade_distance: f32,
+}
+
+impl Default for HelperSettings {
+    fn default() -> Self {
+        HelperSettings {
+            grid: false,
+            gizmo: false,
+            bounds: false,
+            background: BackgroundMode::Solid,
+            background_top: [0.0, 0.0, 0.0],
+            background_bottom: [0.0, 0.0, 0.0],
+            grid_spacing: 10.0,
+            grid_height: -100.0,
+            grid_fade_distance: 1500.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct HelperParameters {
+    background_top: [f32; 4],
+    background_bottom: [f32; 4],
+    background_mode: u32,
+    grid_spacing: f32,
+    grid_height: f32,
+    grid_fade_distance: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct LineVertex {
+    position: [f32; 3],
+    color: [f32; 3],
+}
+
+pub struct Helpers {
+    pub settings: HelperSettings,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    params_buffer: wgpu::Buffer,
+    background_texture: Texture,
+    //Gizmo axes followed by the bounds edges
+    line_buffer: wgpu::Buffer,
+    //Outline of the clip region, rewritten every frame it's shown
+    clip_buffer: wgpu::Buffer,
+    extent: f32,
+    background_pipeline: wgpu::RenderPipeline,
+    grid_pipeline: wgpu::RenderPipeline,
+    line_pipeline: wgpu::RenderPipeline,
+}
+
+impl Helpers {
+    //extent is the half size of the simulation bounds
+    pub fn new(gpu: &Gpu, fat_cam: &FatCamera, extent: f32) -> Helpers {
+        let bind_group_layout =
+            gpu.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Texture {
+                                multisampled: false,
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                            count: None,
+                        },
+                    ],
+                    label: Some("helper_bind_group_layout"),
+                });
+        let params_buffer = gpu
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Helper Params Buffer"),
+                contents: bytemuck::bytes_of(&HelperParameters::zeroed()),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+        //Grey stand in until an equirect image is loaded
+        let placeholder = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            1,
+            1,
+            image::Rgba([32, 32, 32, 255]),
+        ));
+        let background_texture = Texture::from_image(
+            &gpu.device,
+            &gpu.queue,
+            &placeholder,
+            Some("background_texture"),
+        )
+        .unwrap();
+        let bind_group =
+            Self::create_bind_group(gpu, &bind_group_layout, &params_buffer, &background_texture);
+
+        let line_buffer = gpu
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Helper Line Buffer"),
+                contents: bytemuck::cast_slice(&Self::line_vertices(extent)),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+
+        let clip_buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Clip Gizmo Buffer"),
+            size: (ClipSettings::MAX_GIZMO_VERTICES * std::mem::size_of::<LineVertex>()) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let (background_pipeline, grid_pipeline, line_pipeline) =
+            Self::build_pipelines(gpu, fat_cam, &bind_group_layout, 1);
+
+        Helpers {
+            settings: HelperSettings::default(),
+            bind_group_layout,
+            bind_group,
+            params_buffer,
+            background_texture,
+            line_buffer,
+            clip_buffer,
+            extent,
+            background_pipeline,
+            grid_pipeline,
+            line_pipeline,
+        }
+    }
+
+    fn line_vertices(extent: f32) -> Vec<LineVertex> {
+        let mut vertices = Vec::new();
+        let axes = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+        for axis in axes {
+            let tip = axis.map(|a| a * GIZMO_SIZE);
+            vertices.push(LineVertex {
+                position: [0.0; 3],
+                color: axis,
+            });
+            vertices.push(LineVertex {
+                position: tip,
+                color: axis,
+            });
+        }
+        //Each cube edge runs along one axis, between two corners that differ only in that axis
+        let color = [0.5, 0.5, 0.5];
+        for axis in 0..3 {
+            for corner in 0..4 {
+                let mut start = [-extent; 3];
+                let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+                if corner & 1 != 0 {
+                    start[a] = extent;
+                }
+                if corner & 2 != 0 {
+                    start[b] = extent;
+                }
+                let mut end = start;
+                end[axis] = extent;
+                vertices.push(LineVertex {
+                    position: start,
+                    color,
+                });
+                vertices.push(LineVertex {
+                    position: end,
+                    color,
+                });
+            }
+        }
+        vertices
+    }
+
+    fn create_bind_group(
+        gpu: &Gpu,
+        layout: &wgpu::BindGroupLayout,
+        params_buffer: &wgpu::Buffer,
+        background_texture: &Texture,
+    ) -> wgpu::BindGroup {
+        gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&background_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&background_texture.sampler),
+                },
+            ],
+            label: Some("helper_bind_group"),
+        })
+    }
+
+    //Background, grid and line pipelines for targets with the given sample count
+    fn build_pipelines(
+        gpu: &Gpu,
+        fat_cam: &FatCamera,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> (
+        wgpu::RenderPipeline,
+        wgpu::RenderPipeline,
+        wgpu::RenderPipeline,
+    ) {
+        let shader = gpu
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Helper Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/helpers.wgsl").into()),
+            });
+        let layout = gpu
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Helper Pipeline Layout"),
+                bind_group_layouts: &[bind_group_layout, &fat_cam.bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let alpha_blend = wgpu::BlendState::ALPHA_BLENDING;
+        let line_layout = wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<LineVertex>() as u64,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3],
+        };
+        let build = |vs_entry_point, fs_entry_point, topology, buffers, blend| {
+            gpu.device
+                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some(fs_entry_point),
+                    layout: Some(&layout),
+                    vertex: wgpu::VertexState {
+                        module: &shader,
+                        entry_point: vs_entry_point,
+                        buffers,
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &shader,
+                        entry_point: fs_entry_point,
+                        targets: &[Some(wgpu::ColorTargetState {
+                            format: PostProcess::HDR_FORMAT,
+                            blend,
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })],
+                    }),
+                    primitive: wgpu::PrimitiveState {
+                        topology,
+                        ..Default::default()
+                    },
+                    //Tested against the scene depth, which is read only by now
+                    depth_stencil: Some(wgpu::DepthStencilState {
+                        format: Texture::DEPTH_FORMAT,
+                        depth_write_enabled: false,
+                        depth_compare: wgpu::CompareFunction::LessEqual,
+                        stencil: wgpu::StencilState::default(),
+                        bias: wgpu::DepthBiasState::default(),
+                    }),
+                    multisample: wgpu::MultisampleState {
+                        count: sample_count,
+                        ..Default::default()
+                    },
+                    multiview: None,
+                })
+        };
+        (
+            build(
+                "vs_fullscreen",
+                "fs_background",
+                wgpu::PrimitiveTopology::TriangleList,
+                &[],
+                None,
+            ),
+            build(
+                "vs_fullscreen",
+                "fs_grid",
+                wgpu::PrimitiveTopology::TriangleList,
+                &[],
+                Some(alpha_blend),
+            ),
+            build(
+                "vs_lines",
+                "fs_lines",
+                wgpu::PrimitiveTopology::LineList,
+                &[line_layout],
+                None,
+            ),
+        )
+    }
+
+    pub fn set_sample_count(&mut self, gpu: &Gpu, fat_cam: &FatCamera, sample_count: u32) {
+        let (background_pipeline, grid_pipeline, line_pipeline) =
+            Self::build_pipelines(gpu, fat_cam, &self.bind_group_layout, sample_count);
+        self.background_pipeline = background_pipeline;
+        self.grid_pipeline = grid_pipeline;
+        self.line_pipeline = line_pipeline;
+    }
+
+    //Uses an equirectangular png or jpeg as the background
+    pub fn load_background(&mut self, gpu: &Gpu, path: &Path) -> Result<()> {
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("couldn't read background {}", path.display()))?;
+        let label = path.to_string_lossy();
+        self.background_texture = Texture::from_bytes(&gpu.device, &gpu.queue, &bytes, &label)?;
+        self.bind_group = Self::create_bind_group(
+            gpu,
+            &self.bind_group_layout,
+            &self.params_buffer,
+            &self.background_texture,
+        );
+        self.settings.background = BackgroundMode::Equirect;
+        Ok(())
+    }
+
+    //Draws the enabled helpers into target on top of the scene geometry. Background and
+    //grid only show where the depth texture isn't covered by something closer.
+    pub fn draw(
+        &self,
+        gpu: &Gpu,
+        encoder: &mut wgpu::CommandEncoder,
+        fat_cam: &FatCamera,
+        target: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+        clip: &ClipSettings,
+    ) {
+        let s = &self.settings;
+        let params = HelperParameters {
+            background_top: [
+                s.background_top[0],
+                s.background_top[1],
+                s.background_top[2],
+                1.0,
+            ],
+            background_bottom: [
+                s.background_bottom[0],
+                s.background_bottom[1],
+                s.background_bottom[2],
+                1.0,
+            ],
+            background_mode: s.background as u32,
+            grid_spacing: s.grid_spacing,
+            grid_height: s.grid_height,
+            grid_fade_distance: s.grid_fade_distance,
+        };
+        gpu.queue
+            .write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+        let clip_vertices: Vec<LineVertex> = if clip.gizmo && clip.shape != ClipShape::Off {
+            clip.gizmo_lines(self.extent)
+                .into_iter()
+                .map(|position| LineVertex {
+                    position,
+                    color: CLIP_COLOR,
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        if !clip_vertices.is_empty() {
+            gpu.queue
+                .write_buffer(&self.clip_buffer, 0, bytemuck::cast_slice(&clip_vertices));
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Helper Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: None,
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_bind_group(1, &fat_cam.bind_group, &[]);
+
+        render_pass.set_pipeline(&self.background_pipeline);
+        render_pass.draw(0..3, 0..1);
+        if s.grid {
+            render_pass.set_pipeline(&self.grid_pipeline);
+            render_pass.draw(0..3, 0..1);
+        }
+        render_pass.set_pipeline(&self.line_pipeline);
+        render_pass.set_vertex_buffer(0, self.line_buffer.slice(..));
+        if s.gizmo {
+            render_pass.draw(0..GIZMO_VERTICES, 0..1);
+        }
+        if s.bounds {
+            render_pass.draw(GIZMO_VERTICES..GIZMO_VERTICES + BOUNDS_VERTICES, 0..1);
+        }
+        if !clip_vertices.is_empty() {
+            render_pass.set_vertex_buffer(0, self.clip_buffer.slice(..));
+            render_pass.draw(0..clip_vertices.len() as u32, 0..1);
+        }
+    }
+}
@@ -1,24 +1,37 @@
-use winit::event::{MouseButton, WindowEvent};
+use std::path::PathBuf;
+
+use winit::event::{MouseButton, VirtualKeyCode, WindowEvent};
 
 use super::math::{FVec2, FVec3};
 
 #[derive(Debug)]
 pub struct Input {
     pub mouse_down: bool,
+    //Left button, separate from the right button camera look
+    pub left_mouse_down: bool,
     pub movement: FVec3,
     pub mouse_delta: (usize, FVec2),
     last_mouse_pos: FVec2,
     pub scroll_delta: f32,
+    //Keys that went down since the last clear_pressed_keys. Key repeat is ignored.
+    pressed_keys: Vec<VirtualKeyCode>,
+    held_keys: Vec<VirtualKeyCode>,
+    //Files dragged onto the window, waiting to be handled
+    dropped_files: Vec<PathBuf>,
 }
 
 impl Input {
     pub fn new() -> Input {
         Input {
             mouse_down: false,
+            left_mouse_down: false,
             movement: FVec3::default(),
             mouse_delta: (0, FVec2::default()),
             last_mouse_pos: FVec2::default(),
             scroll_delta: 0.0,
+            pressed_keys: Vec::new(),
+            held_keys: Vec::new(),
+            dropped_files: Vec::new(),
         }
     }
 
@@ -34,6 +47,32 @@ impl Input {
         self.mouse_delta.1
     }
 
+    //Last cursor position in window pixels
+    pub fn cursor_position(&self) -> FVec2 {
+        self.last_mouse_pos
+    }
+
+    pub fn key_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.pressed_keys.contains(&key)
+    }
+
+    pub fn key_held(&self, key: VirtualKeyCode) -> bool {
+        self.held_keys.contains(&key)
+    }
+
+    pub fn clear_pressed_keys(&mut self) {
+        self.pressed_keys.clear();
+    }
+
+    //Lines scrolled since the last call
+    pub fn take_scroll_delta(&mut self) -> f32 {
+        std::mem::take(&mut self.scroll_delta)
+    }
+
+    pub fn take_dropped_files(&mut self) -> Vec<PathBuf> {
+        std::mem::take(&mut self.dropped_files)
+    }
+
     #[allow(deprecated)]
     pub fn handle_input(&mut self, event: &WindowEvent, render_ticks: usize) {
         match event {
@@ -43,6 +82,12 @@ impl Input {
                 is_synthetic: _,
             } => match input.state {
                 winit::event::ElementState::Pressed => {
+                    if let Some(key) = input.virtual_keycode {
+                        if !self.held_keys.contains(&key) {
+                            self.held_keys.push(key);
+                            self.pressed_keys.push(key);
+                        }
+                    }
                     if input.virtual_keycode == Some(winit::event::VirtualKeyCode::A) {
                         self.movement.x = -1.0;
                     }
@@ -63,6 +108,9 @@ impl Input {
                     }
                 }
                 winit::event::ElementState::Released => {
+                    if let Some(key) = input.virtual_keycode {
+                        self.held_keys.retain(|k| *k != key);
+                    }
                     if input.virtual_keycode == Some(winit::event::VirtualKeyCode::A) {
                         self.movement.x = 0.0;
                     }
@@ -83,6 +131,9 @@ impl Input {
                     }
                 }
             },
+            WindowEvent::DroppedFile(path) => {
+                self.dropped_files.push(path.clone());
+            }
             WindowEvent::CursorMoved {
                 device_id: _,
                 position,
@@ -109,7 +160,7 @@ impl Input {
                 modifiers: _,
             } => match delta {
                 winit::event::MouseScrollDelta::LineDelta(_x, y) => {
-                    self.scroll_delta = *y;
+                    self.scroll_delta += *y;
                 }
                 winit::event::MouseScrollDelta::PixelDelta(_pos) => {}
             },
@@ -123,12 +174,18 @@ impl Input {
                     MouseButton::Right => {
                         self.mousedown();
                     }
+                    MouseButton::Left => {
+                        self.left_mouse_down = true;
+                    }
                     _ => {}
                 },
                 winit::event::ElementState::Released => match button {
                     MouseButton::Right => {
                         self.mouseup(render_ticks);
                     }
+                    MouseButton::Left => {
+                        self.left_mouse_down = false;
+                    }
                     _ => {}
                 },
             },
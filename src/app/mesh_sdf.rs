@@ -0,0 +1,332 @@
+//Signed distance fields baked from triangle meshes. Exact distances next to the surface,
+//swept out to the rest of the grid through the neighbours' closest triangles, signed by
+//counting surface crossings along x. Same scheme as Bridson's SDFGen.
+
+use std::path::Path;
+
+use anyhow::*;
+use cgmath::{InnerSpace, Vector3};
+
+pub struct TriangleMesh {
+    pub vertices: Vec<Vector3<f32>>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+impl TriangleMesh {
+    //Only positions and faces are read, polygons are split into fans
+    pub fn load_obj(path: &Path) -> Result<TriangleMesh> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("couldn't read mesh {}", path.display()))?;
+        let mut vertices = Vec::new();
+        let mut triangles = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("v") => {
+                    let coords = tokens
+                        .take(3)
+                        .map(|t| t.parse::<f32>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .with_context(|| format!("bad vertex on line {}", number + 1))?;
+                    if coords.len() != 3 {
+                        bail!("vertex on line {} needs 3 coordinates", number + 1);
+                    }
+                    vertices.push(Vector3::new(coords[0], coords[1], coords[2]));
+                }
+                Some("f") => {
+                    let mut face = Vec::new();
+                    for token in tokens {
+                        //v, v/vt, v//vn or v/vt/vn, negative indices count back from the end
+                        let index: i64 = token
+                            .split('/')
+                            .next()
+                            .unwrap_or("")
+                            .parse()
+                            .with_context(|| format!("bad face on line {}", number + 1))?;
+                        let index = if index < 0 {
+                            vertices.len() as i64 + index
+                        } else {
+                            index - 1
+                        };
+                        if index < 0 || index >= vertices.len() as i64 {
+                            bail!("face on line {} uses a missing vertex", number + 1);
+                        }
+                        face.push(index as usize);
+                    }
+                    for i in 1..face.len().saturating_sub(1) {
+                        triangles.push([face[0], face[i], face[i + 1]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if triangles.is_empty() {
+            bail!("{} has no faces", path.display());
+        }
+        Ok(TriangleMesh {
+            vertices,
+            triangles,
+        })
+    }
+
+    //Centres the mesh on the origin and scales it so its largest half extent is half_size
+    pub fn normalize(&mut self, half_size: f32) {
+        let mut min = Vector3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = -min;
+        for v in &self.vertices {
+            min = Vector3::new(min.x.min(v.x), min.y.min(v.y), min.z.min(v.z));
+            max = Vector3::new(max.x.max(v.x), max.y.max(v.y), max.z.max(v.z));
+        }
+        let center = (min + max) * 0.5;
+        let extent = (max - min) * 0.5;
+        let scale = half_size / extent.x.max(extent.y).max(extent.z).max(1e-6);
+        for v in &mut self.vertices {
+            *v = (*v - center) * scale;
+        }
+    }
+
+    fn triangle(&self, index: usize) -> [Vector3<f32>; 3] {
+        let [a, b, c] = self.triangles[index];
+        [self.vertices[a], self.vertices[b], self.vertices[c]]
+    }
+}
+
+//Closest point on the triangle abc to p, from Ericson's Real-Time Collision Detection
+fn closest_point_on_triangle(p: Vector3<f32>, [a, b, c]: [Vector3<f32>; 3]) -> Vector3<f32> {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+    let bp = p - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+    let cp = p - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+    let denom = 1.0 / (va + vb + vc);
+    a + ab * (vb * denom) + ac * (vc * denom)
+}
+
+//Distances on a resolution^3 grid spanning -1 to 1 on every axis, x fastest
+pub struct MeshSdf {
+    pub resolution: u32,
+    pub distances: Vec<f32>,
+}
+
+impl MeshSdf {
+    //Cells around each triangle that get exact distances before the sweeps
+    const EXACT_BAND: i32 = 1;
+
+    pub fn cell_size(&self) -> f32 {
+        2.0 / self.resolution as f32
+    }
+
+    pub fn cell_center(&self, i: usize, j: usize, k: usize) -> Vector3<f32> {
+        let cell = self.cell_size();
+        Vector3::new(i as f32 + 0.5, j as f32 + 0.5, k as f32 + 0.5) * cell
+            - Vector3::new(1.0, 1.0, 1.0)
+    }
+
+    fn index(&self, i: usize, j: usize, k: usize) -> usize {
+        let n = self.resolution as usize;
+        (k * n + j) * n + i
+    }
+
+    //Loads an obj and bakes it scaled to fit, leaving a couple of cells of outside around it.
+    //Slow enough at 64^3 that the app runs it on a worker thread.
+    pub fn bake_obj(path: &Path, resolution: u32) -> Result<MeshSdf> {
+        let mut mesh = TriangleMesh::load_obj(path)?;
+        mesh.normalize(1.0 - 4.0 / resolution as f32);
+        Ok(MeshSdf::bake(&mesh, resolution))
+    }
+
+    //The mesh should be closed and fit inside -1 to 1, see TriangleMesh::normalize
+    pub fn bake(mesh: &TriangleMesh, resolution: u32) -> MeshSdf {
+        let n = resolution as usize;
+        let mut sdf = MeshSdf {
+            resolution,
+            //Further than anything in the grid
+            distances: vec![4.0; n * n * n],
+        };
+        let mut closest = vec![usize::MAX; n * n * n];
+        let mut crossings = vec![0u32; n * n * n];
+        let cell = sdf.cell_size();
+        //Continuous grid coordinate of a position, cell centres at whole numbers
+        let to_grid = |x: f32| (x + 1.0) / cell - 0.5;
+
+        for t in 0..mesh.triangles.len() {
+            let tri = mesh.triangle(t);
+            let lo = tri
+                .iter()
+                .fold(Vector3::new(f32::MAX, f32::MAX, f32::MAX), |m, v| {
+                    Vector3::new(m.x.min(v.x), m.y.min(v.y), m.z.min(v.z))
+                });
+            let hi = tri
+                .iter()
+                .fold(-Vector3::new(f32::MAX, f32::MAX, f32::MAX), |m, v| {
+                    Vector3::new(m.x.max(v.x), m.y.max(v.y), m.z.max(v.z))
+                });
+            let range = |lo: f32, hi: f32, band: i32| {
+                let start = (to_grid(lo).floor() as i32 - band).max(0) as usize;
+                let end = (to_grid(hi).ceil() as i32 + band).min(n as i32 - 1);
+                start..(end + 1).max(0) as usize
+            };
+            for k in range(lo.z, hi.z, Self::EXACT_BAND) {
+                for j in range(lo.y, hi.y, Self::EXACT_BAND) {
+                    for i in range(lo.x, hi.x, Self::EXACT_BAND) {
+                        let p = sdf.cell_center(i, j, k);
+                        let d = (p - closest_point_on_triangle(p, tri)).magnitude();
+                        let index = sdf.index(i, j, k);
+                        if d < sdf.distances[index] {
+                            sdf.distances[index] = d;
+                            closest[index] = t;
+                        }
+                    }
+                }
+            }
+            //Where the +x ray from each yz cell centre crosses the triangle, counted in the
+            //first cell past the crossing
+            for k in range(lo.z, hi.z, 0) {
+                for j in range(lo.y, hi.y, 0) {
+                    let p = sdf.cell_center(0, j, k);
+                    if let Some(x) = crossing_x(p.y, p.z, tri) {
+                        let i = to_grid(x).ceil().max(0.0) as usize;
+                        if i < n {
+                            crossings[sdf.index(i, j, k)] += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        for _ in 0..2 {
+            for direction in 0..8 {
+                sdf.sweep(mesh, &mut closest, direction);
+            }
+        }
+
+        for k in 0..n {
+            for j in 0..n {
+                let mut count = 0;
+                for i in 0..n {
+                    let index = sdf.index(i, j, k);
+                    count += crossings[index];
+                    if count % 2 == 1 {
+                        sdf.distances[index] = -sdf.distances[index];
+                    }
+                }
+            }
+        }
+        sdf
+    }
+
+    //Passes the closest triangles on through the grid in one of the eight diagonal directions
+    fn sweep(&mut self, mesh: &TriangleMesh, closest: &mut [usize], direction: usize) {
+        let n = self.resolution as i32;
+        let steps = [
+            if direction & 1 == 0 { 1 } else { -1 },
+            if direction & 2 == 0 { 1 } else { -1 },
+            if direction & 4 == 0 { 1 } else { -1 },
+        ];
+        let span = |step: i32| -> Vec<i32> {
+            if step > 0 {
+                (1..n).collect()
+            } else {
+                (0..n - 1).rev().collect()
+            }
+        };
+        for k in span(steps[2]) {
+            for j in span(steps[1]) {
+                for i in span(steps[0]) {
+                    let index = self.index(i as usize, j as usize, k as usize);
+                    let p = self.cell_center(i as usize, j as usize, k as usize);
+                    for neighbour in 1..8 {
+                        let ni = i - steps[0] * (neighbour & 1);
+                        let nj = j - steps[1] * ((neighbour >> 1) & 1);
+                        let nk = k - steps[2] * ((neighbour >> 2) & 1);
+                        let t = closest[self.index(ni as usize, nj as usize, nk as usize)];
+                        if t == usize::MAX || t == closest[index] {
+                            continue;
+                        }
+                        let d = (p - closest_point_on_triangle(p, mesh.triangle(t))).magnitude();
+                        if d < self.distances[index] {
+                            self.distances[index] = d;
+                            closest[index] = t;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    //Trilinear lookup, like sample_mesh_sdf in sim.wgsl. Outside the grid the distance to
+    //the grid is added to the nearest value inside.
+    #[cfg(test)]
+    pub fn distance(&self, p: Vector3<f32>) -> f32 {
+        let n = self.resolution as usize;
+        let clamped = Vector3::new(
+            p.x.clamp(-1.0, 1.0),
+            p.y.clamp(-1.0, 1.0),
+            p.z.clamp(-1.0, 1.0),
+        );
+        let outside = (p - clamped).magnitude();
+        let g = (clamped + Vector3::new(1.0, 1.0, 1.0)) / self.cell_size()
+            - Vector3::new(0.5, 0.5, 0.5);
+        let base = [g.x.floor(), g.y.floor(), g.z.floor()];
+        let t = [g.x - base[0], g.y - base[1], g.z - base[2]];
+        let mut sum = 0.0;
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut cell = [0; 3];
+            for axis in 0..3 {
+                let offset = (corner >> axis) & 1;
+                cell[axis] = (base[axis] as i32 + offset as i32).clamp(0, n as i32 - 1) as usize;
+                weight *= if offset == 1 { t[axis] } else { 1.0 - t[axis] };
+            }
+            sum += self.distances[self.index(cell[0], cell[1], cell[2])] * weight;
+        }
+        sum + outside
+    }
+}
+
+//x where the line through (y, z) along x crosses the triangle, if it does. Edges count on
+//one side only, so a ray through a shared edge crosses one of the two triangles.
+fn crossing_x(y: f32, z: f32, [a, b, c]: [Vector3<f32>; 3]) -> Option<f32> {
+    let edge = |p: Vector3<f32>, q: Vector3<f32>| (q.y - p.y) * (z - p.z) - (q.z - p.z) * (y - p.y);
+    let (wa, wb, wc) = (edge(b, c), edge(c, a), edge(a, b));
+    let owned = |w: f32, p: Vector3<f32>, q: Vector3<f32>| {
+        w > 0.0 || (w == 0.0 && (q.y > p.y || (q.y == p.y && q.z > p.z)))
+    };
+    let positive = owned(wa, b, c) && owned(wb, c, a) && owned(wc, a, b);
+    let negative = owned(-wa, c, b) && owned(-wb, a, c) && owned(-wc, b, a);
+    if !positive && !negative {
+        return None;
+    }
+    let total = wa + wb + wc;
+    if total == 0.0 {
+        return None;
+    }
+    Some((a.x * wa + b.x * wb + c.x * wc) / total)
+}
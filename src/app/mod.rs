@@ -1,12 +1,41 @@
+pub mod accumulation;
+pub mod boids;
 pub mod camera;
+pub mod clip;
+pub mod colliders;
+pub mod field;
+pub mod field_benchmark;
+pub mod field_view;
+pub mod fluid;
+pub mod forces;
 pub mod gpu;
+pub mod helpers;
 pub mod input;
 pub mod math;
+pub mod mesh_sdf;
+pub mod mouse_force;
+pub mod nbody;
+pub mod panorama;
 pub mod particle_gpu;
 pub mod particle_system;
+pub mod post;
+pub mod scene;
+pub mod sort;
+pub mod spatial_hash;
 pub mod texture;
 pub mod time;
-use std::time::Duration;
+pub mod trails;
+pub mod volume;
+use std::{
+    path::PathBuf,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+
+use cgmath::{EuclideanSpace, InnerSpace, Vector3};
+use rand::{rngs::StdRng, SeedableRng};
 
 use winit::{
     dpi::{PhysicalPosition, PhysicalSize},
@@ -16,16 +45,50 @@ use winit::{
 };
 
 use self::{
-    camera::FatCamera, gpu::Gpu, input::Input, math::UVec2, particle_system::ParticleSystem,
-    texture::Texture, time::Time,
+    boids::BoidSettings,
+    camera::{
+        projection::{Projection, ProjectionMode},
+        FatCamera, ViewPreset,
+    },
+    colliders::{Collider, ColliderOperation, ColliderShape, MAX_COLLIDERS},
+    field::FieldParameters,
+    field_benchmark::FieldBenchmark,
+    fluid::FluidSettings,
+    forces::{Force, ForceKind, MAX_FORCES},
+    gpu::Gpu,
+    input::Input,
+    math::UVec2,
+    mesh_sdf::MeshSdf,
+    mouse_force::{MouseForceSettings, MouseTarget},
+    nbody::NBodySettings,
+    particle_gpu::{QuadMode, SpriteMode},
+    particle_system::{
+        Behaviour, BlendMode, ParticleSystem, TargetSettings, MAX_EXTENT, NUM_PARTICLES,
+    },
+    time::Time,
+    trails::TrailMode,
 };
 
+//Cells along each side of baked mesh colliders
+const MESH_SDF_RESOLUTION: u32 = 64;
+
+//An obj being baked into the collider sdf on a worker thread
+struct MeshBake {
+    path: PathBuf,
+    start: Instant,
+    receiver: mpsc::Receiver<Result<MeshSdf>>,
+}
+
 pub struct App {
     pub time: Time,
     pub particle_system: ParticleSystem,
     size: UVec2,
     pub fat_cam: FatCamera,
     pub input: Input,
+    pub mouse_force: MouseForceSettings,
+    //What NumpadEnter adds
+    pub collider_shape: ColliderShape,
+    mesh_bake: Option<MeshBake>,
     frame: usize,
 }
 
@@ -38,6 +101,7 @@ impl App {
             0.4,
             cgmath::Deg(90.0),
             (0.0, 0.0, 70.0).into(),
+            Projection::clip_planes_for_extent(MAX_EXTENT),
         );
         let particle_system = ParticleSystem::new(&gpu, sim_size, &fat_cam);
         let time = Time::new(Duration::from_secs_f32(1.0));
@@ -49,6 +113,9 @@ impl App {
             size: sim_size,
             fat_cam,
             input,
+            mouse_force: MouseForceSettings::default(),
+            collider_shape: ColliderShape::Sphere,
+            mesh_bake: None,
             frame: 0,
         }
     }
@@ -67,20 +134,1095 @@ impl App {
         self.size.x = new_size.width;
         self.size.y = new_size.height;
         self.fat_cam.projection.resize(self.size.x, self.size.y);
-        self.particle_system.particle_gpu.depth_texture =
-            Texture::create_depth_texture(&gpu.device, &gpu.config, "depth_texture");
+        self.particle_system.resize(gpu, &self.fat_cam, self.size);
     }
 
     pub fn tick(&mut self, gpu: &Gpu) {
         self.input.clear(self.time.render_ticks());
+        self.handle_hotkeys(gpu);
+        self.handle_dropped_files(gpu);
+        self.finish_mesh_bake(gpu);
         self.fat_cam.controller.process_input(&self.input);
         self.fat_cam.update_camera(&gpu);
+        self.update_mouse_force();
         self.particle_system
             .render(&gpu, &self.fat_cam, &mut self.time);
+        self.input.clear_pressed_keys();
         let fps_data = self.time.get_fps();
         match fps_data {
-            Some(fps) => println!("FPS: {}", fps.render_fps),
+            Some(fps) => {
+                println!("FPS: {}", fps.render_fps);
+                if let Some(sort_time) = self.particle_system.last_sort_time {
+                    println!("Sort: {:.3}ms", sort_time.as_secs_f64() * 1000.0);
+                }
+            }
             None => {}
         }
     }
+
+    //Left drag pushes particles around along the ray under the cursor
+    fn update_mouse_force(&mut self) {
+        let forces = &mut self.particle_system.particle_gpu.forces;
+        forces.mouse = if self.input.left_mouse_down {
+            let (origin, direction) = self
+                .fat_cam
+                .cursor_ray(self.input.cursor_position(), self.size);
+            Some(self.mouse_force.force(origin, direction))
+        } else {
+            None
+        };
+    }
+
+    //Uploads the mesh collider once its bake finishes. The first bake adds a mesh collider.
+    fn finish_mesh_bake(&mut self, gpu: &Gpu) {
+        let bake = match self.mesh_bake.take() {
+            Some(bake) => bake,
+            None => return,
+        };
+        let result = match bake.receiver.try_recv() {
+            Ok(result) => result,
+            Err(mpsc::TryRecvError::Empty) => {
+                self.mesh_bake = Some(bake);
+                return;
+            }
+            Err(mpsc::TryRecvError::Disconnected) => Err(anyhow!("the bake thread panicked")),
+        };
+        match result {
+            Ok(mesh) => {
+                println!(
+                    "Baked {} in {:.2}s",
+                    bake.path.display(),
+                    bake.start.elapsed().as_secs_f64()
+                );
+                let particle_gpu = &mut self.particle_system.particle_gpu;
+                particle_gpu.set_collider_mesh(gpu, mesh);
+                let colliders = &mut particle_gpu.colliders.colliders;
+                if !colliders.iter().any(|c| c.shape == ColliderShape::Mesh) {
+                    colliders.push(Collider::new(
+                        ColliderShape::Mesh,
+                        Vector3::new(0.0, 0.0, 0.0),
+                        MAX_EXTENT * 0.3,
+                    ));
+                }
+            }
+            Err(e) => println!("Mesh load failed: {:#}", e),
+        }
+    }
+
+    //Images dropped on the window become the particle sprite, or the background if
+    //their name ends in _equirect. Obj meshes are baked into the mesh collider in the background.
+    fn handle_dropped_files(&mut self, gpu: &Gpu) {
+        for path in self.input.take_dropped_files() {
+            let is_mesh = path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("obj"));
+            if is_mesh {
+                //A bake still running is dropped, its result has nowhere to go
+                let (sender, receiver) = mpsc::channel();
+                let bake_path = path.clone();
+                std::thread::spawn(move || {
+                    sender
+                        .send(MeshSdf::bake_obj(&bake_path, MESH_SDF_RESOLUTION))
+                        .ok();
+                });
+                println!("Baking {}", path.display());
+                self.mesh_bake = Some(MeshBake {
+                    path,
+                    start: Instant::now(),
+                    receiver,
+                });
+                continue;
+            }
+            let is_background = path
+                .file_stem()
+                .is_some_and(|stem| stem.to_string_lossy().ends_with("_equirect"));
+            if is_background {
+                match self.particle_system.helpers.load_background(gpu, &path) {
+                    Ok(()) => println!("Loaded background {}", path.display()),
+                    Err(e) => println!("Background load failed: {:#}", e),
+                }
+                continue;
+            }
+            match self.particle_system.particle_gpu.load_sprite(gpu, &path) {
+                Ok(()) => {
+                    let (columns, rows) = particle_gpu::atlas_grid(&path).unwrap_or((1, 1));
+                    let render = &mut self.particle_system.render_settings;
+                    render.atlas_columns = columns;
+                    render.atlas_rows = rows;
+                    render.sprite_mode = SpriteMode::Texture;
+                    println!(
+                        "Loaded sprite {} ({}x{} frames)",
+                        path.display(),
+                        columns,
+                        rows
+                    );
+                }
+                Err(e) => println!("Sprite load failed: {:#}", e),
+            }
+        }
+    }
+
+    //Ctrl switches most keys over to the colliders, the checks and the running behaviour,
+    //Shift gives the number row, = / - and Back to the force stack
+    fn handle_hotkeys(&mut self, gpu: &Gpu) {
+        let shift = self.input.key_held(VirtualKeyCode::LShift)
+            || self.input.key_held(VirtualKeyCode::RShift);
+        let ctrl = self.input.key_held(VirtualKeyCode::LControl)
+            || self.input.key_held(VirtualKeyCode::RControl);
+        self.handle_view_keys();
+        self.handle_collider_keys(shift, ctrl);
+        self.handle_check_keys(gpu, ctrl);
+        if ctrl {
+            self.handle_behaviour_keys(gpu, shift);
+            return;
+        }
+        self.handle_mouse_force_keys(shift);
+        self.handle_output_keys(gpu);
+        self.handle_volume_keys();
+        self.handle_target_keys(gpu);
+        self.handle_trail_keys();
+        self.handle_render_keys();
+        self.handle_helper_keys();
+        self.handle_field_view_keys();
+        self.handle_field_keys(shift);
+        self.handle_force_keys(shift);
+        if !shift {
+            self.handle_clip_keys();
+            self.handle_post_keys();
+        }
+    }
+
+    // Numpad5 toggles orthographic, Numpad1 / 3 / 7 front, right and top views, Numpad9 views
+    // from the other side, F6 / F5 near plane, F4 / F3 far plane, scroll zooms the
+    // orthographic view, or moves the mouse force's point while dragging
+    fn handle_view_keys(&mut self) {
+        let fat_cam = &mut self.fat_cam;
+        if self.input.key_pressed(VirtualKeyCode::Numpad5) {
+            let focus_distance = fat_cam.camera.position.to_vec().magnitude();
+            let mode = fat_cam.projection.mode.next();
+            fat_cam.projection.set_mode(mode, focus_distance);
+            println!("Projection: {:?}", mode);
+        }
+        let presets = [
+            (VirtualKeyCode::Numpad1, ViewPreset::Front),
+            (VirtualKeyCode::Numpad3, ViewPreset::Right),
+            (VirtualKeyCode::Numpad7, ViewPreset::Top),
+        ];
+        for (key, preset) in presets {
+            if self.input.key_pressed(key) {
+                fat_cam.camera.set_view_preset(preset, MAX_EXTENT * 2.0);
+                println!("View: {:?}", preset);
+            }
+        }
+        if self.input.key_pressed(VirtualKeyCode::Numpad9) {
+            fat_cam.camera.flip_view();
+        }
+        let projection = &mut fat_cam.projection;
+        if self.input.key_pressed(VirtualKeyCode::F6) {
+            projection.znear = (projection.znear * 1.5).min(projection.zfar * 0.99);
+            println!("Near plane: {}", projection.znear);
+        }
+        if self.input.key_pressed(VirtualKeyCode::F5) {
+            projection.znear /= 1.5;
+            println!("Near plane: {}", projection.znear);
+        }
+        if self.input.key_pressed(VirtualKeyCode::F4) {
+            projection.zfar *= 1.5;
+            println!("Far plane: {}", projection.zfar);
+        }
+        if self.input.key_pressed(VirtualKeyCode::F3) {
+            projection.zfar = (projection.zfar / 1.5).max(projection.znear * 1.01);
+            println!("Far plane: {}", projection.zfar);
+        }
+        let scroll = self.input.take_scroll_delta();
+        let mouse_force = &mut self.mouse_force;
+        if scroll != 0.0 && self.input.left_mouse_down && mouse_force.target == MouseTarget::Depth {
+            mouse_force.depth = (mouse_force.depth * 1.1f32.powf(scroll)).max(1.0);
+            println!("Mouse force depth: {}", mouse_force.depth);
+        } else if scroll != 0.0 && projection.mode == ProjectionMode::Orthographic {
+            projection.zoom(scroll);
+        }
+    }
+
+    // left drag applies the mouse force, Numpad* cycles attract, repel and swirl,
+    // Numpad/ switches between the whole ray and a point on it, Numpad+ / Numpad- mouse force
+    // radius, with Shift its strength
+    fn handle_mouse_force_keys(&mut self, shift: bool) {
+        let mouse_force = &mut self.mouse_force;
+        if self.input.key_pressed(VirtualKeyCode::NumpadMultiply) {
+            mouse_force.mode = mouse_force.mode.next();
+            println!("Mouse force: {:?}", mouse_force.mode);
+        }
+        if self.input.key_pressed(VirtualKeyCode::NumpadDivide) {
+            mouse_force.target = mouse_force.target.next();
+            println!("Mouse force target: {:?}", mouse_force.target);
+        }
+        if self.input.key_pressed(VirtualKeyCode::NumpadAdd) {
+            if shift {
+                mouse_force.strength *= 1.25;
+                println!("Mouse force strength: {}", mouse_force.strength);
+            } else {
+                mouse_force.radius *= 1.25;
+                println!("Mouse force radius: {}", mouse_force.radius);
+            }
+        }
+        if self.input.key_pressed(VirtualKeyCode::NumpadSubtract) {
+            if shift {
+                mouse_force.strength /= 1.25;
+                println!("Mouse force strength: {}", mouse_force.strength);
+            } else {
+                mouse_force.radius = (mouse_force.radius / 1.25).max(1.0);
+                println!("Mouse force radius: {}", mouse_force.radius);
+            }
+        }
+    }
+
+    // M cycles render modes, Home / End accumulation decay, C toggles clear on camera move,
+    // F12 exports the accumulation buffer, F10 cycles panorama formats, F11 exports a panorama
+    fn handle_output_keys(&mut self, gpu: &Gpu) {
+        if self.input.key_pressed(VirtualKeyCode::M) {
+            let mode = self.particle_system.render_mode.next();
+            self.particle_system.set_render_mode(mode);
+            println!("Render mode: {:?}", mode);
+        }
+        let accumulation = &mut self.particle_system.accumulation.settings;
+        if self.input.key_pressed(VirtualKeyCode::Home) {
+            accumulation.decay = 1.0 - (1.0 - accumulation.decay) * 0.5;
+            println!("Accumulation decay: {}", accumulation.decay);
+        }
+        if self.input.key_pressed(VirtualKeyCode::End) {
+            accumulation.decay = 1.0 - ((1.0 - accumulation.decay) * 2.0).min(1.0);
+            println!("Accumulation decay: {}", accumulation.decay);
+        }
+        if self.input.key_pressed(VirtualKeyCode::C) {
+            accumulation.clear_on_camera_move = !accumulation.clear_on_camera_move;
+            println!(
+                "Clear on camera move: {}",
+                accumulation.clear_on_camera_move
+            );
+        }
+        if self.input.key_pressed(VirtualKeyCode::F12) {
+            let path = format!(
+                "accumulation_{}.png",
+                chrono::Local::now().format("%Y%m%d_%H%M%S")
+            );
+            match self.particle_system.export_accumulation(gpu, &path) {
+                Ok(()) => println!("Saved {}", path),
+                Err(e) => println!("Export failed: {}", e),
+            }
+        }
+        if self.input.key_pressed(VirtualKeyCode::F10) {
+            let panorama = &mut self.particle_system.panorama.settings;
+            panorama.format = panorama.format.next();
+            println!("Panorama format: {:?}", panorama.format);
+        }
+        if self.input.key_pressed(VirtualKeyCode::F11) {
+            let format = self.particle_system.panorama.settings.format;
+            let path = format!(
+                "{}_{}.png",
+                format!("{:?}", format).to_lowercase(),
+                chrono::Local::now().format("%Y%m%d_%H%M%S")
+            );
+            match self
+                .particle_system
+                .export_panorama(gpu, &self.fat_cam, &self.time, &path)
+            {
+                Ok(()) => println!("Saved {}", path),
+                Err(e) => println!("Export failed: {}", e),
+            }
+        }
+    }
+
+    // G toggles volume speed colouring, K / L volume density, J cycles volume resolution
+    fn handle_volume_keys(&mut self) {
+        let volume = &mut self.particle_system.volume.settings;
+        if self.input.key_pressed(VirtualKeyCode::G) {
+            volume.use_velocity = !volume.use_velocity;
+            println!("Volume speed colouring: {}", volume.use_velocity);
+        }
+        if self.input.key_pressed(VirtualKeyCode::L) {
+            volume.density_scale *= 1.25;
+            println!("Volume density: {}", volume.density_scale);
+        }
+        if self.input.key_pressed(VirtualKeyCode::K) {
+            volume.density_scale /= 1.25;
+            println!("Volume density: {}", volume.density_scale);
+        }
+        if self.input.key_pressed(VirtualKeyCode::J) {
+            volume.resolution = match volume.resolution {
+                r if r < 128 => 128,
+                r if r < 256 => 256,
+                _ => 64,
+            };
+            println!("Volume resolution: {}", volume.resolution);
+        }
+    }
+
+    // F9 cycles MSAA, F8 / F7 render scale
+    fn handle_target_keys(&mut self, gpu: &Gpu) {
+        let mut targets = self.particle_system.target_settings;
+        if self.input.key_pressed(VirtualKeyCode::F9) {
+            let counts = TargetSettings::SAMPLE_COUNTS;
+            let i = counts.iter().position(|&c| c == targets.sample_count);
+            targets.sample_count = counts[i.map_or(0, |i| (i + 1) % counts.len())];
+        }
+        if self.input.key_pressed(VirtualKeyCode::F8) {
+            targets.render_scale = (targets.render_scale * 2.0).min(2.0);
+        }
+        if self.input.key_pressed(VirtualKeyCode::F7) {
+            targets.render_scale = (targets.render_scale * 0.5).max(0.25);
+        }
+        if targets != self.particle_system.target_settings {
+            self.particle_system
+                .set_target_settings(gpu, &self.fat_cam, targets);
+            let (width, height) = targets.scaled_size(gpu);
+            println!(
+                "MSAA: {}x, render scale: {} ({}x{})",
+                targets.sample_count, targets.render_scale, width, height
+            );
+        }
+    }
+
+    // Y toggles trails, U switches ribbons/lines, PageUp / PageDown trail length
+    fn handle_trail_keys(&mut self) {
+        let trails = &mut self.particle_system.particle_gpu.trails.settings;
+        if self.input.key_pressed(VirtualKeyCode::Y) {
+            trails.enabled = !trails.enabled;
+            println!("Trails: {}", trails.enabled);
+        }
+        if self.input.key_pressed(VirtualKeyCode::U) {
+            trails.mode = match trails.mode {
+                TrailMode::Ribbon => TrailMode::Line,
+                TrailMode::Line => TrailMode::Ribbon,
+            };
+            println!("Trail mode: {:?}", trails.mode);
+        }
+        if self.input.key_pressed(VirtualKeyCode::PageUp) {
+            trails.length *= 2;
+            println!("Trail length: {}", trails.length);
+        }
+        if self.input.key_pressed(VirtualKeyCode::PageDown) {
+            trails.length = (trails.length / 2).max(2);
+            println!("Trail length: {}", trails.length);
+        }
+    }
+
+    // B toggles sorted alpha blending, P toggles sort profiling,
+    // V toggles velocity aligned quads, . / , particle size, ' / ; shutter time,
+    // X cycles sprite modes, Z cycles what picks the atlas frame,
+    // O / I soft particle fade distance, N toggles scene geometry
+    fn handle_render_keys(&mut self) {
+        if self.input.key_pressed(VirtualKeyCode::B) {
+            self.particle_system.blend_mode = match self.particle_system.blend_mode {
+                BlendMode::Additive => BlendMode::SortedAlpha,
+                BlendMode::SortedAlpha => BlendMode::Additive,
+            };
+            println!("Blend mode: {:?}", self.particle_system.blend_mode);
+        }
+        if self.input.key_pressed(VirtualKeyCode::P) {
+            self.particle_system.profile_sort = !self.particle_system.profile_sort;
+            println!("Profile sort: {}", self.particle_system.profile_sort);
+        }
+        let render = &mut self.particle_system.render_settings;
+        if self.input.key_pressed(VirtualKeyCode::V) {
+            render.quad_mode = match render.quad_mode {
+                QuadMode::Billboard => QuadMode::VelocityAligned,
+                QuadMode::VelocityAligned => QuadMode::Billboard,
+            };
+            println!("Quad mode: {:?}", render.quad_mode);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Period) {
+            render.particle_size *= 1.25;
+            println!("Particle size: {}", render.particle_size);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Comma) {
+            render.particle_size /= 1.25;
+            println!("Particle size: {}", render.particle_size);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Apostrophe) {
+            render.shutter_time *= 1.25;
+            println!("Shutter time: {}", render.shutter_time);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Semicolon) {
+            render.shutter_time /= 1.25;
+            println!("Shutter time: {}", render.shutter_time);
+        }
+        if self.input.key_pressed(VirtualKeyCode::X) {
+            render.sprite_mode = render.sprite_mode.next();
+            println!("Sprite mode: {:?}", render.sprite_mode);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Z) {
+            render.frame_source = render.frame_source.next();
+            println!("Atlas frame from: {:?}", render.frame_source);
+        }
+        if self.input.key_pressed(VirtualKeyCode::O) {
+            render.soft_fade_distance = (render.soft_fade_distance * 1.5).max(0.25);
+            println!("Soft fade distance: {}", render.soft_fade_distance);
+        }
+        if self.input.key_pressed(VirtualKeyCode::I) {
+            render.soft_fade_distance /= 1.5;
+            if render.soft_fade_distance < 0.25 {
+                render.soft_fade_distance = 0.0;
+            }
+            println!("Soft fade distance: {}", render.soft_fade_distance);
+        }
+        if self.input.key_pressed(VirtualKeyCode::N) {
+            self.particle_system.scene.enabled = !self.particle_system.scene.enabled;
+            println!("Scene geometry: {}", self.particle_system.scene.enabled);
+        }
+    }
+
+    // H toggles the grid, F toggles the origin gizmo, R toggles the bounds,
+    // \ cycles backgrounds
+    fn handle_helper_keys(&mut self) {
+        let helpers = &mut self.particle_system.helpers.settings;
+        if self.input.key_pressed(VirtualKeyCode::H) {
+            helpers.grid = !helpers.grid;
+            println!("Grid: {}", helpers.grid);
+        }
+        if self.input.key_pressed(VirtualKeyCode::F) {
+            helpers.gizmo = !helpers.gizmo;
+            println!("Origin gizmo: {}", helpers.gizmo);
+        }
+        if self.input.key_pressed(VirtualKeyCode::R) {
+            helpers.bounds = !helpers.bounds;
+            println!("Bounds: {}", helpers.bounds);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Backslash) {
+            helpers.background = helpers.background.next();
+            println!("Background: {:?}", helpers.background);
+        }
+    }
+
+    // 1 cycles clip shapes, 2 cycles the clip axis, 3 aligns the clip plane to the view,
+    // 4 / 5 move the clip region, 6 / 7 clip region size, 8 inverts the clip,
+    // 9 switches between hiding and dimming clipped particles, 0 toggles the clip gizmo
+    fn handle_clip_keys(&mut self) {
+        let clip = &mut self.particle_system.render_settings.clip;
+        if self.input.key_pressed(VirtualKeyCode::Key1) {
+            clip.shape = clip.shape.next();
+            println!("Clip shape: {:?}", clip.shape);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Key2) {
+            clip.next_axis();
+            println!("Clip normal: {:?}", clip.normal);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Key3) {
+            clip.normal = self.fat_cam.camera.direction();
+            println!("Clip normal: {:?}", clip.normal);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Key4) {
+            clip.nudge(MAX_EXTENT / 50.0);
+            println!("Clip center: {:?}", clip.center);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Key5) {
+            clip.nudge(-MAX_EXTENT / 50.0);
+            println!("Clip center: {:?}", clip.center);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Key6) {
+            clip.scale(1.25);
+            println!("{}", clip.size_description());
+        }
+        if self.input.key_pressed(VirtualKeyCode::Key7) {
+            clip.scale(1.0 / 1.25);
+            println!("{}", clip.size_description());
+        }
+        if self.input.key_pressed(VirtualKeyCode::Key8) {
+            clip.invert = !clip.invert;
+            println!("Clip inverted: {}", clip.invert);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Key9) {
+            clip.dim = if clip.dim > 0.0 { 0.0 } else { 0.1 };
+            println!("Clipped particle alpha: {}", clip.dim);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Key0) {
+            clip.gizmo = !clip.gizmo;
+            println!("Clip gizmo: {}", clip.gizmo);
+        }
+    }
+
+    // F1 toggles field arrows, F2 toggles streamlines, ` toggles the lic plane,
+    // Tab cycles the field plane, Numpad8 / Numpad2 move the field plane
+    fn handle_field_view_keys(&mut self) {
+        let field_view = &mut self.particle_system.field_view.settings;
+        if self.input.key_pressed(VirtualKeyCode::F1) {
+            field_view.arrows = !field_view.arrows;
+            println!("Field arrows: {}", field_view.arrows);
+        }
+        if self.input.key_pressed(VirtualKeyCode::F2) {
+            field_view.streamlines = !field_view.streamlines;
+            println!("Streamlines: {}", field_view.streamlines);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Grave) {
+            field_view.lic = !field_view.lic;
+            println!("LIC plane: {}", field_view.lic);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Tab) {
+            field_view.plane = field_view.plane.next();
+            println!("Field plane: {:?}", field_view.plane);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Numpad8) {
+            field_view.plane_offset = (field_view.plane_offset + MAX_EXTENT / 50.0).min(MAX_EXTENT);
+            println!("Field plane offset: {}", field_view.plane_offset);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Numpad2) {
+            field_view.plane_offset =
+                (field_view.plane_offset - MAX_EXTENT / 50.0).max(-MAX_EXTENT);
+            println!("Field plane offset: {}", field_view.plane_offset);
+        }
+    }
+
+    // Numpad6 / Numpad4 field evolution speed, Numpad0 starts or freezes the field,
+    // Up / Down noise octaves, Shift + Up / Down octave gain, Right / Left domain warp,
+    // Shift + Right / Left lacunarity, / cycles fractal modes, Delete toggles octave rotation
+    fn handle_field_keys(&mut self, shift: bool) {
+        let field = &mut self.particle_system.field;
+        if self.input.key_pressed(VirtualKeyCode::Numpad6) {
+            field.time_multiplier = (field.time_multiplier * 1.5).max(0.01);
+            println!("Field evolution speed: {}", field.time_multiplier);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Numpad4) {
+            field.time_multiplier /= 1.5;
+            println!("Field evolution speed: {}", field.time_multiplier);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Numpad0) {
+            field.time_multiplier = if field.time_multiplier > 0.0 {
+                0.0
+            } else {
+                FieldParameters::EVOLVING_TIME_MULTIPLIER
+            };
+            println!("Field evolution speed: {}", field.time_multiplier);
+        }
+        let fractal = &mut field.fractal;
+        if self.input.key_pressed(VirtualKeyCode::Up) {
+            if shift {
+                fractal.gain = (fractal.gain + 0.05).min(0.95);
+            } else {
+                fractal.octaves = (fractal.octaves + 1).min(8);
+            }
+            println!("Octaves: {}, gain: {}", fractal.octaves, fractal.gain);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Down) {
+            if shift {
+                fractal.gain = (fractal.gain - 0.05).max(0.05);
+            } else {
+                fractal.octaves = (fractal.octaves - 1).max(1);
+            }
+            println!("Octaves: {}, gain: {}", fractal.octaves, fractal.gain);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Right) {
+            if shift {
+                fractal.lacunarity = (fractal.lacunarity + 0.1).min(4.0);
+            } else {
+                fractal.warp_strength += 0.1;
+            }
+            println!(
+                "Lacunarity: {}, warp: {}",
+                fractal.lacunarity, fractal.warp_strength
+            );
+        }
+        if self.input.key_pressed(VirtualKeyCode::Left) {
+            if shift {
+                fractal.lacunarity = (fractal.lacunarity - 0.1).max(1.1);
+            } else {
+                fractal.warp_strength = (fractal.warp_strength - 0.1).max(0.0);
+            }
+            println!(
+                "Lacunarity: {}, warp: {}",
+                fractal.lacunarity, fractal.warp_strength
+            );
+        }
+        if self.input.key_pressed(VirtualKeyCode::Slash) {
+            fractal.mode = fractal.mode.next();
+            println!("Fractal mode: {:?}", fractal.mode);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Delete) {
+            fractal.rotate_octaves = !fractal.rotate_octaves;
+            println!("Rotate octaves: {}", fractal.rotate_octaves);
+        }
+    }
+
+    // Shift + 1 to 7 add an attractor, line vortex, toroidal vortex, wind, drag, gravity or
+    // turbulence in front of the camera, Shift + 0 cycles where the last force acts between
+    // everywhere, a box and a sphere, forces face the way the camera looks when added and
+    // Shift + = / - stretch or squash the last one along that view direction,
+    // Back removes the last force, Shift + Back all of them,
+    // Shift + 8 / 9 how closely particles follow the flow
+    fn handle_force_keys(&mut self, shift: bool) {
+        let forces = &mut self.particle_system.particle_gpu.forces.forces;
+        if self.input.key_pressed(VirtualKeyCode::Back) {
+            if shift {
+                forces.clear();
+            } else {
+                forces.pop();
+            }
+            println!("Forces: {}", forces.len());
+        }
+        if !shift {
+            return;
+        }
+        let force_keys = [
+            VirtualKeyCode::Key1,
+            VirtualKeyCode::Key2,
+            VirtualKeyCode::Key3,
+            VirtualKeyCode::Key4,
+            VirtualKeyCode::Key5,
+            VirtualKeyCode::Key6,
+            VirtualKeyCode::Key7,
+        ];
+        for (key, kind) in force_keys.into_iter().zip(ForceKind::ALL) {
+            if !self.input.key_pressed(key) {
+                continue;
+            }
+            if forces.len() >= MAX_FORCES {
+                println!("Force stack is full");
+                continue;
+            }
+            let camera = &self.fat_cam.camera;
+            let direction = camera.direction();
+            let position = camera.position.to_vec() + direction * 100.0;
+            let axis = match kind {
+                ForceKind::Gravity => -Vector3::unit_y(),
+                ForceKind::Wind | ForceKind::ToroidalVortex => direction,
+                _ => Vector3::unit_y(),
+            };
+            let mut force = Force::new(kind, position, axis);
+            force.orient(camera.rotation());
+            forces.push(force);
+            println!(
+                "Added {:?} at {:?}, {} forces",
+                kind,
+                position,
+                forces.len()
+            );
+        }
+        if let Some(force) = forces.last_mut() {
+            if self.input.key_pressed(VirtualKeyCode::Key0) {
+                force.region = force.region.next();
+                println!("{:?} region: {:?}", force.kind, force.region);
+            }
+            if self.input.key_pressed(VirtualKeyCode::Equals) {
+                force.scale.z *= 1.25;
+                println!("{:?} scale: {:?}", force.kind, force.scale);
+            }
+            if self.input.key_pressed(VirtualKeyCode::Minus) {
+                force.scale.z /= 1.25;
+                println!("{:?} scale: {:?}", force.kind, force.scale);
+            }
+        }
+        let field = &mut self.particle_system.field;
+        if self.input.key_pressed(VirtualKeyCode::Key8) {
+            field.field_response =
+                (field.field_response * 2.0).min(FieldParameters::EXACT_RESPONSE);
+            println!("Field response: {}", field.field_response);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Key9) {
+            field.field_response = (field.field_response / 2.0).max(0.1);
+            println!("Field response: {}", field.field_response);
+        }
+    }
+
+    // NumpadEnter adds a collider in front of the camera, Shift + NumpadEnter one that
+    // subtracts, NumpadDecimal cycles collider shapes, Shift + NumpadDecimal cycles bounce,
+    // Ctrl + Back removes the last collider, Ctrl + Shift + Back all of them,
+    // Ctrl + Numpad+ / Numpad- how far from the colliders the flow bends around them
+    fn handle_collider_keys(&mut self, shift: bool, ctrl: bool) {
+        let colliders = &mut self.particle_system.particle_gpu.colliders;
+        if ctrl {
+            if self.input.key_pressed(VirtualKeyCode::Back) {
+                if shift {
+                    colliders.colliders.clear();
+                } else {
+                    colliders.colliders.pop();
+                }
+                println!("Colliders: {}", colliders.colliders.len());
+            }
+            let field = &mut self.particle_system.field;
+            if self.input.key_pressed(VirtualKeyCode::NumpadAdd) {
+                field.boundary_width += 5.0;
+                println!("Field boundary width: {}", field.boundary_width);
+            }
+            if self.input.key_pressed(VirtualKeyCode::NumpadSubtract) {
+                field.boundary_width = (field.boundary_width - 5.0).max(0.0);
+                println!("Field boundary width: {}", field.boundary_width);
+            }
+            return;
+        }
+        if self.input.key_pressed(VirtualKeyCode::NumpadDecimal) {
+            if shift {
+                let restitution = &mut colliders.settings.restitution;
+                *restitution = if *restitution >= 1.0 {
+                    0.0
+                } else {
+                    *restitution + 0.5
+                };
+                println!("Collider restitution: {}", restitution);
+            } else {
+                self.collider_shape = self.collider_shape.next();
+                println!("Collider shape: {:?}", self.collider_shape);
+            }
+        }
+        if self.input.key_pressed(VirtualKeyCode::NumpadEnter) {
+            if self.collider_shape == ColliderShape::Mesh && colliders.mesh.is_none() {
+                println!("Drop an obj file on the window to bake a mesh first");
+            } else if colliders.colliders.len() >= MAX_COLLIDERS {
+                println!("Collider list is full");
+            } else {
+                let camera = &self.fat_cam.camera;
+                let direction = camera.direction();
+                let position = camera.position.to_vec() + direction * 100.0;
+                let mut collider = Collider::new(self.collider_shape, position, 30.0);
+                if collider.shape == ColliderShape::Plane {
+                    collider.axis = -direction;
+                }
+                if shift {
+                    collider.operation = ColliderOperation::Subtract;
+                }
+                colliders.colliders.push(collider);
+                println!(
+                    "Added {:?} {:?} collider, {} colliders",
+                    collider.operation,
+                    collider.shape,
+                    colliders.colliders.len()
+                );
+            }
+        }
+    }
+
+    // Insert benchmarks the curl and checks the field for divergence and the flow around the
+    // colliders, with n-body it also checks the energy on the cpu, Ctrl + Insert checks the
+    // spatial hash cells and neighbours against the cpu
+    fn handle_check_keys(&mut self, gpu: &Gpu, ctrl: bool) {
+        if !self.input.key_pressed(VirtualKeyCode::Insert) {
+            return;
+        }
+        let particle_gpu = &self.particle_system.particle_gpu;
+        if ctrl {
+            match particle_gpu.spatial_hash.validate(
+                gpu,
+                &particle_gpu.particle_buffers,
+                0,
+                1000,
+                &mut StdRng::from_entropy(),
+            ) {
+                Ok(report) => println!(
+                    "Spatial hash, {} cells off, {} queries: {} mismatched, {} missing, {} extra, {} too crowded to list, {:.1} neighbours on average",
+                    report.bad_cells,
+                    report.queries,
+                    report.mismatched,
+                    report.missing,
+                    report.extra,
+                    report.truncated,
+                    report.mean_neighbours
+                ),
+                Err(e) => println!("Spatial hash check failed: {:#}", e),
+            }
+            return;
+        }
+        let report = FieldBenchmark::new(
+            gpu,
+            &particle_gpu.params_bind_group_layout,
+            NUM_PARTICLES as u32,
+        )
+        .run(gpu, &particle_gpu.params_bind_group, 20);
+        println!(
+            "Curl, {} samples x {}: analytic {:.3}ms ({:.1}M/s), finite difference {:.3}ms ({:.1}M/s), {:.2}x faster",
+            report.samples,
+            report.iterations,
+            report.analytic.total.as_secs_f64() * 1000.0,
+            report.analytic.throughput / 1e6,
+            report.finite_difference.total.as_secs_f64() * 1000.0,
+            report.finite_difference.throughput / 1e6,
+            report.speedup()
+        );
+        let divergence = self.particle_system.field.check_divergence(
+            &mut StdRng::from_entropy(),
+            10000,
+            MAX_EXTENT,
+        );
+        println!(
+            "Divergence: median {:.2e} relative, max {:.2e} relative, max {:.2e} absolute",
+            divergence.median_relative, divergence.max_relative, divergence.max_absolute
+        );
+        let colliders = &particle_gpu.colliders.colliders;
+        if !colliders.is_empty() {
+            let boundary = self.particle_system.field.check_boundary(
+                &mut StdRng::from_entropy(),
+                colliders,
+                1000,
+                MAX_EXTENT,
+            );
+            println!(
+                "Boundary, {} surface points: normal flow median {:.2e}, max {:.2e} of the tangential, divergence median {:.2e} relative",
+                boundary.samples,
+                boundary.median_normal,
+                boundary.max_normal,
+                boundary.median_divergence
+            );
+        }
+        if self.particle_system.behaviour == Behaviour::NBody {
+            let energy =
+                particle_gpu
+                    .nbody
+                    .settings
+                    .check_energy(&mut StdRng::from_entropy(), 256, 200);
+            println!(
+                "N-body, {} bodies over {} steps: energy drift max {:.2e}, final {:.2e} relative, Barnes-Hut with {} nodes off by median {:.2e}, max {:.2e}",
+                energy.bodies,
+                energy.steps,
+                energy.max_drift,
+                energy.final_drift,
+                energy.tree_nodes,
+                energy.tree_median_error,
+                energy.tree_max_error
+            );
+        }
+    }
+
+    // Ctrl + B cycles the field, boids, fluid and n-body behaviours, the rest of the Ctrl keys
+    // tune the running one, see BOID_KEYS, FLUID_KEYS and NBODY_KEYS. With n-body Ctrl + [
+    // also resets to a galaxy.
+    fn handle_behaviour_keys(&mut self, gpu: &Gpu, shift: bool) {
+        if self.input.key_pressed(VirtualKeyCode::B) {
+            self.particle_system.behaviour = self.particle_system.behaviour.next();
+            println!("Behaviour: {:?}", self.particle_system.behaviour);
+        }
+        let particle_gpu = &mut self.particle_system.particle_gpu;
+        match self.particle_system.behaviour {
+            Behaviour::Field => {}
+            Behaviour::Boids => tune(
+                &self.input,
+                &BOID_KEYS,
+                &mut particle_gpu.boids.settings,
+                shift,
+            ),
+            Behaviour::Fluid => tune(
+                &self.input,
+                &FLUID_KEYS,
+                &mut particle_gpu.fluid.settings,
+                shift,
+            ),
+            Behaviour::NBody => {
+                tune(
+                    &self.input,
+                    &NBODY_KEYS,
+                    &mut particle_gpu.nbody.settings,
+                    shift,
+                );
+                if !shift && self.input.key_pressed(VirtualKeyCode::LBracket) {
+                    self.particle_system.reset_galaxy(gpu);
+                    println!("Reset to a galaxy");
+                }
+            }
+        }
+    }
+
+    // = / - exposure, ] / [ bloom intensity, T cycles tone mapping
+    fn handle_post_keys(&mut self) {
+        let post = &mut self.particle_system.post.settings;
+        if self.input.key_pressed(VirtualKeyCode::Equals) {
+            post.exposure *= 1.25;
+            println!("Exposure: {}", post.exposure);
+        }
+        if self.input.key_pressed(VirtualKeyCode::Minus) {
+            post.exposure /= 1.25;
+            println!("Exposure: {}", post.exposure);
+        }
+        if self.input.key_pressed(VirtualKeyCode::RBracket) {
+            post.bloom_intensity += 0.05;
+            println!("Bloom intensity: {}", post.bloom_intensity);
+        }
+        if self.input.key_pressed(VirtualKeyCode::LBracket) {
+            post.bloom_intensity = (post.bloom_intensity - 0.05).max(0.0);
+            println!("Bloom intensity: {}", post.bloom_intensity);
+        }
+        if self.input.key_pressed(VirtualKeyCode::T) {
+            post.tone_mapping = post.tone_mapping.next();
+            println!("Tone mapping: {:?}", post.tone_mapping);
+        }
+    }
+}
+
+//A Ctrl key that tunes a setting of the running behaviour. Rows with Some(shift) only fire
+//with Shift held or not, rows with None fire either way and are told whether it's held.
+type TuningKey<S> = (VirtualKeyCode, Option<bool>, fn(&mut S, bool));
+
+//Shift turns the settings J / K / L scale down instead of up
+fn tuning_step(shift: bool) -> f32 {
+    if shift {
+        1.0 / 1.25
+    } else {
+        1.25
+    }
 }
+
+fn tune<S>(input: &Input, keys: &[TuningKey<S>], settings: &mut S, shift: bool) {
+    for &(key, with_shift, action) in keys {
+        if with_shift != Some(!shift) && input.key_pressed(key) {
+            action(settings, shift);
+        }
+    }
+}
+
+// Ctrl + J / K / L raise separation, alignment and cohesion, with Shift lower them,
+// Ctrl + O / I perception radius, Ctrl + . / , max speed, with Shift max force,
+// Ctrl + ] / [ how much the boids follow the field
+const BOID_KEYS: [TuningKey<BoidSettings>; 11] = [
+    (VirtualKeyCode::J, None, |boids, shift| {
+        boids.separation *= tuning_step(shift);
+        println!("Boid separation: {}", boids.separation);
+    }),
+    (VirtualKeyCode::K, None, |boids, shift| {
+        boids.alignment *= tuning_step(shift);
+        println!("Boid alignment: {}", boids.alignment);
+    }),
+    (VirtualKeyCode::L, None, |boids, shift| {
+        boids.cohesion *= tuning_step(shift);
+        println!("Boid cohesion: {}", boids.cohesion);
+    }),
+    (VirtualKeyCode::O, None, |boids, _| {
+        boids.perception_radius *= 1.25;
+        println!("Boid perception radius: {}", boids.perception_radius);
+    }),
+    (VirtualKeyCode::I, None, |boids, _| {
+        boids.perception_radius = (boids.perception_radius / 1.25).max(0.5);
+        println!("Boid perception radius: {}", boids.perception_radius);
+    }),
+    (VirtualKeyCode::Period, Some(false), |boids, _| {
+        boids.max_speed *= 1.25;
+        println!("Boid max speed: {}", boids.max_speed);
+    }),
+    (VirtualKeyCode::Comma, Some(false), |boids, _| {
+        boids.max_speed /= 1.25;
+        println!("Boid max speed: {}", boids.max_speed);
+    }),
+    (VirtualKeyCode::Period, Some(true), |boids, _| {
+        boids.max_force *= 1.25;
+        println!("Boid max force: {}", boids.max_force);
+    }),
+    (VirtualKeyCode::Comma, Some(true), |boids, _| {
+        boids.max_force /= 1.25;
+        println!("Boid max force: {}", boids.max_force);
+    }),
+    (VirtualKeyCode::RBracket, None, |boids, _| {
+        boids.field_mix = (boids.field_mix + 0.1).min(1.0);
+        println!("Boid field mix: {:.1}", boids.field_mix);
+    }),
+    (VirtualKeyCode::LBracket, None, |boids, _| {
+        boids.field_mix = (boids.field_mix - 0.1).max(0.0);
+        println!("Boid field mix: {:.1}", boids.field_mix);
+    }),
+];
+
+// Ctrl + J / K / L raise stiffness, viscosity and surface tension, with Shift lower them,
+// Ctrl + O / I smoothing radius, Ctrl + . / , rest density, with Shift the container size,
+// Ctrl + ] / [ gravity
+const FLUID_KEYS: [TuningKey<FluidSettings>; 11] = [
+    (VirtualKeyCode::J, None, |fluid, shift| {
+        fluid.stiffness *= tuning_step(shift);
+        println!(
+            "Fluid stiffness: {}, time step {:.4}",
+            fluid.stiffness,
+            fluid.stable_time_step()
+        );
+    }),
+    (VirtualKeyCode::K, None, |fluid, shift| {
+        fluid.viscosity *= tuning_step(shift);
+        println!(
+            "Fluid viscosity: {}, time step {:.4}",
+            fluid.viscosity,
+            fluid.stable_time_step()
+        );
+    }),
+    (VirtualKeyCode::L, None, |fluid, shift| {
+        fluid.surface_tension *= tuning_step(shift);
+        println!("Fluid surface tension: {}", fluid.surface_tension);
+    }),
+    (VirtualKeyCode::O, None, |fluid, _| {
+        fluid.smoothing_radius *= 1.25;
+        println!("Fluid smoothing radius: {}", fluid.smoothing_radius);
+    }),
+    (VirtualKeyCode::I, None, |fluid, _| {
+        fluid.smoothing_radius = (fluid.smoothing_radius / 1.25).max(0.5);
+        println!("Fluid smoothing radius: {}", fluid.smoothing_radius);
+    }),
+    (VirtualKeyCode::Period, Some(false), |fluid, _| {
+        fluid.rest_density *= 1.25;
+        println!("Fluid rest density: {}", fluid.rest_density);
+    }),
+    (VirtualKeyCode::Comma, Some(false), |fluid, _| {
+        fluid.rest_density /= 1.25;
+        println!("Fluid rest density: {}", fluid.rest_density);
+    }),
+    (VirtualKeyCode::Period, Some(true), |fluid, _| {
+        fluid.container = (fluid.container * 1.25).map(|x| x.min(MAX_EXTENT));
+        println!("Fluid container: {:?}", fluid.container);
+    }),
+    (VirtualKeyCode::Comma, Some(true), |fluid, _| {
+        fluid.container /= 1.25;
+        println!("Fluid container: {:?}", fluid.container);
+    }),
+    (VirtualKeyCode::RBracket, None, |fluid, _| {
+        fluid.gravity *= 1.25;
+        println!("Fluid gravity: {:?}", fluid.gravity);
+    }),
+    (VirtualKeyCode::LBracket, None, |fluid, _| {
+        fluid.gravity /= 1.25;
+        println!("Fluid gravity: {:?}", fluid.gravity);
+    }),
+];
+
+// Ctrl + J / K / L raise G, softening and body mass, with Shift lower them,
+// Ctrl + O / I double or halve the bodies, Ctrl + . / , mass spread, with Shift the
+// Barnes-Hut opening angle, Ctrl + ] switches direct and Barnes-Hut,
+// Ctrl + Shift + ] / [ time step
+const NBODY_KEYS: [TuningKey<NBodySettings>; 12] = [
+    (VirtualKeyCode::J, None, |nbody, shift| {
+        nbody.gravitational_constant *= tuning_step(shift);
+        println!("Gravitational constant: {}", nbody.gravitational_constant);
+    }),
+    (VirtualKeyCode::K, None, |nbody, shift| {
+        nbody.softening *= tuning_step(shift);
+        println!("Softening length: {}", nbody.softening);
+    }),
+    (VirtualKeyCode::L, None, |nbody, shift| {
+        nbody.particle_mass *= tuning_step(shift);
+        println!("Body mass: {}", nbody.particle_mass);
+    }),
+    (VirtualKeyCode::O, None, |nbody, _| {
+        nbody.body_count = (nbody.body_count * 2).min(NUM_PARTICLES as u32);
+        println!("Bodies: {}", nbody.body_count);
+    }),
+    (VirtualKeyCode::I, None, |nbody, _| {
+        nbody.body_count = (nbody.body_count / 2).max(64);
+        println!("Bodies: {}", nbody.body_count);
+    }),
+    (VirtualKeyCode::Period, Some(false), |nbody, _| {
+        nbody.mass_spread += 0.5;
+        println!("Body mass spread: {}", nbody.mass_spread);
+    }),
+    (VirtualKeyCode::Comma, Some(false), |nbody, _| {
+        nbody.mass_spread = (nbody.mass_spread - 0.5).max(0.0);
+        println!("Body mass spread: {}", nbody.mass_spread);
+    }),
+    (VirtualKeyCode::Period, Some(true), |nbody, _| {
+        nbody.theta = (nbody.theta + 0.1).min(1.5);
+        println!("Barnes-Hut opening angle: {:.1}", nbody.theta);
+    }),
+    (VirtualKeyCode::Comma, Some(true), |nbody, _| {
+        nbody.theta = (nbody.theta - 0.1).max(0.1);
+        println!("Barnes-Hut opening angle: {:.1}", nbody.theta);
+    }),
+    (VirtualKeyCode::RBracket, Some(false), |nbody, _| {
+        nbody.solver = nbody.solver.next();
+        println!("N-body solver: {:?}", nbody.solver);
+    }),
+    (VirtualKeyCode::RBracket, Some(true), |nbody, _| {
+        nbody.time_step *= 1.25;
+        println!("N-body time step: {:.4}", nbody.time_step);
+    }),
+    (VirtualKeyCode::LBracket, Some(true), |nbody, _| {
+        nbody.time_step /= 1.25;
+        println!("N-body time step: {:.4}", nbody.time_step);
+    }),
+];
@@ -0,0 +1,97 @@
+//Temporary force under the cursor while the left mouse button is held, for pushing the
+//particles around by hand.
+
+use cgmath::{EuclideanSpace, Point3, Vector3};
+
+use super::forces::{Force, ForceKind, ForceRegion};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MouseForceMode {
+    Attract,
+    Repel,
+    Swirl,
+}
+
+impl MouseForceMode {
+    pub fn next(self) -> MouseForceMode {
+        match self {
+            MouseForceMode::Attract => MouseForceMode::Repel,
+            MouseForceMode::Repel => MouseForceMode::Swirl,
+            MouseForceMode::Swirl => MouseForceMode::Attract,
+        }
+    }
+}
+
+//Where along the cursor ray the force acts
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MouseTarget {
+    //The whole ray, particles are pulled towards or spun around it
+    Ray,
+    //A point depth away from the camera on the ray
+    Depth,
+}
+
+impl MouseTarget {
+    pub fn next(self) -> MouseTarget {
+        match self {
+            MouseTarget::Ray => MouseTarget::Depth,
+            MouseTarget::Depth => MouseTarget::Ray,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct MouseForceSettings {
+    pub mode: MouseForceMode,
+    pub target: MouseTarget,
+    //Distance the force has halved at
+    pub radius: f32,
+    pub strength: f32,
+    pub depth: f32,
+}
+
+impl Default for MouseForceSettings {
+    fn default() -> Self {
+        MouseForceSettings {
+            mode: MouseForceMode::Attract,
+            target: MouseTarget::Ray,
+            radius: 30.0,
+            strength: 400.0,
+            depth: 100.0,
+        }
+    }
+}
+
+impl MouseForceSettings {
+    //The force for a cursor ray from origin along the normalized direction
+    pub fn force(&self, origin: Point3<f32>, direction: Vector3<f32>) -> Force {
+        let (kind, position) = match self.target {
+            MouseTarget::Ray => {
+                let kind = match self.mode {
+                    MouseForceMode::Swirl => ForceKind::LineVortex,
+                    _ => ForceKind::RayAttractor,
+                };
+                (kind, origin.to_vec())
+            }
+            MouseTarget::Depth => {
+                let kind = match self.mode {
+                    MouseForceMode::Swirl => ForceKind::LineVortex,
+                    _ => ForceKind::Attractor,
+                };
+                (kind, origin.to_vec() + direction * self.depth)
+            }
+        };
+        let mut force = Force::new(kind, position, direction);
+        force.strength = match self.mode {
+            MouseForceMode::Repel => -self.strength,
+            _ => self.strength,
+        };
+        force.radius = self.radius;
+        //A swirl at a depth would spin the whole line through it otherwise
+        if self.target == MouseTarget::Depth && self.mode == MouseForceMode::Swirl {
+            force.region = ForceRegion::Sphere;
+            force.region_size = Vector3::new(self.radius * 3.0, 0.0, 0.0);
+        }
+        force
+    }
+}
@@ -0,0 +1,695 @@
+//Self gravity for Behaviour::NBody. The first body_count particles attract everything, the
+//rest are massless tracers that only get pulled along. The gpu either sums every body
+//directly, a tile of bodies at a time in workgroup memory, or walks a Barnes-Hut octree
+//built here from a read back of the bodies, see nbody.wgsl.
+
+use std::sync::mpsc;
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use cgmath::{InnerSpace, Vector3, Zero};
+use rand::Rng;
+
+use super::{gpu::Gpu, particle_gpu::Particle};
+
+//Bodies per octree leaf, and the depth coincident bodies stop splitting at
+const LEAF_SIZE: usize = 16;
+const MAX_TREE_DEPTH: u32 = 24;
+//Scale length of the discs galaxy_disc lays out
+pub const GALAXY_SCALE_LENGTH: f32 = 30.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NBodySolver {
+    //Every particle sums the pull of every body, O(particles * bodies)
+    Tiled,
+    //Far away groups of bodies pull as one
+    BarnesHut,
+}
+
+impl NBodySolver {
+    pub fn next(self) -> NBodySolver {
+        match self {
+            NBodySolver::Tiled => NBodySolver::BarnesHut,
+            NBodySolver::BarnesHut => NBodySolver::Tiled,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct NBodySettings {
+    pub solver: NBodySolver,
+    pub gravitational_constant: f32,
+    //Plummer softening length, keeps close encounters from flinging bodies away
+    pub softening: f32,
+    pub particle_mass: f32,
+    //Per body masses spread log uniformly from particle_mass / e^mass_spread to
+    //particle_mass * e^mass_spread, 0 gives every body the same mass
+    pub mass_spread: f32,
+    //Particles that attract, clamped to the particle count. Every particle is pulled by every
+    //body, so the tiled solver does particles * body_count interactions a step: a million
+    //particles against 1024 bodies is already around 1e9.
+    pub body_count: u32,
+    //Barnes-Hut opening angle, nodes smaller than theta times their distance pull as one
+    pub theta: f32,
+    //Frames between Barnes-Hut tree builds, each reads the bodies back and builds on the cpu.
+    //In between the tree lags behind the bodies, see NBody::update_tree.
+    pub tree_interval: u32,
+    pub time_step: f32,
+}
+
+impl Default for NBodySettings {
+    fn default() -> Self {
+        NBodySettings {
+            solver: NBodySolver::Tiled,
+            gravitational_constant: 1.0,
+            softening: 2.0,
+            particle_mass: 32.0,
+            mass_spread: 0.0,
+            body_count: 1024,
+            theta: 0.7,
+            tree_interval: 30,
+            time_step: 0.016,
+        }
+    }
+}
+
+//Same hash as body_hash in nbody.wgsl, so the cpu and gpu agree on the masses
+fn body_hash(x: u32) -> u32 {
+    let state = x.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+impl NBodySettings {
+    pub fn body_mass(&self, index: u32) -> f32 {
+        if self.mass_spread == 0.0 {
+            return self.particle_mass;
+        }
+        let u = body_hash(index) as f32 / 4294967296.0;
+        self.particle_mass * (self.mass_spread * (2.0 * u - 1.0)).exp()
+    }
+
+    //Softened pull of a body of mass at d away, without the gravitational constant
+    pub fn pull(&self, d: Vector3<f32>, mass: f32) -> Vector3<f32> {
+        let r2 = d.magnitude2() + self.softening * self.softening;
+        d * (mass / (r2 * r2 * r2).sqrt())
+    }
+
+    //Acceleration at p from every body, as nbody_tiled sums it
+    pub fn direct_acceleration(
+        &self,
+        p: Vector3<f32>,
+        positions: &[Vector3<f32>],
+        masses: &[f32],
+    ) -> Vector3<f32> {
+        positions
+            .iter()
+            .zip(masses)
+            .map(|(&q, &mass)| self.pull(q - p, mass))
+            .sum::<Vector3<f32>>()
+            * self.gravitational_constant
+    }
+
+    //Kinetic plus softened potential energy
+    pub fn total_energy(
+        &self,
+        positions: &[Vector3<f32>],
+        velocities: &[Vector3<f32>],
+        masses: &[f32],
+    ) -> f64 {
+        let g = self.gravitational_constant as f64;
+        let epsilon2 = (self.softening * self.softening) as f64;
+        let mut energy = 0.0;
+        for i in 0..positions.len() {
+            energy += 0.5 * masses[i] as f64 * velocities[i].magnitude2() as f64;
+            for j in i + 1..positions.len() {
+                let r2 = (positions[j] - positions[i]).magnitude2() as f64;
+                energy -= g * masses[i] as f64 * masses[j] as f64 / (r2 + epsilon2).sqrt();
+            }
+        }
+        energy
+    }
+
+    //Exponential disc in the xy plane with scale_length, on circular orbits around the
+    //enclosed part of total_mass. Cold, so it soon breaks into spiral arms.
+    pub fn galaxy_disc(
+        &self,
+        rng: &mut impl Rng,
+        count: usize,
+        total_mass: f32,
+        scale_length: f32,
+    ) -> Vec<(Vector3<f32>, Vector3<f32>)> {
+        (0..count)
+            .map(|_| {
+                //Surface density falling off as e^(-r / scale_length) puts r * e^(-r / scale_length)
+                //of the bodies at each radius, a gamma distribution
+                let u: f32 = rng.gen_range(1e-6..1.0);
+                let v: f32 = rng.gen_range(1e-6..1.0);
+                let r = (-scale_length * (u * v).ln()).min(scale_length * 5.0);
+                let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+                let height = rng.gen_range(-0.5..0.5) * scale_length * 0.1;
+                let x = r / scale_length;
+                let enclosed = total_mass * (1.0 - (1.0 + x) * (-x).exp());
+                let r2 = r * r + self.softening * self.softening;
+                let speed =
+                    (self.gravitational_constant * enclosed * r * r / (r2 * r2 * r2).sqrt()).sqrt();
+                let (sin, cos) = angle.sin_cos();
+                (
+                    Vector3::new(cos * r, sin * r, height),
+                    Vector3::new(-sin * speed, cos * speed, 0.0),
+                )
+            })
+            .collect()
+    }
+
+    //Runs bodies through the gpu's integrator and direct summation on the cpu for steps and
+    //tracks how far the energy wanders. Also compares Barnes-Hut accelerations with theta
+    //against direct summation on the starting disc.
+    pub fn check_energy(&self, rng: &mut impl Rng, bodies: usize, steps: usize) -> EnergyReport {
+        let bodies = bodies.max(2);
+        //The same total mass as the gpu's bodies, so the dynamics match
+        let mass_scale = self.body_count.max(1) as f32 / bodies as f32;
+        let masses: Vec<f32> = (0..bodies as u32)
+            .map(|i| self.body_mass(i) * mass_scale)
+            .collect();
+        let disc = self.galaxy_disc(rng, bodies, masses.iter().sum(), GALAXY_SCALE_LENGTH);
+        let mut positions: Vec<Vector3<f32>> = disc.iter().map(|&(p, _)| p).collect();
+        let mut velocities: Vec<Vector3<f32>> = disc.iter().map(|&(_, v)| v).collect();
+
+        let tree = Octree::build(&positions, &masses);
+        let mut errors: Vec<f32> = positions
+            .iter()
+            .map(|&p| {
+                let exact = self.direct_acceleration(p, &positions, &masses);
+                let approximate = tree.acceleration(self, p, &positions, &masses);
+                (approximate - exact).magnitude() / exact.magnitude().max(1e-12)
+            })
+            .collect();
+        errors.sort_by(f32::total_cmp);
+
+        let initial_energy = self.total_energy(&positions, &velocities, &masses);
+        let mut max_drift = 0.0f64;
+        let mut drift = 0.0;
+        for _ in 0..steps {
+            //Kick then drift, like nbody_advance
+            let accelerations: Vec<Vector3<f32>> = positions
+                .iter()
+                .map(|&p| self.direct_acceleration(p, &positions, &masses))
+                .collect();
+            for i in 0..bodies {
+                velocities[i] += accelerations[i] * self.time_step;
+                positions[i] += velocities[i] * self.time_step;
+            }
+            let energy = self.total_energy(&positions, &velocities, &masses);
+            drift = ((energy - initial_energy) / initial_energy.abs().max(1e-12)).abs();
+            max_drift = max_drift.max(drift);
+        }
+        EnergyReport {
+            bodies,
+            steps,
+            max_drift,
+            final_drift: drift,
+            tree_nodes: tree.nodes.len(),
+            tree_median_error: errors[errors.len() / 2],
+            tree_max_error: errors[errors.len() - 1],
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct EnergyReport {
+    pub bodies: usize,
+    pub steps: usize,
+    //Largest and last energy change relative to the initial energy
+    pub max_drift: f64,
+    pub final_drift: f64,
+    pub tree_nodes: usize,
+    //Barnes-Hut acceleration errors relative to direct summation
+    pub tree_median_error: f32,
+    pub tree_max_error: f32,
+}
+
+//Octree node as the gpu reads it. Nodes are stored depth first, so an internal node's
+//first child follows it and next skips past all of its children.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct TreeNode {
+    //Mass in w
+    pub center_of_mass: [f32; 4],
+    //Side length in w
+    pub center: [f32; 4],
+    pub next: u32,
+    //Leaves' bodies, count is 0 for internal nodes
+    pub first: u32,
+    pub count: u32,
+    _padding: u32,
+}
+
+pub struct Octree {
+    pub nodes: Vec<TreeNode>,
+    //Body indices, each leaf's together
+    pub indices: Vec<u32>,
+}
+
+impl Octree {
+    pub fn build(positions: &[Vector3<f32>], masses: &[f32]) -> Octree {
+        let mut tree = Octree {
+            nodes: Vec::new(),
+            indices: Vec::with_capacity(positions.len()),
+        };
+        if positions.is_empty() {
+            return tree;
+        }
+        let mut min = positions[0];
+        let mut max = positions[0];
+        for p in positions {
+            min = Vector3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Vector3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+        let extent = max - min;
+        let size = extent.x.max(extent.y).max(extent.z) * 1.001 + 1e-3;
+        let mut bodies: Vec<u32> = (0..positions.len() as u32).collect();
+        tree.build_node(positions, masses, &mut bodies, (min + max) * 0.5, size, 0);
+        tree
+    }
+
+    fn build_node(
+        &mut self,
+        positions: &[Vector3<f32>],
+        masses: &[f32],
+        bodies: &mut [u32],
+        center: Vector3<f32>,
+        size: f32,
+        depth: u32,
+    ) {
+        let node_index = self.nodes.len();
+        let mut mass = 0.0;
+        let mut weighted = Vector3::zero();
+        for &i in bodies.iter() {
+            mass += masses[i as usize];
+            weighted += positions[i as usize] * masses[i as usize];
+        }
+        let center_of_mass = if mass > 0.0 { weighted / mass } else { center };
+        self.nodes.push(TreeNode {
+            center_of_mass: center_of_mass.extend(mass).into(),
+            center: center.extend(size).into(),
+            next: 0,
+            first: 0,
+            count: 0,
+            _padding: 0,
+        });
+
+        if bodies.len() <= LEAF_SIZE || depth >= MAX_TREE_DEPTH {
+            self.nodes[node_index].first = self.indices.len() as u32;
+            self.nodes[node_index].count = bodies.len() as u32;
+            self.indices.extend_from_slice(bodies);
+        } else {
+            let octant = |i: &u32| {
+                let p = positions[*i as usize];
+                (p.x >= center.x) as usize
+                    | ((p.y >= center.y) as usize) << 1
+                    | ((p.z >= center.z) as usize) << 2
+            };
+            bodies.sort_unstable_by_key(octant);
+            let mut start = 0;
+            for child in 0..8 {
+                let end = start
+                    + bodies[start..]
+                        .iter()
+                        .take_while(|i| octant(i) == child)
+                        .count();
+                if end > start {
+                    let offset = Vector3::new(
+                        if child & 1 != 0 { 0.25 } else { -0.25 },
+                        if child & 2 != 0 { 0.25 } else { -0.25 },
+                        if child & 4 != 0 { 0.25 } else { -0.25 },
+                    ) * size;
+                    self.build_node(
+                        positions,
+                        masses,
+                        &mut bodies[start..end],
+                        center + offset,
+                        size * 0.5,
+                        depth + 1,
+                    );
+                }
+                start = end;
+            }
+        }
+        self.nodes[node_index].next = self.nodes.len() as u32;
+    }
+
+    //Acceleration at p, walked like nbody_tree walks it
+    pub fn acceleration(
+        &self,
+        settings: &NBodySettings,
+        p: Vector3<f32>,
+        positions: &[Vector3<f32>],
+        masses: &[f32],
+    ) -> Vector3<f32> {
+        let mut acceleration = Vector3::zero();
+        let mut i = 0;
+        while i < self.nodes.len() {
+            let node = &self.nodes[i];
+            let [x, y, z, mass] = node.center_of_mass;
+            let d = Vector3::new(x, y, z) - p;
+            let size = node.center[3];
+            if size * size < settings.theta * settings.theta * d.magnitude2() {
+                acceleration += settings.pull(d, mass);
+                i = node.next as usize;
+            } else if node.count > 0 {
+                for &j in &self.indices[node.first as usize..(node.first + node.count) as usize] {
+                    acceleration += settings.pull(positions[j as usize] - p, masses[j as usize]);
+                }
+                i = node.next as usize;
+            } else {
+                i += 1;
+            }
+        }
+        acceleration * settings.gravitational_constant
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct NBodyParameters {
+    gravitational_constant: f32,
+    softening: f32,
+    particle_mass: f32,
+    mass_spread: f32,
+    body_count: u32,
+    //0 until a tree is built
+    node_count: u32,
+    theta: f32,
+    time_step: f32,
+}
+
+//A copy of the bodies, mapped once the gpu has made it
+struct BodyReadback {
+    buffer: wgpu::Buffer,
+    body_count: usize,
+    receiver: mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>,
+}
+
+pub struct NBody {
+    pub settings: NBodySettings,
+    pub particle_count: u32,
+    pub buffer: wgpu::Buffer,
+    tree_nodes: wgpu::Buffer,
+    tree_indices: wgpu::Buffer,
+    //Nodes and indices the tree buffers have room for
+    tree_capacity: (usize, usize),
+    node_count: u32,
+    //Frames since the tree was built, None before the first one
+    tree_age: Option<u32>,
+    //Bodies on their way back for the next build
+    readback: Option<BodyReadback>,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl NBody {
+    pub fn new(gpu: &Gpu, num_particles: usize) -> NBody {
+        let buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("NBody Parameters"),
+            size: std::mem::size_of::<NBodyParameters>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let (tree_nodes, tree_indices) = Self::create_tree_buffers(gpu, 1, 1);
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout =
+            gpu.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        storage_entry(1),
+                        storage_entry(2),
+                    ],
+                    label: Some("nbody_bind_group_layout"),
+                });
+        let bind_group =
+            Self::create_bind_group(gpu, &bind_group_layout, &buffer, &tree_nodes, &tree_indices);
+        NBody {
+            settings: NBodySettings::default(),
+            particle_count: num_particles as u32,
+            buffer,
+            tree_nodes,
+            tree_indices,
+            tree_capacity: (1, 1),
+            node_count: 0,
+            tree_age: None,
+            readback: None,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    fn create_tree_buffers(
+        gpu: &Gpu,
+        nodes: usize,
+        indices: usize,
+    ) -> (wgpu::Buffer, wgpu::Buffer) {
+        let create_buffer = |label, size: usize| {
+            gpu.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size.max(16) as u64,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        (
+            create_buffer("Tree Nodes", nodes * std::mem::size_of::<TreeNode>()),
+            create_buffer("Tree Indices", indices * 4),
+        )
+    }
+
+    fn create_bind_group(
+        gpu: &Gpu,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+        tree_nodes: &wgpu::Buffer,
+        tree_indices: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: tree_nodes.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: tree_indices.as_entire_binding(),
+                },
+            ],
+            label: Some("nbody_bind_group"),
+        })
+    }
+
+    pub fn body_count(&self) -> u32 {
+        self.settings.body_count.min(self.particle_count)
+    }
+
+    pub fn write(&self, gpu: &Gpu) {
+        let settings = &self.settings;
+        gpu.queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::bytes_of(&NBodyParameters {
+                gravitational_constant: settings.gravitational_constant,
+                softening: settings.softening,
+                particle_mass: settings.particle_mass,
+                mass_spread: settings.mass_spread,
+                body_count: self.body_count(),
+                node_count: self.node_count,
+                theta: settings.theta,
+                time_step: settings.time_step,
+            }),
+        );
+    }
+
+    //Forces a rebuild next frame, for when the bodies jump. The old tree and any read back in
+    //flight are dropped, so there's no gravity until the new tree arrives.
+    pub fn invalidate_tree(&mut self) {
+        self.tree_age = None;
+        self.readback = None;
+        self.node_count = 0;
+    }
+
+    //Rebuilds the Barnes-Hut tree every tree_interval frames from the bodies in
+    //particle_buffer, the buffer this frame's pass reads. The bodies are copied out and the
+    //tree is built on a later frame once the copy has been mapped, so the render thread never
+    //waits on the gpu and a fresh tree is a frame or two behind the bodies.
+    //Between builds the tree goes stale: nodes keep the centres of mass and bounds of the
+    //last build and bodies stay in the leaves they were sorted into, only the bodies of
+    //leaves close enough to open are read where they are now. Far pulls drift off as bodies
+    //move relative to the node sizes, so fast moving bodies want a shorter tree_interval.
+    //Returns the node count when it rebuilt.
+    pub fn update_tree(
+        &mut self,
+        gpu: &Gpu,
+        particle_buffer: &wgpu::Buffer,
+    ) -> Result<Option<usize>> {
+        if let Some(readback) = self.readback.take() {
+            gpu.device.poll(wgpu::Maintain::Poll);
+            match readback.receiver.try_recv() {
+                Result::Ok(result) => result?,
+                Err(mpsc::TryRecvError::Empty) => {
+                    self.readback = Some(readback);
+                    return Ok(None);
+                }
+                Err(mpsc::TryRecvError::Disconnected) => bail!("the body read back was dropped"),
+            }
+            return Ok(Some(self.build_tree(gpu, &readback)));
+        }
+        match self.tree_age {
+            Some(age) if age + 1 < self.settings.tree_interval.max(1) => {
+                self.tree_age = Some(age + 1);
+                return Ok(None);
+            }
+            _ => {}
+        }
+        let body_count = self.body_count() as usize;
+        if body_count == 0 {
+            self.node_count = 0;
+            self.tree_age = Some(0);
+            return Ok(Some(0));
+        }
+        let size = (body_count * std::mem::size_of::<Particle>()).max(16) as u64;
+        let buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Body Readback"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = gpu
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Body Readback Encoder"),
+            });
+        encoder.copy_buffer_to_buffer(particle_buffer, 0, &buffer, 0, size);
+        gpu.queue.submit([encoder.finish()]);
+
+        let (sender, receiver) = mpsc::channel();
+        buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                sender.send(result).ok();
+            });
+        self.readback = Some(BodyReadback {
+            buffer,
+            body_count,
+            receiver,
+        });
+        Ok(None)
+    }
+
+    //Builds the tree from a mapped read back and uploads it, returns the node count
+    fn build_tree(&mut self, gpu: &Gpu, readback: &BodyReadback) -> usize {
+        let body_count = readback.body_count;
+        let positions: Vec<Vector3<f32>> = {
+            let data = readback.buffer.slice(..).get_mapped_range();
+            bytemuck::cast_slice::<u8, Particle>(&data)[..body_count]
+                .iter()
+                .map(|particle| {
+                    Vector3::new(
+                        particle.position[0],
+                        particle.position[1],
+                        particle.position[2],
+                    )
+                })
+                .collect()
+        };
+        readback.buffer.unmap();
+
+        let masses: Vec<f32> = (0..body_count as u32)
+            .map(|i| self.settings.body_mass(i))
+            .collect();
+        let tree = Octree::build(&positions, &masses);
+        if tree.nodes.len() > self.tree_capacity.0 || tree.indices.len() > self.tree_capacity.1 {
+            //Room to grow so the buffers aren't remade every build
+            let capacity = (tree.nodes.len() * 3 / 2, body_count);
+            let (tree_nodes, tree_indices) = Self::create_tree_buffers(gpu, capacity.0, capacity.1);
+            self.tree_nodes = tree_nodes;
+            self.tree_indices = tree_indices;
+            self.tree_capacity = capacity;
+            self.bind_group = Self::create_bind_group(
+                gpu,
+                &self.bind_group_layout,
+                &self.buffer,
+                &self.tree_nodes,
+                &self.tree_indices,
+            );
+        }
+        gpu.queue
+            .write_buffer(&self.tree_nodes, 0, bytemuck::cast_slice(&tree.nodes));
+        gpu.queue
+            .write_buffer(&self.tree_indices, 0, bytemuck::cast_slice(&tree.indices));
+        self.node_count = tree.nodes.len() as u32;
+        self.tree_age = Some(0);
+        tree.nodes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn energy_is_conserved() {
+        for mass_spread in [0.0, 1.0] {
+            let settings = NBodySettings {
+                mass_spread,
+                ..NBodySettings::default()
+            };
+            let report = settings.check_energy(&mut StdRng::seed_from_u64(50), 128, 200);
+            //Usually under 1e-2, close encounters in the random disc sometimes push it higher
+            assert!(
+                report.max_drift < 5e-2,
+                "mass spread {}: {:?}",
+                mass_spread,
+                report
+            );
+        }
+    }
+
+    #[test]
+    fn barnes_hut_matches_direct_summation() {
+        let settings = NBodySettings::default();
+        let report = settings.check_energy(&mut StdRng::seed_from_u64(50), 512, 0);
+        //The max isn't checked, bodies whose pulls nearly cancel get large relative errors
+        assert!(report.tree_median_error < 0.04, "{:?}", report);
+        //Opening every node sums every body directly
+        let settings = NBodySettings {
+            theta: 0.0,
+            ..settings
+        };
+        let report = settings.check_energy(&mut StdRng::seed_from_u64(50), 512, 0);
+        assert!(report.tree_max_error < 1e-4, "{:?}", report);
+    }
+}
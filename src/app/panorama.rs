@@ -0,0 +1,333 @@
+//Offline frames for domes and headsets: side by side stereo, and 360 degree equirectangular
+//or fisheye dome master images reprojected from six cube face renders.
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::{Deg, InnerSpace, Matrix4, Point3, Vector3};
+use wgpu::util::DeviceExt;
+
+use super::{
+    camera::{projection::Projection, CameraMatrices, FPSCamera},
+    gpu::Gpu,
+    post::{fullscreen_pipeline, PostProcess},
+    texture::Texture,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PanoramaFormat {
+    //Left eye on the left, each eye at the scene target size
+    Stereo,
+    Equirect,
+    //Angular fisheye dome master
+    Fisheye,
+}
+
+impl PanoramaFormat {
+    pub fn next(self) -> PanoramaFormat {
+        match self {
+            PanoramaFormat::Stereo => PanoramaFormat::Equirect,
+            PanoramaFormat::Equirect => PanoramaFormat::Fisheye,
+            PanoramaFormat::Fisheye => PanoramaFormat::Stereo,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct PanoramaSettings {
+    pub format: PanoramaFormat,
+    //Distance between the eyes in world units
+    pub eye_separation: f32,
+    //Distance that ends up with zero parallax, on the screen plane
+    pub convergence: f32,
+    //Size of each cube face render
+    pub face_size: u32,
+    //Equirect images are half as tall as they are wide
+    pub equirect_width: u32,
+    pub fisheye_size: u32,
+    //Angle covered by the fisheye circle, 180 for a hemispherical dome
+    pub fisheye_fov: f32,
+}
+
+impl Default for PanoramaSettings {
+    fn default() -> Self {
+        PanoramaSettings {
+            format: PanoramaFormat::Equirect,
+            eye_separation: 4.0,
+            convergence: 200.0,
+            face_size: 1024,
+            equirect_width: 4096,
+            fisheye_size: 2048,
+            fisheye_fov: 180.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct Basis {
+    forward: [f32; 4],
+    up: [f32; 4],
+    right: [f32; 4],
+}
+
+impl Basis {
+    fn new(forward: Vector3<f32>, up: Vector3<f32>) -> Basis {
+        let right = forward.cross(up);
+        Basis {
+            forward: forward.extend(0.0).into(),
+            up: up.extend(0.0).into(),
+            right: right.extend(0.0).into(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct PanoramaParameters {
+    rig: Basis,
+    faces: [Basis; 6],
+    format: u32,
+    fisheye_fov: f32,
+    _padding: [f32; 2],
+}
+
+//Level forward and world up of the camera, so panoramas keep the horizon straight
+fn rig_axes(camera: &FPSCamera) -> (Vector3<f32>, Vector3<f32>) {
+    let direction = camera.direction();
+    let level = Vector3::new(direction.x, 0.0, direction.z);
+    let forward = if level.magnitude2() > 1e-8 {
+        level.normalize()
+    } else {
+        -Vector3::unit_z()
+    };
+    (forward, Vector3::unit_y())
+}
+
+//Forward and up of the six cube faces: front, right, back, left, top, bottom
+fn face_axes(camera: &FPSCamera) -> [(Vector3<f32>, Vector3<f32>); 6] {
+    let (forward, up) = rig_axes(camera);
+    let right = forward.cross(up);
+    [
+        (forward, up),
+        (right, up),
+        (-forward, up),
+        (-right, up),
+        (up, -forward),
+        (-up, forward),
+    ]
+}
+
+//Camera for one cube face, a square 90 degree view from the camera position
+pub fn face_matrices(camera: &FPSCamera, projection: &Projection, face: usize) -> CameraMatrices {
+    let (forward, up) = face_axes(camera)[face];
+    let view = Matrix4::look_to_rh(camera.position, forward, up);
+    CameraMatrices::new(view, projection.with_view(1, 1, Deg(90.0)).calc_matrix())
+}
+
+//Camera for one eye, moved offset along the camera's right. Both eyes look straight ahead
+//and the frustum is sheared instead, so they agree at the convergence distance.
+pub fn eye_matrices(
+    camera: &FPSCamera,
+    projection: &Projection,
+    offset: f32,
+    convergence: f32,
+) -> CameraMatrices {
+    let direction = camera.direction();
+    let right = direction.cross(Vector3::unit_y()).normalize();
+    let position: Point3<f32> = camera.position + right * offset;
+    let view = Matrix4::look_to_rh(position, direction, Vector3::unit_y());
+    let projection = projection.calc_matrix();
+    let shift = projection.x.x * offset / convergence.max(1e-3);
+    CameraMatrices::new(
+        view,
+        Matrix4::from_translation(Vector3::new(shift, 0.0, 0.0)) * projection,
+    )
+}
+
+pub struct Panorama {
+    pub settings: PanoramaSettings,
+    bind_group_layout: wgpu::BindGroupLayout,
+    params_buffer: wgpu::Buffer,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Panorama {
+    pub const FACES: usize = 6;
+
+    pub fn new(gpu: &Gpu) -> Panorama {
+        let bind_group_layout =
+            gpu.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Texture {
+                                multisampled: false,
+                                view_dimension: wgpu::TextureViewDimension::D2Array,
+                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                    ],
+                    label: Some("panorama_bind_group_layout"),
+                });
+        let params_buffer = gpu
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Panorama Params Buffer"),
+                contents: bytemuck::bytes_of(&PanoramaParameters::zeroed()),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+        let shader = gpu
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Panorama Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/panorama.wgsl").into()),
+            });
+        let layout = gpu
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Panorama Pipeline Layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let pipeline = fullscreen_pipeline(
+            gpu,
+            &layout,
+            &shader,
+            "fs_main",
+            PostProcess::HDR_FORMAT,
+            None,
+            1,
+        );
+
+        Panorama {
+            settings: PanoramaSettings::default(),
+            bind_group_layout,
+            params_buffer,
+            pipeline,
+        }
+    }
+
+    //Hdr texture array the cube faces get copied into, one layer per face
+    pub fn create_faces(&self, gpu: &Gpu) -> Texture {
+        let size = wgpu::Extent3d {
+            width: self.settings.face_size,
+            height: self.settings.face_size,
+            depth_or_array_layers: Self::FACES as u32,
+        };
+        let texture = gpu.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("panorama_faces"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: PostProcess::HDR_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let sampler = gpu.device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        Texture {
+            texture,
+            view,
+            sampler,
+            size,
+        }
+    }
+
+    //Resamples the cube faces into an equirect or fisheye hdr image
+    pub fn reproject(&self, gpu: &Gpu, faces: &Texture, camera: &FPSCamera) -> Texture {
+        let s = &self.settings;
+        let (width, height) = match s.format {
+            PanoramaFormat::Fisheye => (s.fisheye_size, s.fisheye_size),
+            _ => (s.equirect_width, s.equirect_width / 2),
+        };
+        let (forward, up) = rig_axes(camera);
+        let params = PanoramaParameters {
+            rig: Basis::new(forward, up),
+            faces: face_axes(camera).map(|(forward, up)| Basis::new(forward, up)),
+            format: s.format as u32,
+            fisheye_fov: s.fisheye_fov,
+            _padding: [0.0; 2],
+        };
+        gpu.queue
+            .write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+        let bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&faces.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&faces.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.params_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("panorama_bind_group"),
+        });
+
+        let output = Texture::create_render_target(
+            &gpu.device,
+            width,
+            height,
+            PostProcess::HDR_FORMAT,
+            "panorama_output",
+        );
+        let mut encoder = gpu
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Panorama Encoder"),
+            });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Panorama Reproject Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &output.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+        gpu.queue.submit([encoder.finish()]);
+        output
+    }
+}
@@ -1,15 +1,34 @@
 //Low level gpu stuff for particles. Keeps main particle system file less cluttered.
 
+use std::mem;
+use std::path::Path;
 use std::time;
 
+use anyhow::*;
+
 use bytemuck::{Pod, Zeroable};
 use rand::Rng;
 use wgpu::util::DeviceExt;
 
-use super::{camera::FatCamera, gpu::Gpu, texture::Texture};
+use super::{
+    boids::Boids,
+    camera::FatCamera,
+    clip::{ClipParameters, ClipSettings},
+    colliders::Colliders,
+    field::FieldParameters,
+    fluid::Fluid,
+    forces::ForceStack,
+    gpu::Gpu,
+    mesh_sdf::MeshSdf,
+    nbody::NBody,
+    post::PostProcess,
+    sort::DepthSort,
+    spatial_hash::{SpatialHash, SpatialHashSettings},
+    texture::Texture,
+    trails::Trails,
+};
 
 pub const PARTICLES_PER_GROUP: u32 = 64;
-const PARTICLE_SIZE: f32 = 0.5;
 
 //Holds all gpu state for particles.
 pub struct ParticleGPU {
@@ -21,12 +40,32 @@ pub struct ParticleGPU {
     pub texture_bind_group: wgpu::BindGroup,
     pub texture_bind_group_layout: wgpu::BindGroupLayout,
     pub render_pipeline: wgpu::RenderPipeline,
+    pub sorted_render_pipeline: wgpu::RenderPipeline,
+    pub depth_sort: DepthSort,
+    pub trails: Trails,
+    pub forces: ForceStack,
+    pub colliders: Colliders,
+    pub boids: Boids,
+    pub fluid: Fluid,
+    pub nbody: NBody,
+    pub spatial_hash: SpatialHash,
     pub compute_pipeline: wgpu::ComputePipeline,
+    //Behaviour::Boids and Behaviour::Fluid, need the spatial hash at group 3
+    pub boids_pipeline: wgpu::ComputePipeline,
+    pub fluid_density_pipeline: wgpu::ComputePipeline,
+    pub fluid_pipeline: wgpu::ComputePipeline,
+    //Behaviour::NBody, need NBody::bind_group at group 3
+    pub nbody_tiled_pipeline: wgpu::ComputePipeline,
+    pub nbody_tree_pipeline: wgpu::ComputePipeline,
     pub work_group_count: u32,
     pub depth_texture: Texture,
-    pub parameters: ParticleSystemParameters,
     pub params_buffer: wgpu::Buffer,
+    //Field parameters at group 1, for anything else running the field from sim.wgsl
+    pub params_bind_group_layout: wgpu::BindGroupLayout,
     pub params_bind_group: wgpu::BindGroup,
+    pub render_params_buffer: wgpu::Buffer,
+    //MSAA samples of the targets the render pipelines draw into
+    pub sample_count: u32,
 }
 
 #[repr(C)]
@@ -39,13 +78,181 @@ pub struct Vertex {
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
 pub struct ParticleSystemParameters {
+    constant_force: [f32; 4],
     noise_scale: f32,
     speed_multiplier: f32,
     curl_multiplier: f32,
-    constant_force: [f32; 3],
     potential_curl_mix: f32,
-    elapsed_time: f32,
-    time_multiplier: f32,
+    field_time: f32,
+    octaves: u32,
+    lacunarity: f32,
+    gain: f32,
+    fractal_mode: u32,
+    rotate_octaves: u32,
+    warp_strength: f32,
+    warp_scale: f32,
+    field_response: f32,
+    boundary_width: f32,
+    _padding: [f32; 2],
+}
+
+impl From<&FieldParameters> for ParticleSystemParameters {
+    fn from(field: &FieldParameters) -> Self {
+        ParticleSystemParameters {
+            constant_force: field.constant_force.extend(0.0).into(),
+            noise_scale: field.noise_scale,
+            speed_multiplier: field.speed_multiplier,
+            curl_multiplier: field.curl_multiplier,
+            potential_curl_mix: field.curl_mix,
+            field_time: field.time,
+            octaves: field.fractal.octaves,
+            lacunarity: field.fractal.lacunarity,
+            gain: field.fractal.gain,
+            fractal_mode: field.fractal.mode as u32,
+            rotate_octaves: field.fractal.rotate_octaves as u32,
+            warp_strength: field.fractal.warp_strength,
+            warp_scale: field.fractal.warp_scale,
+            field_response: field.field_response,
+            boundary_width: field.boundary_width,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum QuadMode {
+    //Camera facing squares
+    Billboard,
+    //Stretched along the projected velocity, like a motion blurred streak
+    VelocityAligned,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SpriteMode {
+    //Sampled from the sprite texture, or a frame of it when it is an atlas
+    Texture,
+    //Procedural shapes, no texture involved
+    SoftGaussian,
+    Ring,
+    HardDisc,
+}
+
+impl SpriteMode {
+    pub fn next(self) -> SpriteMode {
+        match self {
+            SpriteMode::Texture => SpriteMode::SoftGaussian,
+            SpriteMode::SoftGaussian => SpriteMode::Ring,
+            SpriteMode::Ring => SpriteMode::HardDisc,
+            SpriteMode::HardDisc => SpriteMode::Texture,
+        }
+    }
+}
+
+//What picks a particle's atlas frame
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FrameSource {
+    Id,
+    //Plays the frames as a flipbook at frame_rate
+    Age,
+    //Frames spread from still to frame_speed_max
+    Speed,
+}
+
+impl FrameSource {
+    pub fn next(self) -> FrameSource {
+        match self {
+            FrameSource::Id => FrameSource::Age,
+            FrameSource::Age => FrameSource::Speed,
+            FrameSource::Speed => FrameSource::Id,
+        }
+    }
+}
+
+//Frame grid from a sprite file name ending in _<columns>x<rows>, e.g. smoke_8x4.png
+pub fn atlas_grid(path: &Path) -> Option<(u32, u32)> {
+    let stem = path.file_stem()?.to_str()?;
+    let captures = regex::Regex::new(r"_(\d+)x(\d+)$").ok()?.captures(stem)?;
+    let columns = captures[1].parse().ok()?;
+    let rows = captures[2].parse().ok()?;
+    (columns > 0 && rows > 0).then(|| (columns, rows))
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct RenderSettings {
+    pub particle_size: f32,
+    pub quad_mode: QuadMode,
+    //Streak length is projected speed * shutter_time, clamped to min/max_length
+    pub shutter_time: f32,
+    pub min_length: f32,
+    pub max_length: f32,
+    //Distance in front of scene geometry over which particles fade out, 0 clips hard
+    pub soft_fade_distance: f32,
+    pub sprite_mode: SpriteMode,
+    //Frame grid of the sprite texture, 1 x 1 for a plain sprite
+    pub atlas_columns: u32,
+    pub atlas_rows: u32,
+    pub frame_source: FrameSource,
+    pub frame_rate: f32,
+    pub frame_speed_max: f32,
+    pub clip: ClipSettings,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            particle_size: 0.5,
+            quad_mode: QuadMode::Billboard,
+            shutter_time: 0.02,
+            min_length: 0.0,
+            max_length: 8.0,
+            soft_fade_distance: 2.0,
+            sprite_mode: SpriteMode::Texture,
+            atlas_columns: 1,
+            atlas_rows: 1,
+            frame_source: FrameSource::Id,
+            frame_rate: 10.0,
+            frame_speed_max: 100.0,
+            clip: ClipSettings::default(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct RenderParameters {
+    particle_size: f32,
+    quad_mode: u32,
+    shutter_time: f32,
+    min_length: f32,
+    max_length: f32,
+    soft_fade_distance: f32,
+    sprite_mode: u32,
+    atlas_columns: u32,
+    atlas_rows: u32,
+    frame_source: u32,
+    frame_rate: f32,
+    frame_speed_max: f32,
+    clip: ClipParameters,
+}
+
+impl From<&RenderSettings> for RenderParameters {
+    fn from(settings: &RenderSettings) -> Self {
+        RenderParameters {
+            particle_size: settings.particle_size,
+            quad_mode: settings.quad_mode as u32,
+            shutter_time: settings.shutter_time,
+            min_length: settings.min_length,
+            max_length: settings.max_length,
+            soft_fade_distance: settings.soft_fade_distance,
+            sprite_mode: settings.sprite_mode as u32,
+            atlas_columns: settings.atlas_columns.max(1),
+            atlas_rows: settings.atlas_rows.max(1),
+            frame_source: settings.frame_source as u32,
+            frame_rate: settings.frame_rate,
+            frame_speed_max: settings.frame_speed_max,
+            clip: ClipParameters::from(&settings.clip),
+        }
+    }
 }
 
 #[repr(C)]
@@ -56,25 +263,26 @@ pub struct Particle {
     pub color: [f32; 4],
 }
 
+//Unit quad, scaled by RenderSettings::particle_size in the vertex shader
 const QUAD_VERTICES: &[Vertex] = &[
     //Top left
     Vertex {
-        position: [-PARTICLE_SIZE / 2.0, PARTICLE_SIZE / 2.0, 0.0, 0.0],
+        position: [-0.5, 0.5, 0.0, 0.0],
         tex_coords: [0.0, 0.0],
     },
     //Bottom left
     Vertex {
-        position: [-PARTICLE_SIZE / 2.0, -PARTICLE_SIZE / 2.0, 0.0, 0.0],
+        position: [-0.5, -0.5, 0.0, 0.0],
         tex_coords: [0.0, 1.0],
     },
     //Bottom right
     Vertex {
-        position: [PARTICLE_SIZE / 2.0, -PARTICLE_SIZE / 2.0, 0.0, 0.0],
+        position: [0.5, -0.5, 0.0, 0.0],
         tex_coords: [1.0, 1.0],
     },
     //Top right
     Vertex {
-        position: [PARTICLE_SIZE / 2.0, PARTICLE_SIZE / 2.0, 0.0, 0.0],
+        position: [0.5, 0.5, 0.0, 0.0],
         tex_coords: [1.0, 0.0],
     },
 ];
@@ -96,14 +304,44 @@ impl ParticleGPU {
     fn create_paramaters_bind_group(
         gpu: &Gpu,
         param_buffer: &wgpu::Buffer,
+        forces: &ForceStack,
+        colliders: &Colliders,
+        boids: &Boids,
+        fluid: &Fluid,
         compute_bind_group_layout: &wgpu::BindGroupLayout,
     ) -> wgpu::BindGroup {
         gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &compute_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: param_buffer.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: param_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: forces.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: colliders.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&colliders.mesh_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: boids.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: fluid.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: fluid.densities.as_entire_binding(),
+                },
+            ],
             label: None,
         })
     }
@@ -119,7 +357,8 @@ impl ParticleGPU {
                         contents: bytemuck::cast_slice(particle_data),
                         usage: wgpu::BufferUsages::VERTEX
                             | wgpu::BufferUsages::STORAGE
-                            | wgpu::BufferUsages::COPY_DST,
+                            | wgpu::BufferUsages::COPY_DST
+                            | wgpu::BufferUsages::COPY_SRC,
                     }),
             );
         }
@@ -153,16 +392,17 @@ impl ParticleGPU {
     }
 
     pub fn new(gpu: &Gpu, fat_cam: &FatCamera, particle_data: &Vec<Particle>) -> Self {
-        let parameters = ParticleSystemParameters {
-            noise_scale: 0.01,
-            speed_multiplier: 20.0,
-            curl_multiplier: 20.0,
-            constant_force: [0.0, 1.0, 0.0],
-            potential_curl_mix: 1.0,
-            elapsed_time: 0.0,
-            time_multiplier: 1.0,
-        };
+        let parameters = ParticleSystemParameters::from(&FieldParameters::default());
         let params_buffer = Self::create_parameters_buffer(gpu, &parameters);
+        let render_params_buffer =
+            gpu.device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Render Params Buffer"),
+                    contents: bytemuck::bytes_of(&RenderParameters::from(
+                        &RenderSettings::default(),
+                    )),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                });
 
         let quad_vertex_buffer = gpu
             .device
@@ -191,62 +431,107 @@ impl ParticleGPU {
         )
         .unwrap(); // CHANGED!
 
-        let depth_texture =
-            Texture::create_depth_texture(&gpu.device, &gpu.config, "depth_texture");
+        let depth_texture = Texture::create_depth_texture(
+            &gpu.device,
+            gpu.config.width,
+            gpu.config.height,
+            1,
+            "depth_texture",
+        );
 
-        let texture_bind_group_layout =
-            gpu.device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    entries: &[
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 0,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            ty: wgpu::BindingType::Texture {
-                                multisampled: false,
-                                view_dimension: wgpu::TextureViewDimension::D2,
-                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            },
-                            count: None,
-                        },
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 1,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            // This should match the filterable field of the
-                            // corresponding Texture entry above.
-                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                            count: None,
-                        },
-                    ],
-                    label: Some("texture_bind_group_layout"),
-                });
+        let texture_bind_group_layout = Self::create_texture_bind_group_layout(gpu, 1);
 
-        let texture_bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&particle_texture.view), // CHANGED!
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&particle_texture.sampler), // CHANGED!
-                },
-            ],
-            label: Some("diffuse_bind_group"),
-        });
+        let texture_bind_group = Self::create_texture_bind_group(
+            gpu,
+            &texture_bind_group_layout,
+            &particle_texture,
+            &depth_texture,
+            &render_params_buffer,
+        );
 
         let particle_buffers = Self::generate_particle_buffers(gpu, particle_data);
 
-        let render_pipeline = Self::build_render_pipeline(gpu, &texture_bind_group_layout, fat_cam);
+        let depth_sort = DepthSort::new(gpu, fat_cam, &particle_buffers, particle_data.len());
+        let spatial_hash = SpatialHash::new(
+            gpu,
+            &particle_buffers,
+            particle_data.len(),
+            SpatialHashSettings::default(),
+        );
+
+        let render_pipeline =
+            Self::build_render_pipeline(gpu, &texture_bind_group_layout, fat_cam, None, 1);
+        let sorted_render_pipeline = Self::build_render_pipeline(
+            gpu,
+            &texture_bind_group_layout,
+            fat_cam,
+            Some(&depth_sort.render_bind_group_layout),
+            1,
+        );
+        let trails = Trails::new(gpu, fat_cam);
+        let forces = ForceStack::new(gpu);
+        let colliders = Colliders::new(gpu);
+        let boids = Boids::new(gpu);
+        let fluid = Fluid::new(gpu, particle_data.len());
+        let nbody = NBody::new(gpu, particle_data.len());
         let (compute_bind_group_layout, param_bg_layout, compute_pipeline) =
-            Self::build_compute_pipeline(gpu, particle_data.len());
+            Self::build_compute_pipeline(gpu, particle_data.len(), &trails);
+        let neighbour_pipeline = |source, entry_point| {
+            let shader_src = format!(
+                "{}\n{}\n{}\n{}",
+                include_str!("../shaders/spatial_hash_common.wgsl"),
+                include_str!("../shaders/spatial_hash.wgsl"),
+                include_str!("../shaders/sim.wgsl"),
+                source
+            );
+            Self::build_behaviour_pipeline(
+                gpu,
+                &compute_bind_group_layout,
+                &param_bg_layout,
+                &trails,
+                &spatial_hash.query_bind_group_layout,
+                shader_src,
+                entry_point,
+            )
+        };
+        let boids_pipeline =
+            neighbour_pipeline(include_str!("../shaders/boids.wgsl"), "boids_main");
+        let fluid_density_pipeline =
+            neighbour_pipeline(include_str!("../shaders/fluid.wgsl"), "fluid_density");
+        let fluid_pipeline =
+            neighbour_pipeline(include_str!("../shaders/fluid.wgsl"), "fluid_main");
+        let nbody_pipeline = |entry_point| {
+            let shader_src = format!(
+                "{}\n{}",
+                include_str!("../shaders/sim.wgsl"),
+                include_str!("../shaders/nbody.wgsl")
+            );
+            Self::build_behaviour_pipeline(
+                gpu,
+                &compute_bind_group_layout,
+                &param_bg_layout,
+                &trails,
+                &nbody.bind_group_layout,
+                shader_src,
+                entry_point,
+            )
+        };
+        let nbody_tiled_pipeline = nbody_pipeline("nbody_tiled");
+        let nbody_tree_pipeline = nbody_pipeline("nbody_tree");
 
         let particle_bind_groups =
             Self::generate_particle_bind_groups(gpu, &compute_bind_group_layout, &particle_buffers);
 
         println!("creating params");
-        let params_bind_group =
-            Self::create_paramaters_bind_group(gpu, &params_buffer, &param_bg_layout);
+        let params_bind_group = Self::create_paramaters_bind_group(
+            gpu,
+            &params_buffer,
+            &forces,
+            &colliders,
+            &boids,
+            &fluid,
+            &param_bg_layout,
+        );
         println!("creating params2");
 
         let work_group_count =
@@ -261,18 +546,207 @@ impl ParticleGPU {
             texture_bind_group_layout,
             particle_bind_groups,
             render_pipeline,
+            sorted_render_pipeline,
+            depth_sort,
+            trails,
+            forces,
+            colliders,
+            boids,
+            fluid,
+            nbody,
+            spatial_hash,
             compute_pipeline,
+            boids_pipeline,
+            fluid_density_pipeline,
+            fluid_pipeline,
+            nbody_tiled_pipeline,
+            nbody_tree_pipeline,
             work_group_count,
             depth_texture,
-            parameters,
             params_buffer,
+            params_bind_group_layout: param_bg_layout,
             params_bind_group,
+            render_params_buffer,
+            sample_count: 1,
+        }
+    }
+
+    //The scene depth binding has to match the depth texture's sample count
+    fn create_texture_bind_group_layout(gpu: &Gpu, sample_count: u32) -> wgpu::BindGroupLayout {
+        gpu.device
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        // This should match the filterable field of the
+                        // corresponding Texture entry above.
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    //Scene depth for soft particles
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: sample_count > 1,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Depth,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("texture_bind_group_layout"),
+            })
+    }
+
+    fn create_texture_bind_group(
+        gpu: &Gpu,
+        layout: &wgpu::BindGroupLayout,
+        particle_texture: &Texture,
+        depth_texture: &Texture,
+        render_params_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&particle_texture.view), // CHANGED!
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&particle_texture.sampler), // CHANGED!
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: render_params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+                },
+            ],
+            label: Some("diffuse_bind_group"),
+        })
+    }
+
+    //Recreates the depth texture for new scene targets, along with the bind group sampling it.
+    //A new sample count also needs new pipelines, including the trail ones.
+    pub fn resize(
+        &mut self,
+        gpu: &Gpu,
+        fat_cam: &FatCamera,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) {
+        if sample_count != self.sample_count {
+            self.texture_bind_group_layout =
+                Self::create_texture_bind_group_layout(gpu, sample_count);
+            self.render_pipeline = Self::build_render_pipeline(
+                gpu,
+                &self.texture_bind_group_layout,
+                fat_cam,
+                None,
+                sample_count,
+            );
+            self.sorted_render_pipeline = Self::build_render_pipeline(
+                gpu,
+                &self.texture_bind_group_layout,
+                fat_cam,
+                Some(&self.depth_sort.render_bind_group_layout),
+                sample_count,
+            );
+            self.trails.set_sample_count(gpu, fat_cam, sample_count);
+            self.sample_count = sample_count;
         }
+        self.depth_texture = Texture::create_depth_texture(
+            &gpu.device,
+            width,
+            height,
+            sample_count,
+            "depth_texture",
+        );
+        self.texture_bind_group = Self::create_texture_bind_group(
+            gpu,
+            &self.texture_bind_group_layout,
+            &self.particle_texture,
+            &self.depth_texture,
+            &self.render_params_buffer,
+        );
+    }
+
+    //Swaps in a sprite image (png or jpeg) from disk
+    pub fn load_sprite(&mut self, gpu: &Gpu, path: &Path) -> Result<()> {
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("couldn't read sprite {}", path.display()))?;
+        let label = path.to_string_lossy();
+        self.particle_texture = Texture::from_bytes(&gpu.device, &gpu.queue, &bytes, &label)?;
+        self.texture_bind_group = Self::create_texture_bind_group(
+            gpu,
+            &self.texture_bind_group_layout,
+            &self.particle_texture,
+            &self.depth_texture,
+            &self.render_params_buffer,
+        );
+        Ok(())
+    }
+
+    //Uploads a baked collider sdf. Any mesh colliders take the new shape.
+    pub fn set_collider_mesh(&mut self, gpu: &Gpu, mesh: MeshSdf) {
+        self.colliders.set_mesh(gpu, mesh);
+        self.params_bind_group = Self::create_paramaters_bind_group(
+            gpu,
+            &self.params_buffer,
+            &self.forces,
+            &self.colliders,
+            &self.boids,
+            &self.fluid,
+            &self.params_bind_group_layout,
+        );
+    }
+
+    pub fn write_parameters(&self, gpu: &Gpu, field: &FieldParameters) {
+        gpu.queue.write_buffer(
+            &self.params_buffer,
+            0,
+            bytemuck::bytes_of(&ParticleSystemParameters::from(field)),
+        );
+    }
+
+    pub fn write_render_parameters(&self, gpu: &Gpu, settings: &RenderSettings) {
+        gpu.queue.write_buffer(
+            &self.render_params_buffer,
+            0,
+            bytemuck::bytes_of(&RenderParameters::from(settings)),
+        );
     }
 
     fn build_compute_pipeline(
         gpu: &Gpu,
         num_particles: usize,
+        trails: &Trails,
     ) -> (
         wgpu::BindGroupLayout,
         wgpu::BindGroupLayout,
@@ -317,23 +791,93 @@ impl ParticleGPU {
         let param_bind_group_layout =
             gpu.device
                 .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    entries: &[wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new((32 * 9) as _),
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: wgpu::BufferSize::new(mem::size_of::<
+                                    ParticleSystemParameters,
+                                >(
+                                )
+                                    as _),
+                            },
+                            count: None,
                         },
-                        count: None,
-                    }],
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 3,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Texture {
+                                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                                view_dimension: wgpu::TextureViewDimension::D3,
+                                multisampled: false,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 4,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 5,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 6,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                    ],
                     label: None,
                 });
         let compute_pipeline_layout =
             gpu.device
                 .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                     label: Some("particle system compute"),
-                    bind_group_layouts: &[&compute_bind_group_layout, &param_bind_group_layout],
+                    bind_group_layouts: &[
+                        &compute_bind_group_layout,
+                        &param_bind_group_layout,
+                        &trails.compute_bind_group_layout,
+                    ],
                     push_constant_ranges: &[],
                 });
         let compute_pipeline =
@@ -351,12 +895,60 @@ impl ParticleGPU {
         )
     }
 
+    //A behaviour built on sim.wgsl that needs one more bind group at group 3
+    fn build_behaviour_pipeline(
+        gpu: &Gpu,
+        compute_bind_group_layout: &wgpu::BindGroupLayout,
+        param_bind_group_layout: &wgpu::BindGroupLayout,
+        trails: &Trails,
+        extra_bind_group_layout: &wgpu::BindGroupLayout,
+        shader_src: String,
+        entry_point: &str,
+    ) -> wgpu::ComputePipeline {
+        let shader = gpu
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(entry_point),
+                source: wgpu::ShaderSource::Wgsl(shader_src.into()),
+            });
+        let pipeline_layout = gpu
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(entry_point),
+                bind_group_layouts: &[
+                    compute_bind_group_layout,
+                    param_bind_group_layout,
+                    &trails.compute_bind_group_layout,
+                    extra_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+        gpu.device
+            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+            })
+    }
+
+    //Without a sort layout this is the additive pipeline fed by the particle vertex buffer.
+    //With one, particles are fetched back to front through the sorted entries and alpha blended.
     fn build_render_pipeline(
         gpu: &Gpu,
         texture_bgl: &wgpu::BindGroupLayout,
         fat_cam: &FatCamera,
+        sorted_bgl: Option<&wgpu::BindGroupLayout>,
+        sample_count: u32,
     ) -> wgpu::RenderPipeline {
-        let shader_src = include_str!("../shaders/renderer.wgsl");
+        let mut shader_src = format!(
+            "{}\n{}",
+            include_str!("../shaders/clip.wgsl"),
+            include_str!("../shaders/renderer.wgsl")
+        );
+        if sample_count > 1 {
+            shader_src = shader_src.replace("texture_depth_2d", "texture_depth_multisampled_2d");
+        }
         let shader = gpu
             .device
             .create_shader_module(wgpu::ShaderModuleDescriptor {
@@ -364,15 +956,63 @@ impl ParticleGPU {
                 source: wgpu::ShaderSource::Wgsl(shader_src.into()),
             });
 
+        let mut bind_group_layouts = vec![texture_bgl, &fat_cam.bind_group_layout];
+        bind_group_layouts.extend(sorted_bgl);
         let render_pipeline_layout =
             gpu.device
                 .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                     label: Some("Render Pipeline Layout"),
 
-                    bind_group_layouts: &[texture_bgl, &fat_cam.bind_group_layout],
+                    bind_group_layouts: &bind_group_layouts,
                     push_constant_ranges: &[],
                 });
 
+        let particle_layout = wgpu::VertexBufferLayout {
+            array_stride: 4 * 12,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4, 2 => Float32x4],
+        };
+        let quad_layout = wgpu::VertexBufferLayout {
+            array_stride: 4 * 6,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &wgpu::vertex_attr_array![3 => Float32x4, 4 => Float32x2],
+        };
+        let (entry_point, buffers, blend) = match sorted_bgl {
+            // Additive into the hdr target so dense regions glow instead of saturating
+            None => (
+                "vs_main",
+                vec![particle_layout, quad_layout],
+                wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                },
+            ),
+            Some(_) => (
+                "vs_sorted",
+                vec![quad_layout],
+                wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                },
+            ),
+        };
+
         let render_pipeline = gpu
             .device
             .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
@@ -380,38 +1020,15 @@ impl ParticleGPU {
                 layout: Some(&render_pipeline_layout),
                 vertex: wgpu::VertexState {
                     module: &shader,
-                    entry_point: "vs_main",
-                    buffers: &[
-                        wgpu::VertexBufferLayout {
-                            array_stride: 4 * 12,
-                            step_mode: wgpu::VertexStepMode::Instance,
-                            attributes: &wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4, 2 => Float32x4],
-                        },
-                        wgpu::VertexBufferLayout {
-                            array_stride: 4 * 6,
-                            step_mode: wgpu::VertexStepMode::Vertex,
-                            attributes: &wgpu::vertex_attr_array![3 => Float32x4, 4 => Float32x2],
-                        },
-                    ],
+                    entry_point,
+                    buffers: &buffers,
                 },
                 fragment: Some(wgpu::FragmentState {
                     module: &shader,
                     entry_point: "fs_main",
                     targets: &[Some(wgpu::ColorTargetState {
-                        // 4.
-                        format: gpu.config.format,
-                        blend: Some(wgpu::BlendState {
-                            color: wgpu::BlendComponent {
-                                src_factor: wgpu::BlendFactor::SrcAlpha,
-                                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                                operation: wgpu::BlendOperation::Add,
-                            },
-                            alpha: wgpu::BlendComponent {
-                                src_factor: wgpu::BlendFactor::One,
-                                dst_factor: wgpu::BlendFactor::One,
-                                operation: wgpu::BlendOperation::Add,
-                            },
-                        }),
+                        format: PostProcess::HDR_FORMAT,
+                        blend: Some(blend),
                         write_mask: wgpu::ColorWrites::ALL,
                     })],
                 }),
@@ -429,13 +1046,13 @@ impl ParticleGPU {
                 },
                 depth_stencil: Some(wgpu::DepthStencilState {
                     format: Texture::DEPTH_FORMAT,
-                    depth_write_enabled: true,
+                    depth_write_enabled: false,
                     depth_compare: wgpu::CompareFunction::Always, // 1.
                     stencil: wgpu::StencilState::default(), // 2.
                     bias: wgpu::DepthBiasState::default(),
                 }), // 1.
                 multisample: wgpu::MultisampleState {
-                    count: 1,                         // 2.
+                    count: sample_count,              // 2.
                     mask: !0,                         // 3.
                     alpha_to_coverage_enabled: false, // 4.
                 },
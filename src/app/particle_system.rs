@@ -1,20 +1,133 @@
 use std::mem;
+use std::time::{Duration, Instant};
 
 use cgmath::Vector3;
 use rand::Rng;
 use wgpu::util::DeviceExt;
 
-use super::camera::FatCamera;
-use super::particle_gpu::{self, Particle, ParticleGPU};
+use super::accumulation::{self, Accumulation};
+use super::camera::{CameraMatrices, FatCamera};
+use super::field::FieldParameters;
+use super::field_view::FieldView;
+use super::helpers::Helpers;
+use super::nbody::{NBodySolver, GALAXY_SCALE_LENGTH};
+use super::panorama::{self, Panorama, PanoramaFormat};
+use super::particle_gpu::{self, Particle, ParticleGPU, RenderSettings};
+use super::post::PostProcess;
+use super::scene::Scene;
+use super::texture::Texture;
 use super::time::Time;
+use super::volume::Volume;
 use super::{gpu::Gpu, math::UVec2};
 
 pub const NUM_PARTICLES: usize = 1000000;
 const cube_size: f32 = 200.0;
+//Half size of the cube particles wrap around in, matches max_extent in sim.wgsl
+pub const MAX_EXTENT: f32 = 300.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BlendMode {
+    //Order independent, buffer order is fine
+    Additive,
+    //Back to front alpha blending through the per frame depth sort
+    SortedAlpha,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RenderMode {
+    Particles,
+    //Long exposure, particles build up in a slowly fading accumulation buffer
+    Accumulation,
+    //Particle density splatted into a grid and ray marched like smoke
+    Volume,
+}
+
+impl RenderMode {
+    pub fn next(self) -> RenderMode {
+        match self {
+            RenderMode::Particles => RenderMode::Accumulation,
+            RenderMode::Accumulation => RenderMode::Volume,
+            RenderMode::Volume => RenderMode::Particles,
+        }
+    }
+}
+
+//What moves the particles
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Behaviour {
+    //The curl field and the force stack
+    Field,
+    //Flocking through the spatial hash, blended with the field by BoidSettings::field_mix
+    Boids,
+    //SPH liquid in a box, see fluid.rs
+    Fluid,
+    //Self gravity, see nbody.rs
+    NBody,
+}
+
+impl Behaviour {
+    pub fn next(self) -> Behaviour {
+        match self {
+            Behaviour::Field => Behaviour::Boids,
+            Behaviour::Boids => Behaviour::Fluid,
+            Behaviour::Fluid => Behaviour::NBody,
+            Behaviour::NBody => Behaviour::Field,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TargetSettings {
+    //Scene targets are this many times the window size, tone mapping rescales into the swapchain
+    pub render_scale: f32,
+    //MSAA samples for particles, trails and geometry
+    pub sample_count: u32,
+}
+
+impl Default for TargetSettings {
+    fn default() -> Self {
+        TargetSettings {
+            render_scale: 1.0,
+            sample_count: 1,
+        }
+    }
+}
+
+impl TargetSettings {
+    pub const SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+
+    pub fn scaled_size(&self, gpu: &Gpu) -> (u32, u32) {
+        let max = gpu.device.limits().max_texture_dimension_2d;
+        let scale = |x: u32| ((x as f32 * self.render_scale).round() as u32).clamp(1, max);
+        (scale(gpu.config.width), scale(gpu.config.height))
+    }
+}
 
 pub struct ParticleSystem {
     pub particle_gpu: ParticleGPU,
+    pub post: PostProcess,
+    pub accumulation: Accumulation,
+    pub volume: Volume,
+    pub scene: Scene,
+    pub helpers: Helpers,
+    //Cpu copy of the field the simulation runs, for the field view
+    pub field: FieldParameters,
+    //Elapsed time the field was last advanced to
+    field_elapsed: Duration,
+    pub field_view: FieldView,
+    pub panorama: Panorama,
     pub size: UVec2,
+    pub behaviour: Behaviour,
+    pub render_mode: RenderMode,
+    pub blend_mode: BlendMode,
+    pub render_settings: RenderSettings,
+    //Change through set_target_settings so the targets get recreated
+    pub target_settings: TargetSettings,
+    //Multisampled colour target, resolved into the hdr texture. Only there with MSAA on.
+    pub msaa_texture: Option<Texture>,
+    //Waits for the gpu around the sort so its cost can be read back. Stalls the frame.
+    pub profile_sort: bool,
+    pub last_sort_time: Option<Duration>,
 }
 
 impl ParticleSystem {
@@ -29,31 +142,186 @@ impl ParticleSystem {
             let x_v = 0.0;
             let y_v = 0.0;
             let z_v = 0.0;
+            //Random starting age so flipbooks don't all play in step
+            let age = rng.gen_range(0.0..10.0);
 
             let r = 0.0;
             let g = 0.0;
             let b = 0.0;
             particle_data.push(Particle {
                 position: [x, y, z, 1.0],
-                velocity: [x_v, y_v, z_v, 0.0],
+                velocity: [x_v, y_v, z_v, age],
                 color: [r, g, b, 1.0],
             });
         }
         particle_data
     }
 
+    //Bodies and tracers alike spread over one disc, on orbits around the bodies' mass
+    fn create_particle_data_galaxy(&self) -> Vec<Particle> {
+        let nbody = &self.particle_gpu.nbody;
+        let total_mass = (0..nbody.body_count())
+            .map(|i| nbody.settings.body_mass(i))
+            .sum();
+        let mut rng = rand::thread_rng();
+        nbody
+            .settings
+            .galaxy_disc(&mut rng, NUM_PARTICLES, total_mass, GALAXY_SCALE_LENGTH)
+            .into_iter()
+            .map(|(position, velocity)| Particle {
+                position: position.extend(1.0).into(),
+                velocity: velocity.extend(rng.gen_range(0.0..10.0)).into(),
+                color: [0.0, 0.0, 0.0, 1.0],
+            })
+            .collect()
+    }
+
+    //Lays the particles out as a rotating disc galaxy for Behaviour::NBody
+    pub fn reset_galaxy(&mut self, gpu: &Gpu) {
+        let particle_data = self.create_particle_data_galaxy();
+        for buffer in &self.particle_gpu.particle_buffers {
+            gpu.queue
+                .write_buffer(buffer, 0, bytemuck::cast_slice(&particle_data));
+        }
+        self.particle_gpu.nbody.invalidate_tree();
+        self.particle_gpu.trails.reset();
+    }
+
     pub fn new(gpu: &Gpu, size: UVec2, fat_cam: &FatCamera) -> ParticleSystem {
         let particle_gpu = ParticleGPU::new(gpu, fat_cam, &Self::create_particle_data_random());
+        let post = PostProcess::new(gpu, gpu.config.width, gpu.config.height);
+        let accumulation = Accumulation::new(gpu, gpu.config.width, gpu.config.height);
+        let volume = Volume::new(gpu, fat_cam, &particle_gpu.particle_buffers, MAX_EXTENT);
+        let scene = Scene::new(gpu, fat_cam);
+        let helpers = Helpers::new(gpu, fat_cam, MAX_EXTENT);
+        let field_view = FieldView::new(
+            gpu,
+            fat_cam,
+            &particle_gpu.params_bind_group_layout,
+            MAX_EXTENT,
+        );
+        let panorama = Panorama::new(gpu);
+
+        ParticleSystem {
+            particle_gpu,
+            post,
+            accumulation,
+            volume,
+            scene,
+            helpers,
+            field: FieldParameters::default(),
+            field_elapsed: Duration::ZERO,
+            field_view,
+            panorama,
+            size,
+            behaviour: Behaviour::Field,
+            render_mode: RenderMode::Particles,
+            blend_mode: BlendMode::Additive,
+            render_settings: RenderSettings::default(),
+            target_settings: TargetSettings::default(),
+            msaa_texture: None,
+            profile_sort: false,
+            last_sort_time: None,
+        }
+    }
+
+    //Recreates every scene target at the scaled window size
+    pub fn resize(&mut self, gpu: &Gpu, fat_cam: &FatCamera, size: UVec2) {
+        self.size = size;
+        let (width, height) = self.target_settings.scaled_size(gpu);
+        self.resize_targets(gpu, fat_cam, width, height);
+    }
+
+    fn resize_targets(&mut self, gpu: &Gpu, fat_cam: &FatCamera, width: u32, height: u32) {
+        let sample_count = self.target_settings.sample_count;
+        self.particle_gpu
+            .resize(gpu, fat_cam, width, height, sample_count);
+        self.post.resize(gpu, width, height);
+        self.accumulation.resize(gpu, width, height, sample_count);
+        self.msaa_texture = (sample_count > 1).then(|| {
+            Texture::create_multisampled_target(
+                &gpu.device,
+                width,
+                height,
+                PostProcess::HDR_FORMAT,
+                sample_count,
+                "msaa_texture",
+            )
+        });
+    }
+
+    pub fn set_target_settings(
+        &mut self,
+        gpu: &Gpu,
+        fat_cam: &FatCamera,
+        mut settings: TargetSettings,
+    ) {
+        if !TargetSettings::SAMPLE_COUNTS.contains(&settings.sample_count) {
+            settings.sample_count = 1;
+        }
+        settings.render_scale = settings.render_scale.clamp(0.25, 4.0);
+        if settings.sample_count != self.target_settings.sample_count {
+            self.scene
+                .set_sample_count(gpu, fat_cam, settings.sample_count);
+            self.helpers
+                .set_sample_count(gpu, fat_cam, settings.sample_count);
+            self.field_view
+                .set_sample_count(gpu, fat_cam, settings.sample_count);
+        }
+        self.target_settings = settings;
+        self.resize(gpu, fat_cam, self.size);
+    }
 
-        ParticleSystem { particle_gpu, size }
+    pub fn set_render_mode(&mut self, render_mode: RenderMode) {
+        if render_mode == RenderMode::Accumulation {
+            self.accumulation.clear();
+        }
+        self.render_mode = render_mode;
+    }
+
+    //Writes the long exposure image, scaled by the current exposure. The hdr texture holds
+    //a resolved copy of the accumulation after every frame in accumulation mode.
+    pub fn export_accumulation(&self, gpu: &Gpu, path: &str) -> anyhow::Result<()> {
+        accumulation::export_png(
+            gpu,
+            &self.post.hdr_texture,
+            path,
+            self.post.settings.exposure,
+        )
     }
 
     pub fn render(&mut self, gpu: &Gpu, fat_cam: &FatCamera, time: &mut Time) {
         time.render_tick();
         self.run_compute(gpu, time);
+        if self.blend_mode == BlendMode::SortedAlpha {
+            self.run_sort(gpu, fat_cam, time);
+        }
         self.run_render(gpu, fat_cam, time);
     }
 
+    fn run_sort(&mut self, gpu: &Gpu, fat_cam: &FatCamera, time: &Time) {
+        let mut encoder = gpu
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Sort Encoder"),
+            });
+        self.particle_gpu
+            .depth_sort
+            .run(&mut encoder, fat_cam, (time.render_ticks() + 1) % 2);
+
+        if self.profile_sort {
+            //Let the simulation finish first so only the sort is timed
+            gpu.device.poll(wgpu::Maintain::Wait);
+            let start = Instant::now();
+            gpu.queue.submit([encoder.finish()]);
+            gpu.device.poll(wgpu::Maintain::Wait);
+            self.last_sort_time = Some(start.elapsed());
+        } else {
+            gpu.queue.submit([encoder.finish()]);
+            self.last_sort_time = None;
+        }
+    }
+
     fn run_render(&mut self, gpu: &Gpu, fat_cam: &FatCamera, time: &Time) {
         let output = gpu.surface.get_current_texture().unwrap();
         let view = output
@@ -65,72 +333,329 @@ impl ParticleSystem {
                 label: Some("Render Encoder"),
             });
         encoder.push_debug_group("Particle System Render");
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: None,
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.particle_gpu.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
-                    }),
-                    stencil_ops: None,
-                }),
-            });
-
-            render_pass.set_pipeline(&self.particle_gpu.render_pipeline);
-            render_pass.set_bind_group(0, &self.particle_gpu.texture_bind_group, &[]); // NEW!
-            render_pass.set_bind_group(1, &fat_cam.bind_group, &[]);
-            render_pass.set_vertex_buffer(
-                0,
-                self.particle_gpu.particle_buffers[(time.render_ticks() + 1) % 2].slice(..),
-            );
-            render_pass.set_vertex_buffer(1, self.particle_gpu.quad_vertex_buffer.slice(..));
-
-            render_pass.set_index_buffer(
-                self.particle_gpu.quad_index_buffer.slice(..),
-                wgpu::IndexFormat::Uint16,
-            ); // 1.
-            render_pass.draw_indexed(0..6, 0, 0..NUM_PARTICLES as u32);
-            // 2.
-        }
+        self.render_scene(gpu, &mut encoder, fat_cam, time);
         encoder.pop_debug_group();
+        self.post.run(gpu, &mut encoder, &view);
         gpu.queue.submit([encoder.finish()]);
         output.present();
     }
 
+    //Everything up to the finished hdr texture, seen through whatever fat_cam's bind group holds
+    fn render_scene(
+        &mut self,
+        gpu: &Gpu,
+        encoder: &mut wgpu::CommandEncoder,
+        fat_cam: &FatCamera,
+        time: &Time,
+    ) {
+        self.particle_gpu
+            .write_render_parameters(gpu, &self.render_settings);
+        let depth_view = &self.particle_gpu.depth_texture.view;
+        let hdr_view = &self.post.hdr_texture.view;
+        //With MSAA the particle pass resolves into the hdr texture
+        let resolve_target = self.msaa_texture.as_ref().map(|_| hdr_view);
+        self.scene.depth_prepass(encoder, fat_cam, depth_view);
+        match self.render_mode {
+            RenderMode::Particles => {
+                let target = match &self.msaa_texture {
+                    Some(msaa_texture) => &msaa_texture.view,
+                    None => hdr_view,
+                };
+                self.scene.draw(encoder, fat_cam, target, depth_view);
+                self.helpers.draw(
+                    gpu,
+                    encoder,
+                    fat_cam,
+                    target,
+                    depth_view,
+                    &self.render_settings.clip,
+                );
+                self.field_view.update(
+                    gpu,
+                    encoder,
+                    &self.field,
+                    &self.particle_gpu.colliders.colliders,
+                    &self.particle_gpu.params_bind_group,
+                );
+                self.field_view.draw(encoder, fat_cam, target, depth_view);
+                self.draw_particles(
+                    encoder,
+                    fat_cam,
+                    time,
+                    target,
+                    resolve_target,
+                    wgpu::LoadOp::Load,
+                );
+            }
+            //Geometry only occludes here, shading it would burn it into the exposure
+            RenderMode::Accumulation => {
+                self.accumulation.decay(encoder, fat_cam);
+                let target = &self.accumulation.texture;
+                self.draw_particles(
+                    encoder,
+                    fat_cam,
+                    time,
+                    &target.view,
+                    resolve_target,
+                    wgpu::LoadOp::Load,
+                );
+                if resolve_target.is_none() {
+                    encoder.copy_texture_to_texture(
+                        target.texture.as_image_copy(),
+                        self.post.hdr_texture.texture.as_image_copy(),
+                        target.size,
+                    );
+                }
+            }
+            RenderMode::Volume => {
+                self.volume.splat(
+                    gpu,
+                    encoder,
+                    &self.particle_gpu.particle_buffers,
+                    (time.render_ticks() + 1) % 2,
+                    NUM_PARTICLES,
+                    &self.render_settings.clip,
+                );
+                self.volume
+                    .draw(encoder, fat_cam, &self.post.hdr_texture.view);
+            }
+        }
+    }
+
+    //Renders one offline view and copies the hdr result into destination
+    fn render_view(
+        &mut self,
+        gpu: &Gpu,
+        fat_cam: &FatCamera,
+        time: &Time,
+        matrices: &CameraMatrices,
+        destination: wgpu::ImageCopyTexture,
+    ) {
+        fat_cam.write_matrices(gpu, matrices);
+        let mut encoder = gpu
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Panorama View Encoder"),
+            });
+        if self.blend_mode == BlendMode::SortedAlpha {
+            self.particle_gpu
+                .depth_sort
+                .run(&mut encoder, fat_cam, (time.render_ticks() + 1) % 2);
+        }
+        self.render_scene(gpu, &mut encoder, fat_cam, time);
+        encoder.copy_texture_to_texture(
+            self.post.hdr_texture.texture.as_image_copy(),
+            destination,
+            self.post.hdr_texture.size,
+        );
+        gpu.queue.submit([encoder.finish()]);
+    }
+
+    //Renders the current frame again in the panorama format and saves it like the
+    //accumulation export. Accumulation mode renders plain particles, the exposure
+    //would smear across the views otherwise.
+    pub fn export_panorama(
+        &mut self,
+        gpu: &Gpu,
+        fat_cam: &FatCamera,
+        time: &Time,
+        path: &str,
+    ) -> anyhow::Result<()> {
+        let render_mode = self.render_mode;
+        if render_mode == RenderMode::Accumulation {
+            self.render_mode = RenderMode::Particles;
+        }
+        let settings = self.panorama.settings;
+        let output = match settings.format {
+            PanoramaFormat::Stereo => {
+                let (width, height) = self.target_settings.scaled_size(gpu);
+                let output = Texture::create_render_target(
+                    &gpu.device,
+                    width * 2,
+                    height,
+                    PostProcess::HDR_FORMAT,
+                    "stereo_output",
+                );
+                for (eye, offset) in [-0.5, 0.5].into_iter().enumerate() {
+                    let matrices = panorama::eye_matrices(
+                        &fat_cam.camera,
+                        &fat_cam.projection,
+                        offset * settings.eye_separation,
+                        settings.convergence,
+                    );
+                    let destination = wgpu::ImageCopyTexture {
+                        texture: &output.texture,
+                        mip_level: 0,
+                        origin: wgpu::Origin3d {
+                            x: eye as u32 * width,
+                            y: 0,
+                            z: 0,
+                        },
+                        aspect: wgpu::TextureAspect::All,
+                    };
+                    self.render_view(gpu, fat_cam, time, &matrices, destination);
+                }
+                output
+            }
+            PanoramaFormat::Equirect | PanoramaFormat::Fisheye => {
+                let faces = self.panorama.create_faces(gpu);
+                self.resize_targets(gpu, fat_cam, settings.face_size, settings.face_size);
+                for face in 0..Panorama::FACES {
+                    let matrices =
+                        panorama::face_matrices(&fat_cam.camera, &fat_cam.projection, face);
+                    let destination = wgpu::ImageCopyTexture {
+                        texture: &faces.texture,
+                        mip_level: 0,
+                        origin: wgpu::Origin3d {
+                            x: 0,
+                            y: 0,
+                            z: face as u32,
+                        },
+                        aspect: wgpu::TextureAspect::All,
+                    };
+                    self.render_view(gpu, fat_cam, time, &matrices, destination);
+                }
+                self.resize(gpu, fat_cam, self.size);
+                self.panorama.reproject(gpu, &faces, &fat_cam.camera)
+            }
+        };
+        fat_cam.write_matrices(gpu, &fat_cam.matrices);
+        self.render_mode = render_mode;
+        accumulation::export_png(gpu, &output, path, self.post.settings.exposure)
+    }
+
+    //Trails and particles into target. The target has to be hdr and match the depth texture,
+    //which is only read: it holds the scene geometry from the depth prepass.
+    fn draw_particles(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        fat_cam: &FatCamera,
+        time: &Time,
+        target: &wgpu::TextureView,
+        resolve_target: Option<&wgpu::TextureView>,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target,
+                ops: wgpu::Operations { load, store: true },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.particle_gpu.depth_texture.view,
+                depth_ops: None,
+                stencil_ops: None,
+            }),
+        });
+
+        self.particle_gpu.trails.draw(&mut render_pass, fat_cam);
+
+        let buffer_index = (time.render_ticks() + 1) % 2;
+        render_pass.set_bind_group(0, &self.particle_gpu.texture_bind_group, &[]); // NEW!
+        render_pass.set_bind_group(1, &fat_cam.bind_group, &[]);
+        match self.blend_mode {
+            BlendMode::Additive => {
+                render_pass.set_pipeline(&self.particle_gpu.render_pipeline);
+                render_pass.set_vertex_buffer(
+                    0,
+                    self.particle_gpu.particle_buffers[buffer_index].slice(..),
+                );
+                render_pass.set_vertex_buffer(1, self.particle_gpu.quad_vertex_buffer.slice(..));
+            }
+            BlendMode::SortedAlpha => {
+                render_pass.set_pipeline(&self.particle_gpu.sorted_render_pipeline);
+                render_pass.set_bind_group(
+                    2,
+                    &self.particle_gpu.depth_sort.render_bind_groups[buffer_index],
+                    &[],
+                );
+                render_pass.set_vertex_buffer(0, self.particle_gpu.quad_vertex_buffer.slice(..));
+            }
+        }
+
+        render_pass.set_index_buffer(
+            self.particle_gpu.quad_index_buffer.slice(..),
+            wgpu::IndexFormat::Uint16,
+        ); // 1.
+        render_pass.draw_indexed(0..6, 0, 0..NUM_PARTICLES as u32);
+        // 2.
+    }
+
     fn run_compute(&mut self, gpu: &Gpu, time: &Time) {
+        let elapsed = time.get_elapsed();
+        self.field
+            .advance((elapsed - self.field_elapsed).as_secs_f32());
+        self.field_elapsed = elapsed;
+        self.particle_gpu.write_parameters(gpu, &self.field);
+        self.particle_gpu.forces.write(gpu);
+        self.particle_gpu.colliders.write(gpu);
+        self.particle_gpu.boids.write(gpu);
+        self.particle_gpu.fluid.write(gpu);
+        //Neighbours are only looked for one cell around
+        let neighbour_radius = match self.behaviour {
+            Behaviour::Field | Behaviour::NBody => None,
+            Behaviour::Boids => Some(self.particle_gpu.boids.settings.perception_radius),
+            Behaviour::Fluid => Some(self.particle_gpu.fluid.settings.smoothing_radius),
+        };
+        if let Some(radius) = neighbour_radius {
+            if self.particle_gpu.spatial_hash.settings.cell_size != radius {
+                self.particle_gpu.spatial_hash.set_cell_size(gpu, radius);
+            }
+        }
+        self.particle_gpu
+            .trails
+            .prepare(gpu, NUM_PARTICLES, &self.render_settings.clip);
         let mut encoder = gpu
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
+        let src = time.render_ticks() % 2;
+        if neighbour_radius.is_some() {
+            self.particle_gpu.spatial_hash.build(&mut encoder, src);
+        }
+        let nbody = &mut self.particle_gpu.nbody;
+        if self.behaviour == Behaviour::NBody && nbody.settings.solver == NBodySolver::BarnesHut {
+            match nbody.update_tree(gpu, &self.particle_gpu.particle_buffers[src]) {
+                Ok(_) => {}
+                Err(e) => println!("Barnes-Hut tree build failed: {:#}", e),
+            }
+        }
+        self.particle_gpu.nbody.write(gpu);
         encoder.push_debug_group("Particle System Compute");
         {
             let mut compute_pass =
                 encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
-            compute_pass.set_pipeline(&self.particle_gpu.compute_pipeline);
-            compute_pass.set_bind_group(
-                0,
-                &self.particle_gpu.particle_bind_groups[time.render_ticks() % 2],
-                &[],
-            );
-            compute_pass.set_bind_group(2, &self.particle_gpu.params_bind_group, &[]);
-
-            compute_pass.dispatch_workgroups(self.particle_gpu.work_group_count, 1, 1);
+            compute_pass.set_bind_group(0, &self.particle_gpu.particle_bind_groups[src], &[]);
+            compute_pass.set_bind_group(1, &self.particle_gpu.params_bind_group, &[]);
+            compute_pass.set_bind_group(2, &self.particle_gpu.trails.compute_bind_group, &[]);
+            if neighbour_radius.is_some() {
+                compute_pass.set_bind_group(
+                    3,
+                    &self.particle_gpu.spatial_hash.query_bind_group,
+                    &[],
+                );
+            } else if self.behaviour == Behaviour::NBody {
+                compute_pass.set_bind_group(3, &self.particle_gpu.nbody.bind_group, &[]);
+            }
+            let pipelines = match self.behaviour {
+                Behaviour::Field => vec![&self.particle_gpu.compute_pipeline],
+                Behaviour::Boids => vec![&self.particle_gpu.boids_pipeline],
+                //Every density has to be in before the pressure forces
+                Behaviour::Fluid => vec![
+                    &self.particle_gpu.fluid_density_pipeline,
+                    &self.particle_gpu.fluid_pipeline,
+                ],
+                Behaviour::NBody => match self.particle_gpu.nbody.settings.solver {
+                    NBodySolver::Tiled => vec![&self.particle_gpu.nbody_tiled_pipeline],
+                    NBodySolver::BarnesHut => vec![&self.particle_gpu.nbody_tree_pipeline],
+                },
+            };
+            for pipeline in pipelines {
+                compute_pass.set_pipeline(pipeline);
+                compute_pass.dispatch_workgroups(self.particle_gpu.work_group_count, 1, 1);
+            }
         }
         encoder.pop_debug_group();
         gpu.queue.submit([encoder.finish()]);
@@ -0,0 +1,246 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use super::{fullscreen_pipeline, PostProcess, PostSettings};
+use crate::app::{gpu::Gpu, texture::Texture};
+
+const BLOOM_MIP_COUNT: usize = 6;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct BloomParameters {
+    threshold: f32,
+    knee: f32,
+    filter_radius: f32,
+    _padding: f32,
+}
+
+//Multi mip bloom. The hdr image is thresholded and downsampled into a mip chain,
+//then upsampled back up additively so mips[0] holds the final (half resolution) glow.
+pub struct Bloom {
+    pub mips: Vec<Texture>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    params_buffer: wgpu::Buffer,
+    prefilter_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+    downsample_bind_groups: Vec<wgpu::BindGroup>,
+    upsample_bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl Bloom {
+    pub fn new(gpu: &Gpu, source: &Texture, width: u32, height: u32) -> Bloom {
+        let shader = gpu
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Bloom Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("../../shaders/bloom.wgsl").into()),
+            });
+
+        let bind_group_layout =
+            gpu.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Texture {
+                                multisampled: false,
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                    ],
+                    label: Some("bloom_bind_group_layout"),
+                });
+
+        let params_buffer = gpu
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Bloom Params Buffer"),
+                contents: bytemuck::bytes_of(&BloomParameters::zeroed()),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+
+        let layout = gpu
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Bloom Pipeline Layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let additive = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::REPLACE,
+        };
+        let prefilter_pipeline = fullscreen_pipeline(
+            gpu,
+            &layout,
+            &shader,
+            "fs_prefilter",
+            PostProcess::HDR_FORMAT,
+            None,
+            1,
+        );
+        let downsample_pipeline = fullscreen_pipeline(
+            gpu,
+            &layout,
+            &shader,
+            "fs_downsample",
+            PostProcess::HDR_FORMAT,
+            None,
+            1,
+        );
+        let upsample_pipeline = fullscreen_pipeline(
+            gpu,
+            &layout,
+            &shader,
+            "fs_upsample",
+            PostProcess::HDR_FORMAT,
+            Some(additive),
+            1,
+        );
+
+        let mut bloom = Bloom {
+            mips: Vec::new(),
+            bind_group_layout,
+            params_buffer,
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            downsample_bind_groups: Vec::new(),
+            upsample_bind_groups: Vec::new(),
+        };
+        bloom.resize(gpu, source, width, height);
+        bloom
+    }
+
+    pub fn resize(&mut self, gpu: &Gpu, source: &Texture, width: u32, height: u32) {
+        self.mips.clear();
+        let (mut w, mut h) = (width, height);
+        for i in 0..BLOOM_MIP_COUNT {
+            w /= 2;
+            h /= 2;
+            if w < 2 || h < 2 {
+                break;
+            }
+            self.mips.push(Texture::create_render_target(
+                &gpu.device,
+                w,
+                h,
+                PostProcess::HDR_FORMAT,
+                &format!("Bloom Mip {}", i),
+            ));
+        }
+
+        self.downsample_bind_groups = (0..self.mips.len())
+            .map(|i| {
+                let src = if i == 0 { source } else { &self.mips[i - 1] };
+                self.create_bind_group(gpu, src)
+            })
+            .collect();
+        self.upsample_bind_groups = (0..self.mips.len().saturating_sub(1))
+            .map(|i| self.create_bind_group(gpu, &self.mips[i + 1]))
+            .collect();
+    }
+
+    fn create_bind_group(&self, gpu: &Gpu, source: &Texture) -> wgpu::BindGroup {
+        gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&source.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&source.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.params_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("bloom_bind_group"),
+        })
+    }
+
+    pub fn run(&self, gpu: &Gpu, encoder: &mut wgpu::CommandEncoder, settings: &PostSettings) {
+        let params = BloomParameters {
+            threshold: settings.bloom_threshold,
+            knee: settings.bloom_threshold * 0.5,
+            filter_radius: settings.bloom_radius,
+            _padding: 0.0,
+        };
+        gpu.queue
+            .write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+
+        encoder.push_debug_group("Bloom");
+        for i in 0..self.mips.len() {
+            let pipeline = if i == 0 {
+                &self.prefilter_pipeline
+            } else {
+                &self.downsample_pipeline
+            };
+            Self::draw(
+                encoder,
+                pipeline,
+                &self.downsample_bind_groups[i],
+                &self.mips[i],
+                wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+            );
+        }
+        for i in (0..self.upsample_bind_groups.len()).rev() {
+            Self::draw(
+                encoder,
+                &self.upsample_pipeline,
+                &self.upsample_bind_groups[i],
+                &self.mips[i],
+                wgpu::LoadOp::Load,
+            );
+        }
+        encoder.pop_debug_group();
+    }
+
+    fn draw(
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+        target: &Texture,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Bloom Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &target.view,
+                resolve_target: None,
+                ops: wgpu::Operations { load, store: true },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
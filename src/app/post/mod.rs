@@ -0,0 +1,294 @@
+pub mod bloom;
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use self::bloom::Bloom;
+use super::{gpu::Gpu, texture::Texture};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ToneMapping {
+    Aces,
+    Reinhard,
+    AgX,
+}
+
+impl ToneMapping {
+    pub fn next(self) -> ToneMapping {
+        match self {
+            ToneMapping::Aces => ToneMapping::Reinhard,
+            ToneMapping::Reinhard => ToneMapping::AgX,
+            ToneMapping::AgX => ToneMapping::Aces,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct PostSettings {
+    pub exposure: f32,
+    pub tone_mapping: ToneMapping,
+    pub bloom_intensity: f32,
+    pub bloom_threshold: f32,
+    //Upsample tent radius in texels
+    pub bloom_radius: f32,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        PostSettings {
+            exposure: 1.0,
+            tone_mapping: ToneMapping::Aces,
+            bloom_intensity: 0.3,
+            bloom_threshold: 1.0,
+            bloom_radius: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct TonemapParameters {
+    exposure: f32,
+    bloom_intensity: f32,
+    mode: u32,
+    output_srgb: u32,
+}
+
+//Owns the hdr scene target and turns it into the final swapchain image:
+//bloom mip chain, then exposure + tone mapping.
+pub struct PostProcess {
+    pub settings: PostSettings,
+    pub hdr_texture: Texture,
+    pub bloom: Bloom,
+    tonemap_pipeline: wgpu::RenderPipeline,
+    tonemap_bind_group_layout: wgpu::BindGroupLayout,
+    tonemap_bind_group: wgpu::BindGroup,
+    params_buffer: wgpu::Buffer,
+}
+
+impl PostProcess {
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    pub fn new(gpu: &Gpu, width: u32, height: u32) -> PostProcess {
+        let hdr_texture = Texture::create_render_target(
+            &gpu.device,
+            width,
+            height,
+            Self::HDR_FORMAT,
+            "hdr_texture",
+        );
+        let bloom = Bloom::new(gpu, &hdr_texture, width, height);
+
+        let params_buffer = gpu
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Tonemap Params Buffer"),
+                contents: bytemuck::bytes_of(&TonemapParameters::zeroed()),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let tonemap_bind_group_layout =
+            gpu.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        texture_entry(0),
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                            count: None,
+                        },
+                        texture_entry(2),
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 3,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                    ],
+                    label: Some("tonemap_bind_group_layout"),
+                });
+
+        let shader = gpu
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Tonemap Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("../../shaders/tonemap.wgsl").into()),
+            });
+        let layout = gpu
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Tonemap Pipeline Layout"),
+                bind_group_layouts: &[&tonemap_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let tonemap_pipeline =
+            fullscreen_pipeline(gpu, &layout, &shader, "fs_main", gpu.config.format, None, 1);
+
+        let tonemap_bind_group = Self::create_tonemap_bind_group(
+            gpu,
+            &tonemap_bind_group_layout,
+            &hdr_texture,
+            &bloom,
+            &params_buffer,
+        );
+
+        PostProcess {
+            settings: PostSettings::default(),
+            hdr_texture,
+            bloom,
+            tonemap_pipeline,
+            tonemap_bind_group_layout,
+            tonemap_bind_group,
+            params_buffer,
+        }
+    }
+
+    fn create_tonemap_bind_group(
+        gpu: &Gpu,
+        layout: &wgpu::BindGroupLayout,
+        hdr_texture: &Texture,
+        bloom: &Bloom,
+        params_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        //Tiny windows can end up without any bloom mips, fall back to the hdr image
+        let bloom_view = match bloom.mips.first() {
+            Some(mip) => &mip.view,
+            None => &hdr_texture.view,
+        };
+        gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&hdr_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&hdr_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(bloom_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: params_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("tonemap_bind_group"),
+        })
+    }
+
+    pub fn resize(&mut self, gpu: &Gpu, width: u32, height: u32) {
+        self.hdr_texture = Texture::create_render_target(
+            &gpu.device,
+            width,
+            height,
+            Self::HDR_FORMAT,
+            "hdr_texture",
+        );
+        self.bloom.resize(gpu, &self.hdr_texture, width, height);
+        self.tonemap_bind_group = Self::create_tonemap_bind_group(
+            gpu,
+            &self.tonemap_bind_group_layout,
+            &self.hdr_texture,
+            &self.bloom,
+            &self.params_buffer,
+        );
+    }
+
+    //Bloom + tone map the hdr texture into output_view
+    pub fn run(
+        &self,
+        gpu: &Gpu,
+        encoder: &mut wgpu::CommandEncoder,
+        output_view: &wgpu::TextureView,
+    ) {
+        let use_bloom = self.settings.bloom_intensity > 0.0 && !self.bloom.mips.is_empty();
+        if use_bloom {
+            self.bloom.run(gpu, encoder, &self.settings);
+        }
+
+        let params = TonemapParameters {
+            exposure: self.settings.exposure,
+            bloom_intensity: if use_bloom {
+                self.settings.bloom_intensity
+            } else {
+                0.0
+            },
+            mode: self.settings.tone_mapping as u32,
+            output_srgb: gpu.config.format.describe().srgb as u32,
+        };
+        gpu.queue
+            .write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tonemap Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.tonemap_pipeline);
+        render_pass.set_bind_group(0, &self.tonemap_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+//Pipeline for a pass that draws a single fullscreen triangle from the shader's vs_main.
+pub fn fullscreen_pipeline(
+    gpu: &Gpu,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    fs_entry_point: &str,
+    format: wgpu::TextureFormat,
+    blend: Option<wgpu::BlendState>,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    gpu.device
+        .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(fs_entry_point),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: fs_entry_point,
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        })
+}
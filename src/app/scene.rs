@@ -0,0 +1,303 @@
+//Opaque scene geometry drawn around the particles. A depth only prepass fills the depth
+//texture first so particles can fade out where they intersect the geometry.
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::{InnerSpace, Vector3};
+use wgpu::util::DeviceExt;
+
+use super::{camera::FatCamera, gpu::Gpu, post::PostProcess, texture::Texture};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct SceneVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub color: [f32; 3],
+}
+
+pub struct Mesh {
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub num_indices: u32,
+}
+
+impl Mesh {
+    pub fn new(gpu: &Gpu, vertices: &[SceneVertex], indices: &[u32], label: &str) -> Mesh {
+        let vertex_buffer = gpu
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Vertex Buffer", label)),
+                contents: bytemuck::cast_slice(vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+        let index_buffer = gpu
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Index Buffer", label)),
+                contents: bytemuck::cast_slice(indices),
+                usage: wgpu::BufferUsages::INDEX,
+            });
+        Mesh {
+            vertex_buffer,
+            index_buffer,
+            num_indices: indices.len() as u32,
+        }
+    }
+
+    //Horizontal square facing +y
+    pub fn plane(gpu: &Gpu, center: Vector3<f32>, half_size: f32, color: [f32; 3]) -> Mesh {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        push_quad(
+            &mut vertices,
+            &mut indices,
+            center,
+            Vector3::unit_y(),
+            Vector3::unit_x() * half_size,
+            -Vector3::unit_z() * half_size,
+            color,
+        );
+        Mesh::new(gpu, &vertices, &indices, "Plane")
+    }
+
+    pub fn cuboid(
+        gpu: &Gpu,
+        center: Vector3<f32>,
+        half_extents: Vector3<f32>,
+        color: [f32; 3],
+    ) -> Mesh {
+        let (x, y, z) = (
+            Vector3::unit_x() * half_extents.x,
+            Vector3::unit_y() * half_extents.y,
+            Vector3::unit_z() * half_extents.z,
+        );
+        //Normal offset plus the two in plane axes, wound counter clockwise seen from outside
+        let faces = [
+            (x, -z, y),
+            (-x, z, y),
+            (y, x, -z),
+            (-y, x, z),
+            (z, x, y),
+            (-z, -x, y),
+        ];
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for (offset, u, v) in faces {
+            push_quad(
+                &mut vertices,
+                &mut indices,
+                center + offset,
+                offset.normalize(),
+                u,
+                v,
+                color,
+            );
+        }
+        Mesh::new(gpu, &vertices, &indices, "Cuboid")
+    }
+}
+
+fn push_quad(
+    vertices: &mut Vec<SceneVertex>,
+    indices: &mut Vec<u32>,
+    center: Vector3<f32>,
+    normal: Vector3<f32>,
+    u: Vector3<f32>,
+    v: Vector3<f32>,
+    color: [f32; 3],
+) {
+    let base = vertices.len() as u32;
+    for (su, sv) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+        vertices.push(SceneVertex {
+            position: (center + u * su + v * sv).into(),
+            normal: normal.into(),
+            color,
+        });
+    }
+    indices.extend([base, base + 1, base + 2, base + 2, base + 3, base]);
+}
+
+pub struct Scene {
+    pub enabled: bool,
+    pub meshes: Vec<Mesh>,
+    depth_pipeline: wgpu::RenderPipeline,
+    color_pipeline: wgpu::RenderPipeline,
+}
+
+impl Scene {
+    pub fn new(gpu: &Gpu, fat_cam: &FatCamera) -> Scene {
+        let (depth_pipeline, color_pipeline) = Self::build_pipelines(gpu, fat_cam, 1);
+
+        //A floor below the particles and a block they flow through
+        let meshes = vec![
+            Mesh::plane(gpu, Vector3::new(0.0, -100.0, 0.0), 300.0, [0.3, 0.3, 0.3]),
+            Mesh::cuboid(
+                gpu,
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(20.0, 20.0, 20.0),
+                [0.6, 0.4, 0.3],
+            ),
+        ];
+
+        Scene {
+            enabled: false,
+            meshes,
+            depth_pipeline,
+            color_pipeline,
+        }
+    }
+
+    //Depth prepass and colour pipelines for targets with the given sample count
+    fn build_pipelines(
+        gpu: &Gpu,
+        fat_cam: &FatCamera,
+        sample_count: u32,
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        let shader = gpu
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Scene Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/scene.wgsl").into()),
+            });
+        let layout = gpu
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Scene Pipeline Layout"),
+                bind_group_layouts: &[&fat_cam.bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        (
+            Self::build_pipeline(gpu, &layout, &shader, false, sample_count),
+            Self::build_pipeline(gpu, &layout, &shader, true, sample_count),
+        )
+    }
+
+    pub fn set_sample_count(&mut self, gpu: &Gpu, fat_cam: &FatCamera, sample_count: u32) {
+        let (depth_pipeline, color_pipeline) = Self::build_pipelines(gpu, fat_cam, sample_count);
+        self.depth_pipeline = depth_pipeline;
+        self.color_pipeline = color_pipeline;
+    }
+
+    //The depth prepass writes depth only. The colour pass then shades exactly the
+    //surfaces that won it, without writing depth again.
+    fn build_pipeline(
+        gpu: &Gpu,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        color: bool,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let targets = [Some(wgpu::ColorTargetState {
+            format: PostProcess::HDR_FORMAT,
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL,
+        })];
+        gpu.device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(if color {
+                    "Scene Color Pipeline"
+                } else {
+                    "Scene Depth Pipeline"
+                }),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: "vs_main",
+                    buffers: &[wgpu::VertexBufferLayout {
+                        array_stride: std::mem::size_of::<SceneVertex>() as u64,
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                        attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3, 2 => Float32x3],
+                    }],
+                },
+                fragment: color.then(|| wgpu::FragmentState {
+                    module: shader,
+                    entry_point: "fs_main",
+                    targets: &targets,
+                }),
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled: !color,
+                    depth_compare: if color {
+                        wgpu::CompareFunction::LessEqual
+                    } else {
+                        wgpu::CompareFunction::Less
+                    },
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+            })
+    }
+
+    fn draw_meshes<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if !self.enabled {
+            return;
+        }
+        for mesh in &self.meshes {
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed(0..mesh.num_indices, 0, 0..1);
+        }
+    }
+
+    //Clears the depth texture and fills it with the opaque geometry
+    pub fn depth_prepass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        fat_cam: &FatCamera,
+        depth_view: &wgpu::TextureView,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Scene Depth Prepass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&self.depth_pipeline);
+        render_pass.set_bind_group(0, &fat_cam.bind_group, &[]);
+        self.draw_meshes(&mut render_pass);
+    }
+
+    //Clears target and shades the geometry laid down by the prepass
+    pub fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        fat_cam: &FatCamera,
+        target: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Scene Color Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: None,
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&self.color_pipeline);
+        render_pass.set_bind_group(0, &fat_cam.bind_group, &[]);
+        self.draw_meshes(&mut render_pass);
+    }
+}
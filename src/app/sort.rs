@@ -0,0 +1,219 @@
+//Per frame back to front sort of particle indices by view depth, so alpha blending
+//can draw through the sorted index list instead of buffer order.
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use super::{camera::FatCamera, gpu::Gpu};
+
+const SORT_GROUP_SIZE: u32 = 256;
+//Dynamic uniform offsets have to be aligned to this
+const STEP_STRIDE: u64 = 256;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct SortStep {
+    j: u32,
+    k: u32,
+}
+
+//The bind groups are what keep the entries buffer alive
+pub struct DepthSort {
+    //Bind groups for drawing particles through the sorted entries, one per particle buffer
+    pub render_bind_groups: Vec<wgpu::BindGroup>,
+    pub render_bind_group_layout: wgpu::BindGroupLayout,
+    sort_bind_groups: Vec<wgpu::BindGroup>,
+    step_bind_group: wgpu::BindGroup,
+    keys_pipeline: wgpu::ComputePipeline,
+    step_pipeline: wgpu::ComputePipeline,
+    step_count: u32,
+    padded_count: u32,
+}
+
+impl DepthSort {
+    pub fn new(
+        gpu: &Gpu,
+        fat_cam: &FatCamera,
+        particle_buffers: &Vec<wgpu::Buffer>,
+        num_particles: usize,
+    ) -> DepthSort {
+        //Bitonic sort needs a power of two element count
+        let padded_count = (num_particles as u32).next_power_of_two();
+        let entries_buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sort Entries Buffer"),
+            size: padded_count as u64 * 8,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let mut steps: Vec<u8> = Vec::new();
+        let mut k = 2;
+        while k <= padded_count {
+            let mut j = k / 2;
+            while j > 0 {
+                let mut step = bytemuck::bytes_of(&SortStep { j, k }).to_vec();
+                step.resize(STEP_STRIDE as usize, 0);
+                steps.extend(step);
+                j /= 2;
+            }
+            k *= 2;
+        }
+        let step_count = (steps.len() as u64 / STEP_STRIDE) as u32;
+        let step_buffer = gpu
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Sort Step Buffer"),
+                contents: &steps,
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+
+        let storage_entry = |binding, read_only, visibility| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let sort_bind_group_layout =
+            gpu.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        storage_entry(0, true, wgpu::ShaderStages::COMPUTE),
+                        storage_entry(1, false, wgpu::ShaderStages::COMPUTE),
+                    ],
+                    label: Some("sort_bind_group_layout"),
+                });
+        let render_bind_group_layout =
+            gpu.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        storage_entry(0, true, wgpu::ShaderStages::VERTEX),
+                        storage_entry(1, true, wgpu::ShaderStages::VERTEX),
+                    ],
+                    label: Some("sorted_render_bind_group_layout"),
+                });
+        let step_bind_group_layout =
+            gpu.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: true,
+                            min_binding_size: wgpu::BufferSize::new(8),
+                        },
+                        count: None,
+                    }],
+                    label: Some("sort_step_bind_group_layout"),
+                });
+
+        let mut sort_bind_groups = Vec::new();
+        let mut render_bind_groups = Vec::new();
+        for buffer in particle_buffers {
+            let entries = [
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: entries_buffer.as_entire_binding(),
+                },
+            ];
+            sort_bind_groups.push(gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &sort_bind_group_layout,
+                entries: &entries,
+                label: Some("sort_bind_group"),
+            }));
+            render_bind_groups.push(gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &render_bind_group_layout,
+                entries: &entries,
+                label: Some("sorted_render_bind_group"),
+            }));
+        }
+        let step_bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &step_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &step_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(8),
+                }),
+            }],
+            label: Some("sort_step_bind_group"),
+        });
+
+        let shader = gpu
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Sort Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/sort.wgsl").into()),
+            });
+        let layout = gpu
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("depth sort"),
+                bind_group_layouts: &[
+                    &sort_bind_group_layout,
+                    &fat_cam.bind_group_layout,
+                    &step_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+        let create_pipeline = |entry_point| {
+            gpu.device
+                .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some(entry_point),
+                    layout: Some(&layout),
+                    module: &shader,
+                    entry_point,
+                })
+        };
+        let keys_pipeline = create_pipeline("compute_keys");
+        let step_pipeline = create_pipeline("bitonic_step");
+
+        DepthSort {
+            render_bind_groups,
+            render_bind_group_layout,
+            sort_bind_groups,
+            step_bind_group,
+            keys_pipeline,
+            step_pipeline,
+            step_count,
+            padded_count,
+        }
+    }
+
+    //Sorts the particles in particle_buffers[buffer_index] by distance to the camera
+    pub fn run(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        fat_cam: &FatCamera,
+        buffer_index: usize,
+    ) {
+        let work_group_count = self.padded_count.div_ceil(SORT_GROUP_SIZE);
+        encoder.push_debug_group("Depth Sort");
+        {
+            let mut compute_pass =
+                encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            compute_pass.set_pipeline(&self.keys_pipeline);
+            compute_pass.set_bind_group(0, &self.sort_bind_groups[buffer_index], &[]);
+            compute_pass.set_bind_group(1, &fat_cam.bind_group, &[]);
+            compute_pass.set_bind_group(2, &self.step_bind_group, &[0]);
+            compute_pass.dispatch_workgroups(work_group_count, 1, 1);
+
+            compute_pass.set_pipeline(&self.step_pipeline);
+            for step in 0..self.step_count {
+                let offset = (step as u64 * STEP_STRIDE) as u32;
+                compute_pass.set_bind_group(2, &self.step_bind_group, &[offset]);
+                compute_pass.dispatch_workgroups(work_group_count, 1, 1);
+            }
+        }
+        encoder.pop_debug_group();
+    }
+}
@@ -0,0 +1,822 @@
+//GPU spatial hash of the particles, for behaviours that need their neighbours. Particles
+//are counting sorted by the hash of the cell they're in, so the particles of a cell sit
+//together in sorted_indices. Shaders append spatial_hash_common.wgsl and spatial_hash.wgsl
+//and bind query_bind_group at group 3 to look neighbours up, see spatial_hash.wgsl.
+
+use std::sync::mpsc;
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use cgmath::{InnerSpace, Vector3};
+use rand::Rng;
+use wgpu::util::DeviceExt;
+
+use super::{gpu::Gpu, particle_gpu::Particle};
+
+const HASH_GROUP_SIZE: u32 = 256;
+//Cells per block of the prefix sum, HASH_SCAN_BLOCK in spatial_hash_common.wgsl
+const SCAN_BLOCK: u32 = 256;
+const QUERY_GROUP_SIZE: u32 = 64;
+//Neighbours validate reads back per particle, particles with more only get their count
+//compared
+const MAX_QUERY_NEIGHBOURS: u32 = 64;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SpatialHashSettings {
+    //Largest neighbour distance the hash can answer for
+    pub cell_size: f32,
+    //Rounded up to a power of two of at least 256
+    pub table_size: u32,
+}
+
+impl Default for SpatialHashSettings {
+    fn default() -> Self {
+        SpatialHashSettings {
+            cell_size: 8.0,
+            table_size: 1 << 20,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct SpatialHashParameters {
+    cell_size: f32,
+    table_size: u32,
+    particle_count: u32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct NeighbourQuery {
+    radius: f32,
+    query_count: u32,
+    max_neighbours: u32,
+    _padding: u32,
+}
+
+//How the gpu neighbour lists compare to the brute force ones
+#[derive(Debug, Copy, Clone)]
+pub struct NeighbourReport {
+    pub queries: usize,
+    //Queries whose lists differ in any way
+    pub mismatched: usize,
+    //Neighbours the brute force search found and the hash didn't, and the other way round
+    pub missing: usize,
+    pub extra: usize,
+    //Queries with too many neighbours to read back, only their counts got compared
+    pub truncated: usize,
+    //Cells whose count, start or particles differ from counting_sort's
+    pub bad_cells: usize,
+    pub mean_neighbours: f32,
+}
+
+//Same as hash_cell_coord and hash_cell in spatial_hash_common.wgsl
+pub fn hash_cell_coord(p: Vector3<f32>, cell_size: f32) -> Vector3<i32> {
+    (p / cell_size).map(|x| x.floor() as i32)
+}
+
+//table_size has to be a power of two
+pub fn hash_cell(coord: Vector3<i32>, table_size: u32) -> u32 {
+    let c = coord.map(|x| x as u32);
+    (c.x.wrapping_mul(73856093) ^ c.y.wrapping_mul(19349663) ^ c.z.wrapping_mul(83492791))
+        & (table_size - 1)
+}
+
+//Counting sort of the positions by cell, what the build passes work out. Gives the cell
+//counts then the cell starts, laid out like SpatialHash::cells, and the sorted indices.
+//Particles sharing a cell stay in order here, the gpu's atomics leave them in any order.
+pub fn counting_sort(
+    positions: &[Vector3<f32>],
+    settings: &SpatialHashSettings,
+) -> (Vec<u32>, Vec<u32>) {
+    let table_size = settings.table_size as usize;
+    let hashes: Vec<usize> = positions
+        .iter()
+        .map(|&p| hash_cell(hash_cell_coord(p, settings.cell_size), settings.table_size) as usize)
+        .collect();
+    let mut cells = vec![0u32; table_size * 2];
+    for &cell in &hashes {
+        cells[cell] += 1;
+    }
+    let mut start = 0;
+    for cell in 0..table_size {
+        cells[table_size + cell] = start;
+        start += cells[cell];
+    }
+    let mut next = cells[table_size..].to_vec();
+    let mut sorted_indices = vec![0u32; positions.len()];
+    for (index, &cell) in hashes.iter().enumerate() {
+        sorted_indices[next[cell] as usize] = index as u32;
+        next[cell] += 1;
+    }
+    (cells, sorted_indices)
+}
+
+//Indices of every particle within radius of positions[index], in order
+pub fn brute_force_neighbours(positions: &[Vector3<f32>], index: usize, radius: f32) -> Vec<u32> {
+    let p = positions[index];
+    positions
+        .iter()
+        .enumerate()
+        .filter(|&(j, q)| j != index && (q - p).magnitude2() <= radius * radius)
+        .map(|(j, _)| j as u32)
+        .collect()
+}
+
+pub struct SpatialHash {
+    pub settings: SpatialHashSettings,
+    pub particle_count: u32,
+    params_buffer: wgpu::Buffer,
+    //The cell counts, then the cell starts
+    pub cells: wgpu::Buffer,
+    pub sorted_indices: wgpu::Buffer,
+    //One per particle buffer, the hash is built from the particles in it
+    particle_bind_groups: Vec<wgpu::BindGroup>,
+    build_bind_group: wgpu::BindGroup,
+    pub query_bind_group_layout: wgpu::BindGroupLayout,
+    pub query_bind_group: wgpu::BindGroup,
+    clear_pipeline: wgpu::ComputePipeline,
+    count_pipeline: wgpu::ComputePipeline,
+    scan_blocks_pipeline: wgpu::ComputePipeline,
+    scan_block_sums_pipeline: wgpu::ComputePipeline,
+    add_offsets_pipeline: wgpu::ComputePipeline,
+    scatter_pipeline: wgpu::ComputePipeline,
+}
+
+impl SpatialHash {
+    pub fn new(
+        gpu: &Gpu,
+        particle_buffers: &[wgpu::Buffer],
+        num_particles: usize,
+        settings: SpatialHashSettings,
+    ) -> SpatialHash {
+        let settings = SpatialHashSettings {
+            table_size: settings.table_size.max(SCAN_BLOCK).next_power_of_two(),
+            ..settings
+        };
+        let particle_count = num_particles as u32;
+        let params_buffer = gpu
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Spatial Hash Parameters"),
+                contents: bytemuck::bytes_of(&SpatialHashParameters {
+                    cell_size: settings.cell_size,
+                    table_size: settings.table_size,
+                    particle_count,
+                    _padding: 0,
+                }),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+        let create_buffer = |label, count: u32| {
+            gpu.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: count.max(1) as u64 * 4,
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })
+        };
+        //Read back by validate, like sorted_indices
+        let cells = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Hash Cells"),
+            size: settings.table_size as u64 * 2 * 4,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let block_sums = create_buffer("Hash Block Sums", settings.table_size / SCAN_BLOCK);
+        let particle_cells = create_buffer("Hash Particle Cells", particle_count);
+        let particle_ranks = create_buffer("Hash Particle Ranks", particle_count);
+        let sorted_indices = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Hash Sorted Indices"),
+            size: particle_count.max(1) as u64 * 4,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let particle_bind_group_layout =
+            gpu.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[storage_entry(0, true)],
+                    label: Some("hash_particle_bind_group_layout"),
+                });
+        let build_bind_group_layout =
+            gpu.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        uniform_entry(0),
+                        storage_entry(1, false),
+                        storage_entry(2, false),
+                        storage_entry(3, false),
+                        storage_entry(4, false),
+                        storage_entry(5, false),
+                        storage_entry(6, false),
+                    ],
+                    label: Some("hash_build_bind_group_layout"),
+                });
+        let query_bind_group_layout =
+            gpu.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        uniform_entry(0),
+                        storage_entry(1, true),
+                        storage_entry(2, true),
+                    ],
+                    label: Some("hash_query_bind_group_layout"),
+                });
+
+        let particle_bind_groups = particle_buffers
+            .iter()
+            .map(|buffer| {
+                gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &particle_bind_group_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }],
+                    label: Some("hash_particle_bind_group"),
+                })
+            })
+            .collect();
+        //The build passes see the counts and the starts as separate arrays
+        let half_cells = |half: u64| {
+            let size = settings.table_size as u64 * 4;
+            wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                buffer: &cells,
+                offset: half * size,
+                size: wgpu::BufferSize::new(size),
+            })
+        };
+        let build_resources = [
+            params_buffer.as_entire_binding(),
+            half_cells(0),
+            half_cells(1),
+            block_sums.as_entire_binding(),
+            particle_cells.as_entire_binding(),
+            particle_ranks.as_entire_binding(),
+            sorted_indices.as_entire_binding(),
+        ];
+        let build_entries: Vec<wgpu::BindGroupEntry> = build_resources
+            .into_iter()
+            .enumerate()
+            .map(|(binding, resource)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource,
+            })
+            .collect();
+        let build_bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &build_bind_group_layout,
+            entries: &build_entries,
+            label: Some("hash_build_bind_group"),
+        });
+        let query_buffers = [&params_buffer, &cells, &sorted_indices];
+        let query_entries: Vec<wgpu::BindGroupEntry> = query_buffers
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect();
+        let query_bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &query_bind_group_layout,
+            entries: &query_entries,
+            label: Some("hash_query_bind_group"),
+        });
+
+        let shader = gpu
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Spatial Hash Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    format!(
+                        "{}\n{}",
+                        include_str!("../shaders/spatial_hash_common.wgsl"),
+                        include_str!("../shaders/spatial_hash_build.wgsl")
+                    )
+                    .into(),
+                ),
+            });
+        let layout = gpu
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("spatial hash"),
+                bind_group_layouts: &[&particle_bind_group_layout, &build_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let pipeline = |entry_point| {
+            gpu.device
+                .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some(entry_point),
+                    layout: Some(&layout),
+                    module: &shader,
+                    entry_point,
+                })
+        };
+
+        SpatialHash {
+            settings,
+            particle_count,
+            params_buffer,
+            cells,
+            sorted_indices,
+            particle_bind_groups,
+            build_bind_group,
+            query_bind_group_layout,
+            query_bind_group,
+            clear_pipeline: pipeline("clear_cells"),
+            count_pipeline: pipeline("count_particles"),
+            scan_blocks_pipeline: pipeline("scan_blocks"),
+            scan_block_sums_pipeline: pipeline("scan_block_sums"),
+            add_offsets_pipeline: pipeline("add_block_offsets"),
+            scatter_pipeline: pipeline("scatter_particles"),
+        }
+    }
+
+    //The table size is fixed at creation, only the cell size can change
+    pub fn set_cell_size(&mut self, gpu: &Gpu, cell_size: f32) {
+        self.settings.cell_size = cell_size;
+        gpu.queue.write_buffer(
+            &self.params_buffer,
+            0,
+            bytemuck::bytes_of(&SpatialHashParameters {
+                cell_size,
+                table_size: self.settings.table_size,
+                particle_count: self.particle_count,
+                _padding: 0,
+            }),
+        );
+    }
+
+    //Hashes the particles in particle_buffers[buffer_index]
+    pub fn build(&self, encoder: &mut wgpu::CommandEncoder, buffer_index: usize) {
+        let cell_groups = self.settings.table_size / HASH_GROUP_SIZE;
+        let particle_groups = self.particle_count.div_ceil(HASH_GROUP_SIZE);
+        encoder.push_debug_group("Spatial Hash");
+        {
+            let mut compute_pass =
+                encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            compute_pass.set_bind_group(0, &self.particle_bind_groups[buffer_index], &[]);
+            compute_pass.set_bind_group(1, &self.build_bind_group, &[]);
+            let passes = [
+                (&self.clear_pipeline, cell_groups),
+                (&self.count_pipeline, particle_groups),
+                (
+                    &self.scan_blocks_pipeline,
+                    self.settings.table_size / SCAN_BLOCK,
+                ),
+                (&self.scan_block_sums_pipeline, 1),
+                (&self.add_offsets_pipeline, cell_groups),
+                (&self.scatter_pipeline, particle_groups),
+            ];
+            for (pipeline, groups) in passes {
+                compute_pass.set_pipeline(pipeline);
+                compute_pass.dispatch_workgroups(groups, 1, 1);
+            }
+        }
+        encoder.pop_debug_group();
+    }
+
+    //Builds the hash from particle_buffers[buffer_index], compares its cells with
+    //counting_sort's, then the neighbour lists of queries random particles within the cell
+    //size against a brute force search on the cpu. Reads the whole particle buffer back, so
+    //it's slow.
+    pub fn validate(
+        &self,
+        gpu: &Gpu,
+        particle_buffers: &[wgpu::Buffer],
+        buffer_index: usize,
+        queries: usize,
+        rng: &mut impl Rng,
+    ) -> Result<NeighbourReport> {
+        if self.particle_count == 0 {
+            bail!("there are no particles to query");
+        }
+        let query_indices: Vec<u32> = (0..queries.max(1))
+            .map(|_| rng.gen_range(0..self.particle_count))
+            .collect();
+        let radius = self.settings.cell_size;
+        let stride = MAX_QUERY_NEIGHBOURS + 1;
+
+        let query_buffer = gpu
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Neighbour Query"),
+                contents: bytemuck::bytes_of(&NeighbourQuery {
+                    radius,
+                    query_count: query_indices.len() as u32,
+                    max_neighbours: MAX_QUERY_NEIGHBOURS,
+                    _padding: 0,
+                }),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+        let indices_buffer = gpu
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Neighbour Query Indices"),
+                contents: bytemuck::cast_slice(&query_indices),
+                usage: wgpu::BufferUsages::STORAGE,
+            });
+        let lists_size = query_indices.len() as u64 * stride as u64 * 4;
+        let lists_buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Neighbour Lists"),
+            size: lists_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let particles_size = self.particle_count as u64 * std::mem::size_of::<Particle>() as u64;
+        let lists_readback = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Neighbour Lists Readback"),
+            size: lists_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let particles_readback = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle Readback"),
+            size: particles_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let cells_size = self.settings.table_size as u64 * 2 * 4;
+        let cells_readback = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Hash Cells Readback"),
+            size: cells_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let sorted_size = self.particle_count.max(1) as u64 * 4;
+        let sorted_readback = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Hash Sorted Indices Readback"),
+            size: sorted_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let shader = gpu
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Neighbour Query Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    format!(
+                        "{}\n{}\n{}",
+                        include_str!("../shaders/spatial_hash_common.wgsl"),
+                        include_str!("../shaders/spatial_hash.wgsl"),
+                        include_str!("../shaders/neighbour_query.wgsl")
+                    )
+                    .into(),
+                ),
+            });
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let query_layout = gpu
+            .device
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    storage_entry(0, true),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    storage_entry(2, true),
+                    storage_entry(3, false),
+                ],
+                label: Some("neighbour_query_bind_group_layout"),
+            });
+        let query_bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &query_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: particle_buffers[buffer_index].as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: query_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: indices_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: lists_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("neighbour_query_bind_group"),
+        });
+        //The query only uses groups 0 and 3, the ones between stay empty
+        let empty_layout = gpu
+            .device
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[],
+                label: None,
+            });
+        let layout = gpu
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("neighbour query"),
+                bind_group_layouts: &[
+                    &query_layout,
+                    &empty_layout,
+                    &empty_layout,
+                    &self.query_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+        let pipeline = gpu
+            .device
+            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("query_neighbours"),
+                layout: Some(&layout),
+                module: &shader,
+                entry_point: "query_neighbours",
+            });
+        let empty_bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &empty_layout,
+            entries: &[],
+            label: None,
+        });
+
+        let mut encoder = gpu
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Neighbour Validation Encoder"),
+            });
+        self.build(&mut encoder, buffer_index);
+        {
+            let mut compute_pass =
+                encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            compute_pass.set_pipeline(&pipeline);
+            compute_pass.set_bind_group(0, &query_bind_group, &[]);
+            compute_pass.set_bind_group(1, &empty_bind_group, &[]);
+            compute_pass.set_bind_group(2, &empty_bind_group, &[]);
+            compute_pass.set_bind_group(3, &self.query_bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                (query_indices.len() as u32).div_ceil(QUERY_GROUP_SIZE),
+                1,
+                1,
+            );
+        }
+        encoder.copy_buffer_to_buffer(&lists_buffer, 0, &lists_readback, 0, lists_size);
+        encoder.copy_buffer_to_buffer(
+            &particle_buffers[buffer_index],
+            0,
+            &particles_readback,
+            0,
+            particles_size,
+        );
+        encoder.copy_buffer_to_buffer(&self.cells, 0, &cells_readback, 0, cells_size);
+        encoder.copy_buffer_to_buffer(&self.sorted_indices, 0, &sorted_readback, 0, sorted_size);
+        gpu.queue.submit([encoder.finish()]);
+
+        let (sender, receiver) = mpsc::channel();
+        let readbacks = [
+            &lists_readback,
+            &particles_readback,
+            &cells_readback,
+            &sorted_readback,
+        ];
+        for buffer in readbacks {
+            let sender = sender.clone();
+            buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    sender.send(result).ok();
+                });
+        }
+        gpu.device.poll(wgpu::Maintain::Wait);
+        for _ in readbacks {
+            receiver.recv()??;
+        }
+
+        let positions: Vec<Vector3<f32>> = {
+            let data = particles_readback.slice(..).get_mapped_range();
+            bytemuck::cast_slice::<u8, Particle>(&data)
+                .iter()
+                .map(|particle| {
+                    Vector3::new(
+                        particle.position[0],
+                        particle.position[1],
+                        particle.position[2],
+                    )
+                })
+                .collect()
+        };
+        particles_readback.unmap();
+        let lists: Vec<u32> =
+            bytemuck::cast_slice(&lists_readback.slice(..).get_mapped_range()).to_vec();
+        lists_readback.unmap();
+        let cells: Vec<u32> =
+            bytemuck::cast_slice(&cells_readback.slice(..).get_mapped_range()).to_vec();
+        cells_readback.unmap();
+        let sorted_indices: Vec<u32> =
+            bytemuck::cast_slice(&sorted_readback.slice(..).get_mapped_range()).to_vec();
+        sorted_readback.unmap();
+        let (expected_cells, expected_sorted) = counting_sort(&positions, &self.settings);
+        let table_size = self.settings.table_size as usize;
+        //The gpu's particles can be in any order within a cell
+        let cell_particles = |cells: &[u32], sorted: &[u32], cell: usize| {
+            let start = cells[table_size + cell] as usize;
+            let mut particles = sorted[start..start + cells[cell] as usize].to_vec();
+            particles.sort_unstable();
+            particles
+        };
+        let bad_cells = (0..table_size)
+            .filter(|&cell| {
+                cells[cell] != expected_cells[cell]
+                    || cells[table_size + cell] != expected_cells[table_size + cell]
+                    || cell_particles(&cells, &sorted_indices, cell)
+                        != cell_particles(&expected_cells, &expected_sorted, cell)
+            })
+            .count();
+
+        let mut report = NeighbourReport {
+            queries: query_indices.len(),
+            mismatched: 0,
+            missing: 0,
+            extra: 0,
+            truncated: 0,
+            bad_cells,
+            mean_neighbours: 0.0,
+        };
+        let mut total_neighbours = 0;
+        for (q, &index) in query_indices.iter().enumerate() {
+            let expected = brute_force_neighbours(&positions, index as usize, radius);
+            let list = &lists[q * stride as usize..(q + 1) * stride as usize];
+            let count = list[0] as usize;
+            total_neighbours += expected.len();
+            if count > MAX_QUERY_NEIGHBOURS as usize {
+                report.truncated += 1;
+                if count != expected.len() {
+                    report.mismatched += 1;
+                }
+                continue;
+            }
+            let mut found = list[1..1 + count].to_vec();
+            found.sort_unstable();
+            let missing = expected
+                .iter()
+                .filter(|j| found.binary_search(j).is_err())
+                .count();
+            let extra = found
+                .iter()
+                .filter(|j| expected.binary_search(j).is_err())
+                .count();
+            if missing > 0 || extra > 0 || count != expected.len() {
+                report.mismatched += 1;
+            }
+            report.missing += missing;
+            report.extra += extra;
+        }
+        report.mean_neighbours = total_neighbours as f32 / report.queries as f32;
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    //Neighbours of positions[index] found through the hash, like neighbour_cells and
+    //cell_range in spatial_hash.wgsl
+    fn hash_neighbours(
+        positions: &[Vector3<f32>],
+        settings: &SpatialHashSettings,
+        cells: &[u32],
+        sorted_indices: &[u32],
+        index: usize,
+        radius: f32,
+    ) -> Vec<u32> {
+        let p = positions[index];
+        let table_size = settings.table_size as usize;
+        let center = hash_cell_coord(p, settings.cell_size);
+        let mut hashes = Vec::new();
+        for i in 0..27 {
+            let offset = Vector3::new(i % 3, i / 3 % 3, i / 9) - Vector3::new(1, 1, 1);
+            let cell = hash_cell(center + offset, settings.table_size) as usize;
+            if !hashes.contains(&cell) {
+                hashes.push(cell);
+            }
+        }
+        let mut found: Vec<u32> = hashes
+            .into_iter()
+            .flat_map(|cell| {
+                let start = cells[table_size + cell] as usize;
+                sorted_indices[start..start + cells[cell] as usize]
+                    .iter()
+                    .copied()
+            })
+            .filter(|&j| {
+                j as usize != index && (positions[j as usize] - p).magnitude2() <= radius * radius
+            })
+            .collect();
+        found.sort_unstable();
+        found
+    }
+
+    fn random_positions(rng: &mut StdRng, count: usize, extent: f32) -> Vec<Vector3<f32>> {
+        (0..count)
+            .map(|_| {
+                Vector3::new(
+                    rng.gen_range(-extent..extent),
+                    rng.gen_range(-extent..extent),
+                    rng.gen_range(-extent..extent),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn counting_sort_groups_particles_by_cell() {
+        let positions = random_positions(&mut StdRng::seed_from_u64(47), 5000, 60.0);
+        let settings = SpatialHashSettings {
+            cell_size: 4.0,
+            table_size: 1024,
+        };
+        let (cells, sorted_indices) = counting_sort(&positions, &settings);
+        let table_size = settings.table_size as usize;
+        let mut seen = vec![false; positions.len()];
+        for cell in 0..table_size {
+            let start = cells[table_size + cell] as usize;
+            for &index in &sorted_indices[start..start + cells[cell] as usize] {
+                assert_eq!(
+                    hash_cell(
+                        hash_cell_coord(positions[index as usize], settings.cell_size),
+                        settings.table_size
+                    ),
+                    cell as u32
+                );
+                assert!(!seen[index as usize], "{} sorted twice", index);
+                seen[index as usize] = true;
+            }
+        }
+        assert!(seen.iter().all(|&seen| seen));
+    }
+
+    #[test]
+    fn hash_neighbours_match_brute_force() {
+        let positions = random_positions(&mut StdRng::seed_from_u64(47), 4000, 50.0);
+        //A small table makes far away cells collide, a big one hardly ever
+        for table_size in [256, 1 << 16] {
+            let settings = SpatialHashSettings {
+                cell_size: 6.0,
+                table_size,
+            };
+            let (cells, sorted_indices) = counting_sort(&positions, &settings);
+            for index in (0..positions.len()).step_by(7) {
+                for radius in [settings.cell_size, settings.cell_size * 0.5] {
+                    assert_eq!(
+                        hash_neighbours(
+                            &positions,
+                            &settings,
+                            &cells,
+                            &sorted_indices,
+                            index,
+                            radius
+                        ),
+                        brute_force_neighbours(&positions, index, radius),
+                        "particle {} with a table of {}",
+                        index,
+                        table_size
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,380 @@
+//Position history for a subset of particles, drawn as fading ribbons or line strips.
+//The compute pass writes into the ring buffer, this module owns it and draws it.
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use super::{
+    camera::FatCamera,
+    clip::{ClipParameters, ClipSettings},
+    gpu::Gpu,
+    post::PostProcess,
+    texture::Texture,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TrailMode {
+    Ribbon,
+    Line,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct TrailSettings {
+    pub enabled: bool,
+    pub mode: TrailMode,
+    //Number of positions kept per particle
+    pub length: u32,
+    //Only the first `count` particles get a trail
+    pub count: u32,
+    pub width: f32,
+    pub alpha: f32,
+}
+
+impl Default for TrailSettings {
+    fn default() -> Self {
+        TrailSettings {
+            enabled: false,
+            mode: TrailMode::Ribbon,
+            length: 32,
+            count: 65536,
+            width: 0.3,
+            alpha: 0.6,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct TrailParameters {
+    length: u32,
+    count: u32,
+    head: u32,
+    reset: u32,
+    width: f32,
+    alpha: f32,
+    _padding: [f32; 2],
+    clip: ClipParameters,
+}
+
+pub struct Trails {
+    pub settings: TrailSettings,
+    pub compute_bind_group_layout: wgpu::BindGroupLayout,
+    pub compute_bind_group: wgpu::BindGroup,
+    render_bind_group_layout: wgpu::BindGroupLayout,
+    render_bind_group: wgpu::BindGroup,
+    history_buffer: wgpu::Buffer,
+    params_buffer: wgpu::Buffer,
+    ribbon_pipeline: wgpu::RenderPipeline,
+    line_pipeline: wgpu::RenderPipeline,
+    //Length and count the history buffer was allocated for, zero when trails are off
+    allocated: (u32, u32),
+    head: u32,
+    needs_reset: bool,
+}
+
+impl Trails {
+    pub fn new(gpu: &Gpu, fat_cam: &FatCamera) -> Trails {
+        let layout_entries = |visibility, read_only| {
+            [
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ]
+        };
+        let compute_bind_group_layout =
+            gpu.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &layout_entries(wgpu::ShaderStages::COMPUTE, false),
+                    label: Some("trail_compute_bind_group_layout"),
+                });
+        let render_bind_group_layout =
+            gpu.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &layout_entries(wgpu::ShaderStages::VERTEX, true),
+                    label: Some("trail_render_bind_group_layout"),
+                });
+
+        let params_buffer = gpu
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Trail Params Buffer"),
+                contents: bytemuck::bytes_of(&TrailParameters::zeroed()),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+        let history_buffer = Self::create_history_buffer(gpu, 0, 0);
+        let (compute_bind_group, render_bind_group) = Self::create_bind_groups(
+            gpu,
+            &compute_bind_group_layout,
+            &render_bind_group_layout,
+            &history_buffer,
+            &params_buffer,
+        );
+
+        let (ribbon_pipeline, line_pipeline) =
+            Self::build_pipelines(gpu, fat_cam, &render_bind_group_layout, 1);
+
+        Trails {
+            settings: TrailSettings::default(),
+            compute_bind_group_layout,
+            compute_bind_group,
+            render_bind_group_layout,
+            render_bind_group,
+            history_buffer,
+            params_buffer,
+            ribbon_pipeline,
+            line_pipeline,
+            allocated: (0, 0),
+            head: 0,
+            needs_reset: false,
+        }
+    }
+
+    fn create_history_buffer(gpu: &Gpu, length: u32, count: u32) -> wgpu::Buffer {
+        //Storage bindings can't be empty, keep a single point around while trails are off
+        let size = (length as u64 * count as u64).max(1) * 16;
+        gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Trail History Buffer"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_bind_groups(
+        gpu: &Gpu,
+        compute_layout: &wgpu::BindGroupLayout,
+        render_layout: &wgpu::BindGroupLayout,
+        history_buffer: &wgpu::Buffer,
+        params_buffer: &wgpu::Buffer,
+    ) -> (wgpu::BindGroup, wgpu::BindGroup) {
+        let entries = [
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: history_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: params_buffer.as_entire_binding(),
+            },
+        ];
+        let compute = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: compute_layout,
+            entries: &entries,
+            label: Some("trail_compute_bind_group"),
+        });
+        let render = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: render_layout,
+            entries: &entries,
+            label: Some("trail_render_bind_group"),
+        });
+        (compute, render)
+    }
+
+    //Ribbon and line pipelines for targets with the given sample count
+    fn build_pipelines(
+        gpu: &Gpu,
+        fat_cam: &FatCamera,
+        render_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        let shader = gpu
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Trail Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    format!(
+                        "{}\n{}",
+                        include_str!("../shaders/clip.wgsl"),
+                        include_str!("../shaders/trails.wgsl")
+                    )
+                    .into(),
+                ),
+            });
+        let layout = gpu
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Trail Pipeline Layout"),
+                bind_group_layouts: &[render_bind_group_layout, &fat_cam.bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let ribbon_pipeline = Self::build_pipeline(
+            gpu,
+            &layout,
+            &shader,
+            "vs_ribbon",
+            wgpu::PrimitiveTopology::TriangleStrip,
+            sample_count,
+        );
+        let line_pipeline = Self::build_pipeline(
+            gpu,
+            &layout,
+            &shader,
+            "vs_line",
+            wgpu::PrimitiveTopology::LineStrip,
+            sample_count,
+        );
+        (ribbon_pipeline, line_pipeline)
+    }
+
+    pub fn set_sample_count(&mut self, gpu: &Gpu, fat_cam: &FatCamera, sample_count: u32) {
+        let (ribbon_pipeline, line_pipeline) =
+            Self::build_pipelines(gpu, fat_cam, &self.render_bind_group_layout, sample_count);
+        self.ribbon_pipeline = ribbon_pipeline;
+        self.line_pipeline = line_pipeline;
+    }
+
+    fn build_pipeline(
+        gpu: &Gpu,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        vs_entry_point: &str,
+        topology: wgpu::PrimitiveTopology,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        gpu.device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(vs_entry_point),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: vs_entry_point,
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: PostProcess::HDR_FORMAT,
+                        blend: Some(wgpu::BlendState {
+                            color: wgpu::BlendComponent {
+                                src_factor: wgpu::BlendFactor::SrcAlpha,
+                                dst_factor: wgpu::BlendFactor::One,
+                                operation: wgpu::BlendOperation::Add,
+                            },
+                            alpha: wgpu::BlendComponent {
+                                src_factor: wgpu::BlendFactor::One,
+                                dst_factor: wgpu::BlendFactor::One,
+                                operation: wgpu::BlendOperation::Add,
+                            },
+                        }),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    //Hidden behind scene geometry, trails are too thin to need soft fading
+                    depth_compare: wgpu::CompareFunction::LessEqual,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+            })
+    }
+
+    //Bytes the history needs for the given settings
+    pub fn memory_bytes(settings: &TrailSettings) -> u64 {
+        settings.length as u64 * settings.count as u64 * 16
+    }
+
+    //Reallocates the history if the settings changed, then advances the ring buffer head.
+    //Call once per simulation step, before the compute pass.
+    pub fn prepare(&mut self, gpu: &Gpu, num_particles: usize, clip: &ClipSettings) {
+        let max_bytes = gpu.device.limits().max_storage_buffer_binding_size as u64;
+        self.settings.length = self.settings.length.max(2);
+        self.settings.count = self.settings.count.min(num_particles as u32);
+        while Self::memory_bytes(&self.settings) > max_bytes {
+            self.settings.count /= 2;
+        }
+
+        let wanted = if self.settings.enabled {
+            (self.settings.length, self.settings.count)
+        } else {
+            (0, 0)
+        };
+        if wanted != self.allocated {
+            self.history_buffer = Self::create_history_buffer(gpu, wanted.0, wanted.1);
+            let (compute, render) = Self::create_bind_groups(
+                gpu,
+                &self.compute_bind_group_layout,
+                &self.render_bind_group_layout,
+                &self.history_buffer,
+                &self.params_buffer,
+            );
+            self.compute_bind_group = compute;
+            self.render_bind_group = render;
+            self.allocated = wanted;
+            self.head = 0;
+            self.needs_reset = true;
+            if self.settings.enabled {
+                println!(
+                    "Trail memory: {:.1} MB ({} particles x {} points)",
+                    Self::memory_bytes(&self.settings) as f64 / (1024.0 * 1024.0),
+                    wanted.1,
+                    wanted.0
+                );
+            }
+        } else if self.allocated.0 > 0 {
+            self.head = (self.head + 1) % self.allocated.0;
+        }
+
+        let params = TrailParameters {
+            length: self.allocated.0,
+            count: self.allocated.1,
+            head: self.head,
+            reset: self.needs_reset as u32,
+            width: self.settings.width,
+            alpha: self.settings.alpha,
+            _padding: [0.0; 2],
+            clip: ClipParameters::from(clip),
+        };
+        gpu.queue
+            .write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+        self.needs_reset = false;
+    }
+
+    //Restarts every trail from its particle next step, for when the particles jump
+    pub fn reset(&mut self) {
+        self.needs_reset = true;
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, fat_cam: &'a FatCamera) {
+        let (length, count) = self.allocated;
+        if length == 0 || count == 0 {
+            return;
+        }
+        let (pipeline, vertex_count) = match self.settings.mode {
+            TrailMode::Ribbon => (&self.ribbon_pipeline, length * 2),
+            TrailMode::Line => (&self.line_pipeline, length),
+        };
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &self.render_bind_group, &[]);
+        render_pass.set_bind_group(1, &fat_cam.bind_group, &[]);
+        render_pass.draw(0..vertex_count, 0..count);
+    }
+}
@@ -0,0 +1,482 @@
+//Smoke like view of the simulation. Particles are splatted into a density (and average speed)
+//grid with fixed point atomics, resolved into a 3d texture and ray marched in a fullscreen pass.
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use super::{
+    camera::FatCamera,
+    clip::{ClipParameters, ClipSettings},
+    gpu::Gpu,
+    post::{fullscreen_pipeline, PostProcess},
+};
+
+const SPLAT_GROUP_SIZE: u32 = 256;
+const RESOLVE_GROUP_SIZE: u32 = 4;
+const VOLUME_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+#[derive(Debug, Copy, Clone)]
+pub struct VolumeSettings {
+    //Cells along each axis of the grid
+    pub resolution: u32,
+    //Ray march samples per pixel
+    pub steps: u32,
+    //Extinction per particle per world unit
+    pub density_scale: f32,
+    pub absorption: f32,
+    pub emission: f32,
+    //Colour by average cell speed instead of density
+    pub use_velocity: bool,
+    //Speed that maps to color_high
+    pub speed_scale: f32,
+    pub color_low: [f32; 3],
+    pub color_high: [f32; 3],
+}
+
+impl Default for VolumeSettings {
+    fn default() -> Self {
+        VolumeSettings {
+            resolution: 128,
+            steps: 128,
+            density_scale: 0.01,
+            absorption: 1.0,
+            emission: 1.0,
+            use_velocity: true,
+            speed_scale: 0.02,
+            color_low: [0.1, 0.2, 0.8],
+            color_high: [1.0, 0.5, 0.1],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct VolumeParameters {
+    color_low: [f32; 4],
+    color_high: [f32; 4],
+    resolution: u32,
+    steps: u32,
+    use_velocity: u32,
+    num_particles: u32,
+    extent: f32,
+    density_scale: f32,
+    absorption: f32,
+    emission: f32,
+    speed_scale: f32,
+    _padding: [f32; 3],
+    clip: ClipParameters,
+}
+
+pub struct Volume {
+    pub settings: VolumeSettings,
+    //Half size of the cube the grid covers, centred on the origin
+    pub extent: f32,
+    compute_bind_group_layout: wgpu::BindGroupLayout,
+    //One per particle buffer. The bind groups are what keep the grid buffers and texture alive.
+    compute_bind_groups: Vec<wgpu::BindGroup>,
+    render_bind_group_layout: wgpu::BindGroupLayout,
+    render_bind_group: wgpu::BindGroup,
+    params_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    splat_pipeline: wgpu::ComputePipeline,
+    resolve_pipeline: wgpu::ComputePipeline,
+    render_pipeline: wgpu::RenderPipeline,
+    allocated_resolution: u32,
+}
+
+impl Volume {
+    pub fn new(
+        gpu: &Gpu,
+        fat_cam: &FatCamera,
+        particle_buffers: &Vec<wgpu::Buffer>,
+        extent: f32,
+    ) -> Volume {
+        let settings = VolumeSettings::default();
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uniform_entry = |binding, visibility| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let compute_bind_group_layout =
+            gpu.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        storage_entry(0, true),
+                        storage_entry(1, false),
+                        storage_entry(2, false),
+                        uniform_entry(3, wgpu::ShaderStages::COMPUTE),
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 4,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::StorageTexture {
+                                access: wgpu::StorageTextureAccess::WriteOnly,
+                                format: VOLUME_FORMAT,
+                                view_dimension: wgpu::TextureViewDimension::D3,
+                            },
+                            count: None,
+                        },
+                    ],
+                    label: Some("volume_compute_bind_group_layout"),
+                });
+        let render_bind_group_layout =
+            gpu.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Texture {
+                                multisampled: false,
+                                view_dimension: wgpu::TextureViewDimension::D3,
+                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                            count: None,
+                        },
+                        uniform_entry(2, wgpu::ShaderStages::FRAGMENT),
+                    ],
+                    label: Some("volume_render_bind_group_layout"),
+                });
+
+        let params_buffer = gpu
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Volume Params Buffer"),
+                contents: bytemuck::bytes_of(&VolumeParameters::zeroed()),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+        let sampler = gpu.device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let splat_shader = gpu
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Volume Splat Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    format!(
+                        "{}\n{}",
+                        include_str!("../shaders/clip.wgsl"),
+                        include_str!("../shaders/volume_splat.wgsl")
+                    )
+                    .into(),
+                ),
+            });
+        let compute_layout = gpu
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Volume Compute Pipeline Layout"),
+                bind_group_layouts: &[&compute_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let compute_pipeline = |entry_point| {
+            gpu.device
+                .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some(entry_point),
+                    layout: Some(&compute_layout),
+                    module: &splat_shader,
+                    entry_point,
+                })
+        };
+        let splat_pipeline = compute_pipeline("splat");
+        let resolve_pipeline = compute_pipeline("resolve");
+
+        let render_shader = gpu
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Volume Render Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    format!(
+                        "{}\n{}",
+                        include_str!("../shaders/clip.wgsl"),
+                        include_str!("../shaders/volume_render.wgsl")
+                    )
+                    .into(),
+                ),
+            });
+        let render_layout = gpu
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Volume Render Pipeline Layout"),
+                bind_group_layouts: &[&render_bind_group_layout, &fat_cam.bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let render_pipeline = fullscreen_pipeline(
+            gpu,
+            &render_layout,
+            &render_shader,
+            "fs_main",
+            PostProcess::HDR_FORMAT,
+            None,
+            1,
+        );
+
+        let (density_buffer, speed_buffer, texture_view) =
+            Self::create_grid(gpu, settings.resolution);
+        let compute_bind_groups = Self::create_compute_bind_groups(
+            gpu,
+            &compute_bind_group_layout,
+            particle_buffers,
+            &density_buffer,
+            &speed_buffer,
+            &params_buffer,
+            &texture_view,
+        );
+        let render_bind_group = Self::create_render_bind_group(
+            gpu,
+            &render_bind_group_layout,
+            &texture_view,
+            &sampler,
+            &params_buffer,
+        );
+
+        Volume {
+            settings,
+            extent,
+            compute_bind_group_layout,
+            compute_bind_groups,
+            render_bind_group_layout,
+            render_bind_group,
+            params_buffer,
+            sampler,
+            splat_pipeline,
+            resolve_pipeline,
+            render_pipeline,
+            allocated_resolution: settings.resolution,
+        }
+    }
+
+    //Atomic density and speed sums plus the texture they are resolved into
+    fn create_grid(gpu: &Gpu, resolution: u32) -> (wgpu::Buffer, wgpu::Buffer, wgpu::TextureView) {
+        let cells = resolution as u64 * resolution as u64 * resolution as u64;
+        let create_buffer = |label| {
+            gpu.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: cells * 4,
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })
+        };
+        let texture = gpu.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("volume_texture"),
+            size: wgpu::Extent3d {
+                width: resolution,
+                height: resolution,
+                depth_or_array_layers: resolution,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: VOLUME_FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        (
+            create_buffer("Volume Density Buffer"),
+            create_buffer("Volume Speed Buffer"),
+            view,
+        )
+    }
+
+    fn create_compute_bind_groups(
+        gpu: &Gpu,
+        layout: &wgpu::BindGroupLayout,
+        particle_buffers: &Vec<wgpu::Buffer>,
+        density_buffer: &wgpu::Buffer,
+        speed_buffer: &wgpu::Buffer,
+        params_buffer: &wgpu::Buffer,
+        texture_view: &wgpu::TextureView,
+    ) -> Vec<wgpu::BindGroup> {
+        particle_buffers
+            .iter()
+            .map(|particle_buffer| {
+                gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: particle_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: density_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: speed_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: params_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 4,
+                            resource: wgpu::BindingResource::TextureView(texture_view),
+                        },
+                    ],
+                    label: Some("volume_compute_bind_group"),
+                })
+            })
+            .collect()
+    }
+
+    fn create_render_bind_group(
+        gpu: &Gpu,
+        layout: &wgpu::BindGroupLayout,
+        texture_view: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+        params_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: params_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("volume_render_bind_group"),
+        })
+    }
+
+    //Bytes used by the grid buffers and the resolved texture
+    pub fn memory_bytes(resolution: u32) -> u64 {
+        let cells = resolution as u64 * resolution as u64 * resolution as u64;
+        cells * (4 + 4 + 8)
+    }
+
+    //Splats the particles of particle_buffers[buffer_index] and resolves them into the volume
+    //texture, weighting each by its clip visibility. Reallocates the grid first if the
+    //resolution changed.
+    pub fn splat(
+        &mut self,
+        gpu: &Gpu,
+        encoder: &mut wgpu::CommandEncoder,
+        particle_buffers: &Vec<wgpu::Buffer>,
+        buffer_index: usize,
+        num_particles: usize,
+        clip: &ClipSettings,
+    ) {
+        let max_bytes = gpu.device.limits().max_storage_buffer_binding_size as u64;
+        let max_dimension = gpu.device.limits().max_texture_dimension_3d;
+        self.settings.resolution = self.settings.resolution.clamp(8, max_dimension);
+        while (self.settings.resolution as u64).pow(3) * 4 > max_bytes {
+            self.settings.resolution /= 2;
+        }
+        self.settings.steps = self.settings.steps.max(1);
+
+        if self.settings.resolution != self.allocated_resolution {
+            let (density_buffer, speed_buffer, texture_view) =
+                Self::create_grid(gpu, self.settings.resolution);
+            self.compute_bind_groups = Self::create_compute_bind_groups(
+                gpu,
+                &self.compute_bind_group_layout,
+                particle_buffers,
+                &density_buffer,
+                &speed_buffer,
+                &self.params_buffer,
+                &texture_view,
+            );
+            self.render_bind_group = Self::create_render_bind_group(
+                gpu,
+                &self.render_bind_group_layout,
+                &texture_view,
+                &self.sampler,
+                &self.params_buffer,
+            );
+            self.allocated_resolution = self.settings.resolution;
+            println!(
+                "Volume memory: {:.1} MB ({}^3 cells)",
+                Self::memory_bytes(self.allocated_resolution) as f64 / (1024.0 * 1024.0),
+                self.allocated_resolution
+            );
+        }
+
+        let s = &self.settings;
+        let params = VolumeParameters {
+            color_low: [s.color_low[0], s.color_low[1], s.color_low[2], 1.0],
+            color_high: [s.color_high[0], s.color_high[1], s.color_high[2], 1.0],
+            resolution: self.allocated_resolution,
+            steps: s.steps,
+            use_velocity: s.use_velocity as u32,
+            num_particles: num_particles as u32,
+            extent: self.extent,
+            density_scale: s.density_scale,
+            absorption: s.absorption,
+            emission: s.emission,
+            speed_scale: s.speed_scale,
+            _padding: [0.0; 3],
+            clip: ClipParameters::from(clip),
+        };
+        gpu.queue
+            .write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+
+        let mut compute_pass =
+            encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+        compute_pass.set_bind_group(0, &self.compute_bind_groups[buffer_index], &[]);
+        compute_pass.set_pipeline(&self.splat_pipeline);
+        compute_pass.dispatch_workgroups((num_particles as u32).div_ceil(SPLAT_GROUP_SIZE), 1, 1);
+        let groups = self.allocated_resolution.div_ceil(RESOLVE_GROUP_SIZE);
+        compute_pass.set_pipeline(&self.resolve_pipeline);
+        compute_pass.dispatch_workgroups(groups, groups, groups);
+    }
+
+    //Ray marches the volume into target, replacing its contents
+    pub fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        fat_cam: &FatCamera,
+        target: &wgpu::TextureView,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Volume Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, &self.render_bind_group, &[]);
+        render_pass.set_bind_group(1, &fat_cam.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
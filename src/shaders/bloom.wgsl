@@ -0,0 +1,84 @@
+struct BloomParameters {
+    threshold: f32,
+    knee: f32,
+    filter_radius: f32,
+    _padding: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> params: BloomParameters;
+
+//Fullscreen triangle generated from the vertex index, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+//13 tap downsample filter (Jimenez 2014). Keeps the bloom stable when bright particles move.
+fn downsample13(uv: vec2<f32>) -> vec3<f32> {
+    let t = 1.0 / vec2<f32>(textureDimensions(t_source));
+    let a = textureSample(t_source, s_source, uv + t * vec2<f32>(-2.0, -2.0)).rgb;
+    let b = textureSample(t_source, s_source, uv + t * vec2<f32>(0.0, -2.0)).rgb;
+    let c = textureSample(t_source, s_source, uv + t * vec2<f32>(2.0, -2.0)).rgb;
+    let d = textureSample(t_source, s_source, uv + t * vec2<f32>(-2.0, 0.0)).rgb;
+    let e = textureSample(t_source, s_source, uv).rgb;
+    let f = textureSample(t_source, s_source, uv + t * vec2<f32>(2.0, 0.0)).rgb;
+    let g = textureSample(t_source, s_source, uv + t * vec2<f32>(-2.0, 2.0)).rgb;
+    let h = textureSample(t_source, s_source, uv + t * vec2<f32>(0.0, 2.0)).rgb;
+    let i = textureSample(t_source, s_source, uv + t * vec2<f32>(2.0, 2.0)).rgb;
+    let j = textureSample(t_source, s_source, uv + t * vec2<f32>(-1.0, -1.0)).rgb;
+    let k = textureSample(t_source, s_source, uv + t * vec2<f32>(1.0, -1.0)).rgb;
+    let l = textureSample(t_source, s_source, uv + t * vec2<f32>(-1.0, 1.0)).rgb;
+    let m = textureSample(t_source, s_source, uv + t * vec2<f32>(1.0, 1.0)).rgb;
+
+    return e * 0.125 + (a + c + g + i) * 0.03125 + (b + d + f + h) * 0.0625 + (j + k + l + m) * 0.125;
+}
+
+//Soft knee threshold so the bloom fades in instead of popping
+fn prefilter(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(color.r, max(color.g, color.b));
+    var soft = brightness - params.threshold + params.knee;
+    soft = clamp(soft, 0.0, 2.0 * params.knee);
+    soft = soft * soft / (4.0 * params.knee + 0.0001);
+    let contribution = max(soft, brightness - params.threshold) / max(brightness, 0.0001);
+    return color * contribution;
+}
+
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(prefilter(downsample13(in.uv)), 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample13(in.uv), 1.0);
+}
+
+//3x3 tent filter, blended additively onto the next larger mip
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let t = params.filter_radius / vec2<f32>(textureDimensions(t_source));
+    var sum = textureSample(t_source, s_source, in.uv).rgb * 4.0;
+    sum += textureSample(t_source, s_source, in.uv + vec2<f32>(-t.x, 0.0)).rgb * 2.0;
+    sum += textureSample(t_source, s_source, in.uv + vec2<f32>(t.x, 0.0)).rgb * 2.0;
+    sum += textureSample(t_source, s_source, in.uv + vec2<f32>(0.0, -t.y)).rgb * 2.0;
+    sum += textureSample(t_source, s_source, in.uv + vec2<f32>(0.0, t.y)).rgb * 2.0;
+    sum += textureSample(t_source, s_source, in.uv + vec2<f32>(-t.x, -t.y)).rgb;
+    sum += textureSample(t_source, s_source, in.uv + vec2<f32>(t.x, -t.y)).rgb;
+    sum += textureSample(t_source, s_source, in.uv + vec2<f32>(-t.x, t.y)).rgb;
+    sum += textureSample(t_source, s_source, in.uv + vec2<f32>(t.x, t.y)).rgb;
+    return vec4<f32>(sum / 16.0, 1.0);
+}
@@ -0,0 +1,80 @@
+//Appended to spatial_hash_common.wgsl, spatial_hash.wgsl and sim.wgsl for
+//Behaviour::Boids. Reynolds' separation, alignment and cohesion steer each particle, then
+//the result is blended with the velocity the curl field would give it.
+
+//See BoidSettings in boids.rs
+struct BoidParameters {
+    separation: f32,
+    alignment: f32,
+    cohesion: f32,
+    perception_radius: f32,
+    max_speed: f32,
+    max_force: f32,
+    //0 flocks only, 1 follows the field
+    field_mix: f32,
+    _padding: f32,
+};
+
+@group(1) @binding(4) var<uniform> boids : BoidParameters;
+
+//Neighbours looked at per particle, dense flocks would stall the frame otherwise
+let MAX_BOID_NEIGHBOURS: u32 = 32u;
+
+//Acceleration turning velocity towards direction at full speed
+fn steer(direction: vec3<f32>, velocity: vec3<f32>) -> vec3<f32> {
+    if (dot(direction, direction) < 1e-12) {
+        return vec3<f32>(0.0);
+    }
+    return clamp_length(normalize(direction) * boids.max_speed - velocity, boids.max_force);
+}
+
+fn boid_acceleration(index: u32, p: vec3<f32>, velocity: vec3<f32>) -> vec3<f32> {
+    let radius2 = boids.perception_radius * boids.perception_radius;
+    var away = vec3<f32>(0.0);
+    var heading = vec3<f32>(0.0);
+    var center = vec3<f32>(0.0);
+    var found = 0u;
+    var cells = neighbour_cells(p);
+    for (var c = 0u; c < cells.count && found < MAX_BOID_NEIGHBOURS; c = c + 1u) {
+        let range = cell_range(cells.hashes[c]);
+        for (var k = range.x; k < range.y && found < MAX_BOID_NEIGHBOURS; k = k + 1u) {
+            let j = hash_sorted.cells[k];
+            let other = particles_src.particles[j];
+            let d = p - other.position.xyz;
+            let d2 = dot(d, d);
+            if (j != index && d2 <= radius2 && d2 > 1e-8) {
+                //Pushed away harder the closer the neighbour is
+                away = away + d / d2;
+                heading = heading + other.velocity.xyz;
+                center = center + other.position.xyz;
+                found = found + 1u;
+            }
+        }
+    }
+    if (found == 0u) {
+        return vec3<f32>(0.0);
+    }
+    let n = f32(found);
+    return steer(away, velocity) * boids.separation
+        + steer(heading / n, velocity) * boids.alignment
+        + steer(center / n - p, velocity) * boids.cohesion;
+}
+
+@compute @workgroup_size(64)
+fn boids_main(
+  @builtin(global_invocation_id) global_id : vec3<u32>,
+) {
+    let index: u32 = global_id.x;
+
+    let total = arrayLength(&particles_src.particles);
+    if (index >= total) {
+        return;
+    }
+    let part = particles_src.particles[index];
+    let p = part.position.xyz;
+
+    let velocity = part.velocity.xyz;
+    let flocking = clamp_length(velocity + boid_acceleration(index, p, velocity) * DT, boids.max_speed);
+    let blended = mix(flocking, follow_field(p, velocity), boids.field_mix);
+    advance_particle(index, part, blended + force_acceleration(p, velocity) * DT);
+}
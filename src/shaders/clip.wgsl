@@ -0,0 +1,38 @@
+//Shared by the particle, trail and volume passes, which all hide or dim the particles
+//outside the clip region. See ClipSettings in clip.rs.
+
+struct ClipParameters {
+    //Radius in w
+    center: vec4<f32>,
+    normal: vec4<f32>,
+    half_size: vec4<f32>,
+    shape: u32,
+    invert: u32,
+    dim: f32,
+    thickness: f32,
+};
+
+let CLIP_OFF: u32 = 0u;
+let CLIP_PLANE: u32 = 1u;
+let CLIP_SLAB: u32 = 2u;
+let CLIP_BOX: u32 = 3u;
+
+//1 where the clip region shows particles, clip.dim elsewhere
+fn clip_visibility(clip: ClipParameters, p: vec3<f32>) -> f32 {
+    if (clip.shape == CLIP_OFF) {
+        return 1.0;
+    }
+    let d = p - clip.center.xyz;
+    var inside = length(d) <= clip.center.w;
+    if (clip.shape == CLIP_PLANE) {
+        inside = dot(d, clip.normal.xyz) <= 0.0;
+    } else if (clip.shape == CLIP_SLAB) {
+        inside = abs(dot(d, clip.normal.xyz)) <= clip.thickness * 0.5;
+    } else if (clip.shape == CLIP_BOX) {
+        inside = all(abs(d) <= clip.half_size.xyz);
+    }
+    if (inside != (clip.invert != 0u)) {
+        return 1.0;
+    }
+    return clip.dim;
+}
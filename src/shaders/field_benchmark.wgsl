@@ -0,0 +1,32 @@
+//Appended to sim.wgsl. Evaluates the curl at scattered points with both methods so they can
+//be timed against each other.
+
+struct BenchmarkOutput {
+    values: array<vec4<f32>>,
+};
+
+@group(0) @binding(2) var<storage, read_write> benchmark_output: BenchmarkOutput;
+
+//Spreads the samples over the bounds with a cheap hash
+fn benchmark_point(index: u32) -> vec3<f32> {
+    let h = vec3<u32>(index * 73856093u, index * 19349663u, index * 83492791u) >> vec3<u32>(16u);
+    return (vec3<f32>(h) / 65535.0 * 2.0 - 1.0) * max_extent;
+}
+
+@compute @workgroup_size(64)
+fn benchmark_analytic(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let index = global_id.x;
+    if (index >= arrayLength(&benchmark_output.values)) {
+        return;
+    }
+    benchmark_output.values[index] = vec4<f32>(curl(benchmark_point(index)), 0.0);
+}
+
+@compute @workgroup_size(64)
+fn benchmark_finite_difference(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let index = global_id.x;
+    if (index >= arrayLength(&benchmark_output.values)) {
+        return;
+    }
+    benchmark_output.values[index] = vec4<f32>(curl_finite_difference(benchmark_point(index)), 0.0);
+}
@@ -0,0 +1,140 @@
+//Appended to sim.wgsl, so field_velocity here is exactly the field the particles follow.
+//The bindings continue after the simulation's own group 0 bindings, the field parameters
+//are the simulation's group 1.
+
+struct FieldViewParameters {
+    //Corner of the arrow grid in xyz, cell spacing in w
+    grid_origin: vec4<f32>,
+    //Centre of the lic plane, half its size in w
+    plane_center: vec4<f32>,
+    plane_u: vec4<f32>,
+    plane_v: vec4<f32>,
+    grid_resolution: u32,
+    lic_resolution: u32,
+    lic_steps: u32,
+    //Speed at the top of the colour ramp
+    speed_scale: f32,
+    lic_alpha: f32,
+};
+
+struct FieldLineVertex {
+    position: vec4<f32>,
+    color: vec4<f32>,
+};
+
+struct FieldLineVertices {
+    vertices: array<FieldLineVertex>,
+};
+
+//Field across the lic plane: xy in plane coordinates, speed in z
+struct PlaneVectors {
+    vectors: array<vec4<f32>>,
+};
+
+@group(0) @binding(2) var<uniform> field_view: FieldViewParameters;
+@group(0) @binding(3) var<storage, read_write> arrows: FieldLineVertices;
+@group(0) @binding(4) var<storage, read_write> plane_vectors: PlaneVectors;
+@group(0) @binding(5) var lic_image: texture_storage_2d<rgba16float, write>;
+
+let ARROW_VERTICES: u32 = 6u;
+
+//Blue through green to red, the same ramp as FieldView::speed_color
+fn speed_color(speed: f32) -> vec3<f32> {
+    let t = clamp(speed / field_view.speed_scale, 0.0, 1.0);
+    if (t < 0.5) {
+        return mix(vec3<f32>(0.1, 0.2, 1.0), vec3<f32>(0.1, 1.0, 0.2), t * 2.0);
+    }
+    return mix(vec3<f32>(0.1, 1.0, 0.2), vec3<f32>(1.0, 0.15, 0.1), t * 2.0 - 1.0);
+}
+
+//One arrow per grid cell: a shaft and two head strokes, scaled by the speed
+@compute @workgroup_size(64)
+fn sample_arrows(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let res = field_view.grid_resolution;
+    let cell = global_id.x;
+    if (cell >= res * res * res) {
+        return;
+    }
+    let coord = vec3<f32>(f32(cell % res), f32((cell / res) % res), f32(cell / (res * res)));
+    let spacing = field_view.grid_origin.w;
+    let center = field_view.grid_origin.xyz + (coord + 0.5) * spacing;
+
+    let velocity = field_velocity(center);
+    let speed = length(velocity);
+    var dir = vec3<f32>(0.0);
+    if (speed > 0.000001) {
+        dir = velocity / speed;
+    }
+    let arrow_length = spacing * 0.9 * clamp(speed / field_view.speed_scale, 0.1, 1.0);
+    var side = cross(dir, vec3<f32>(0.0, 1.0, 0.0));
+    if (dot(side, side) < 0.0001) {
+        side = cross(dir, vec3<f32>(1.0, 0.0, 0.0));
+    }
+    side = normalize(side) * arrow_length * 0.15;
+
+    let tail = center - dir * arrow_length * 0.5;
+    let tip = center + dir * arrow_length * 0.5;
+    let head = tip - dir * arrow_length * 0.3;
+    var points = array<vec3<f32>, 6>(tail, tip, tip, head + side, tip, head - side);
+    let color = vec4<f32>(speed_color(speed), 1.0);
+    for (var i = 0u; i < ARROW_VERTICES; i = i + 1u) {
+        arrows.vertices[cell * ARROW_VERTICES + i] = FieldLineVertex(vec4<f32>(points[i], 1.0), color);
+    }
+}
+
+//Samples the field over the lic plane, projected into the plane
+@compute @workgroup_size(8, 8)
+fn sample_plane(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let res = field_view.lic_resolution;
+    if (global_id.x >= res || global_id.y >= res) {
+        return;
+    }
+    let uv = (vec2<f32>(global_id.xy) + 0.5) / f32(res) * 2.0 - 1.0;
+    let half_size = field_view.plane_center.w;
+    let p = field_view.plane_center.xyz
+        + (field_view.plane_u.xyz * uv.x + field_view.plane_v.xyz * uv.y) * half_size;
+    let velocity = field_velocity(p);
+    let in_plane = vec2<f32>(dot(velocity, field_view.plane_u.xyz), dot(velocity, field_view.plane_v.xyz));
+    plane_vectors.vectors[global_id.y * res + global_id.x] = vec4<f32>(in_plane, length(velocity), 0.0);
+}
+
+fn white_noise(texel: vec2<i32>) -> f32 {
+    var h = u32(texel.x) * 374761393u + u32(texel.y) * 668265263u;
+    h = (h ^ (h >> 13u)) * 1274126177u;
+    return f32(h ^ (h >> 16u)) / 4294967295.0;
+}
+
+//Line integral convolution: averages white noise along the in plane streamline through
+//each texel, lic_steps texels each way, so the noise smears into the flow direction
+@compute @workgroup_size(8, 8)
+fn convolve_plane(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let res = i32(field_view.lic_resolution);
+    let texel = vec2<i32>(global_id.xy);
+    if (texel.x >= res || texel.y >= res) {
+        return;
+    }
+    var sum = white_noise(texel);
+    var count = 1.0;
+    for (var way = 0; way < 2; way = way + 1) {
+        let direction = f32(way) * 2.0 - 1.0;
+        var pos = vec2<f32>(texel) + 0.5;
+        for (var i = 0u; i < field_view.lic_steps; i = i + 1u) {
+            let current = vec2<i32>(floor(pos));
+            let v = plane_vectors.vectors[current.y * res + current.x].xy;
+            if (length(v) < 0.000001) {
+                break;
+            }
+            pos = pos + normalize(v) * direction * 0.5;
+            let next = vec2<i32>(floor(pos));
+            if (any(next < vec2<i32>(0)) || any(next >= vec2<i32>(res))) {
+                break;
+            }
+            sum = sum + white_noise(next);
+            count = count + 1.0;
+        }
+    }
+    //Averaging flattens the noise towards 0.5, stretch the contrast back out
+    let intensity = clamp((sum / count - 0.5) * 3.0 + 0.5, 0.0, 1.0);
+    let speed = plane_vectors.vectors[texel.y * res + texel.x].z;
+    textureStore(lic_image, texel, vec4<f32>(speed_color(speed) * intensity, field_view.lic_alpha));
+}
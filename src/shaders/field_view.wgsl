@@ -0,0 +1,77 @@
+struct FieldViewParameters {
+    grid_origin: vec4<f32>,
+    //Centre of the lic plane, half its size in w
+    plane_center: vec4<f32>,
+    plane_u: vec4<f32>,
+    plane_v: vec4<f32>,
+    grid_resolution: u32,
+    lic_resolution: u32,
+    lic_steps: u32,
+    speed_scale: f32,
+    lic_alpha: f32,
+};
+
+struct CameraUniform {
+    _mat: mat4x4<f32>,
+};
+
+struct LineInput {
+    @location(0) position: vec4<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) uv: vec2<f32>,
+};
+
+@group(0) @binding(0) var<uniform> field_view: FieldViewParameters;
+@group(0) @binding(1) var t_lic: texture_2d<f32>;
+@group(0) @binding(2) var s_lic: sampler;
+
+@group(1) @binding(0)
+var<uniform> camera_view: CameraUniform;
+@group(1) @binding(1)
+var<uniform> camera_projection: CameraUniform;
+
+//Arrows and streamlines
+@vertex
+fn vs_lines(model: LineInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera_projection._mat * camera_view._mat * vec4<f32>(model.position.xyz, 1.0);
+    out.color = model.color;
+    out.uv = vec2<f32>(0.0);
+    return out;
+}
+
+@fragment
+fn fs_lines(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
+
+//Two triangles covering the lic plane, texel rows along plane_v like in field_sample.wgsl
+@vertex
+fn vs_plane(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(0.0, 0.0),
+    );
+    let uv = corners[index];
+    let offset = (uv * 2.0 - 1.0) * field_view.plane_center.w;
+    let p = field_view.plane_center.xyz + field_view.plane_u.xyz * offset.x + field_view.plane_v.xyz * offset.y;
+    var out: VertexOutput;
+    out.clip_position = camera_projection._mat * camera_view._mat * vec4<f32>(p, 1.0);
+    out.color = vec4<f32>(1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_plane(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_lic, s_lic, in.uv);
+}
@@ -0,0 +1,145 @@
+//Appended to spatial_hash_common.wgsl, spatial_hash.wgsl and sim.wgsl for
+//Behaviour::Fluid. fluid_density works out every particle's density from its neighbours,
+//then fluid_main turns the densities into pressure, viscosity and surface tension
+//accelerations and moves the particles. See fluid.rs.
+
+//See FluidSettings in fluid.rs
+struct FluidParameters {
+    //Half size, wall restitution in w
+    container: vec4<f32>,
+    gravity: vec4<f32>,
+    smoothing_radius: f32,
+    rest_density: f32,
+    particle_mass: f32,
+    stiffness: f32,
+    viscosity: f32,
+    surface_tension: f32,
+    //Already clamped to stay stable
+    time_step: f32,
+    max_speed: f32,
+    poly6: f32,
+    spiky_gradient: f32,
+    viscosity_laplacian: f32,
+    _padding: f32,
+};
+
+struct Densities {
+    densities: array<f32>,
+};
+
+@group(1) @binding(5) var<uniform> fluid : FluidParameters;
+@group(1) @binding(6) var<storage, read_write> fluid_densities : Densities;
+
+//Neighbours within the kernel fluid_main sums forces over per particle. The density pass
+//counts every neighbour, squashed fluid has more than this and its pressure has to show it.
+let MAX_FLUID_NEIGHBOURS: u32 = 128u;
+
+fn fluid_pressure(density: f32) -> f32 {
+    return max(fluid.stiffness * (density - fluid.rest_density), 0.0);
+}
+
+@compute @workgroup_size(64)
+fn fluid_density(
+  @builtin(global_invocation_id) global_id : vec3<u32>,
+) {
+    let index: u32 = global_id.x;
+
+    let total = arrayLength(&particles_src.particles);
+    if (index >= total) {
+        return;
+    }
+    let p = particles_src.particles[index].position.xyz;
+    let h2 = fluid.smoothing_radius * fluid.smoothing_radius;
+    //The particle itself is one of the neighbours, so this never ends up 0
+    var density = 0.0;
+    var cells = neighbour_cells(p);
+    for (var c = 0u; c < cells.count; c = c + 1u) {
+        let range = cell_range(cells.hashes[c]);
+        for (var k = range.x; k < range.y; k = k + 1u) {
+            let j = hash_sorted.cells[k];
+            let d = p - particles_src.particles[j].position.xyz;
+            let r2 = dot(d, d);
+            if (r2 < h2) {
+                let w = h2 - r2;
+                density = density + w * w * w;
+            }
+        }
+    }
+    fluid_densities.densities[index] = density * fluid.poly6 * fluid.particle_mass;
+}
+
+//Keeps particles in the container, bouncing what goes into a wall
+fn contain(p: vec3<f32>, v: vec3<f32>) -> Collision {
+    let half_size = fluid.container.xyz;
+    let clamped = clamp(p, -half_size, half_size);
+    var velocity = v;
+    for (var axis = 0; axis < 3; axis = axis + 1) {
+        if (clamped[axis] != p[axis] && velocity[axis] * p[axis] > 0.0) {
+            velocity[axis] = -velocity[axis] * fluid.container.w;
+        }
+    }
+    return Collision(clamped, velocity);
+}
+
+@compute @workgroup_size(64)
+fn fluid_main(
+  @builtin(global_invocation_id) global_id : vec3<u32>,
+) {
+    let index: u32 = global_id.x;
+
+    let total = arrayLength(&particles_src.particles);
+    if (index >= total) {
+        return;
+    }
+    var part = particles_src.particles[index];
+    let p = part.position.xyz;
+    let velocity = part.velocity.xyz;
+    let h = fluid.smoothing_radius;
+    let density = fluid_densities.densities[index];
+    let pressure = fluid_pressure(density);
+
+    var pressure_force = vec3<f32>(0.0);
+    var viscosity_force = vec3<f32>(0.0);
+    var cohesion = vec3<f32>(0.0);
+    var found = 0u;
+    var cells = neighbour_cells(p);
+    for (var c = 0u; c < cells.count && found < MAX_FLUID_NEIGHBOURS; c = c + 1u) {
+        let range = cell_range(cells.hashes[c]);
+        for (var k = range.x; k < range.y && found < MAX_FLUID_NEIGHBOURS; k = k + 1u) {
+            let j = hash_sorted.cells[k];
+            let other = particles_src.particles[j];
+            let d = p - other.position.xyz;
+            let r2 = dot(d, d);
+            if (r2 < h * h) {
+                found = found + 1u;
+                if (j != index && r2 > 1e-12) {
+                    let r = sqrt(r2);
+                    let q = h - r;
+                    let other_density = fluid_densities.densities[j];
+                    let shared_pressure = (pressure + fluid_pressure(other_density)) * 0.5;
+                    //Minus the spiky gradient, pointing away from the neighbour
+                    pressure_force = pressure_force + d / r * (fluid.particle_mass * shared_pressure / other_density * fluid.spiky_gradient * q * q);
+                    viscosity_force = viscosity_force + (other.velocity.xyz - velocity) * (fluid.particle_mass / other_density * fluid.viscosity_laplacian * q);
+                    let w = h * h - r2;
+                    cohesion = cohesion - d * (fluid.particle_mass * fluid.poly6 * w * w * w);
+                }
+            }
+        }
+    }
+    //Becker and Teschner's cohesion is a force, the particle's own mass makes it an acceleration
+    let acceleration = (pressure_force + viscosity_force * fluid.viscosity) / density
+        + cohesion * fluid.surface_tension / fluid.particle_mass
+        + fluid.gravity.xyz
+        + force_acceleration(p, velocity);
+
+    let dt = fluid.time_step;
+    let new_velocity = clamp_length(velocity + acceleration * dt, fluid.max_speed);
+    let collision = collide(p + new_velocity * dt, new_velocity);
+    let contained = contain(collision.position, collision.velocity);
+    record_trail(index, contained.position, false);
+
+    part.position = vec4<f32>(contained.position, 1.0);
+    //w is the particle's age, used to pick flipbook frames
+    part.velocity = vec4<f32>(contained.velocity, part.velocity.w + dt);
+    particles_dst.particles[index] = part;
+}
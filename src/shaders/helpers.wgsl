@@ -0,0 +1,143 @@
+struct HelperParameters {
+    background_top: vec4<f32>,
+    background_bottom: vec4<f32>,
+    background_mode: u32,
+    grid_spacing: f32,
+    grid_height: f32,
+    grid_fade_distance: f32,
+};
+
+struct CameraUniform {
+    _mat: mat4x4<f32>,
+};
+
+struct FullscreenOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+struct LineInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec3<f32>,
+};
+
+struct LineOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+};
+
+struct GridOutput {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+};
+
+@group(0) @binding(0) var<uniform> helpers: HelperParameters;
+@group(0) @binding(1) var t_background: texture_2d<f32>;
+@group(0) @binding(2) var s_background: sampler;
+
+@group(1) @binding(0)
+var<uniform> camera_view: CameraUniform;
+@group(1) @binding(1)
+var<uniform> camera_projection: CameraUniform;
+@group(1) @binding(2)
+var<uniform> camera_view_inv: CameraUniform;
+@group(1) @binding(3)
+var<uniform> camera_projection_inv: CameraUniform;
+
+let BACKGROUND_SOLID: u32 = 0u;
+let BACKGROUND_GRADIENT: u32 = 1u;
+let PI: f32 = 3.14159265;
+
+//Fullscreen triangle on the far plane, so it only shows where the depth buffer is still clear
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> FullscreenOutput {
+    var out: FullscreenOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 1.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn unproject(ndc: vec2<f32>, z: f32) -> vec3<f32> {
+    let view = camera_projection_inv._mat * vec4<f32>(ndc, z, 1.0);
+    return (camera_view_inv._mat * vec4<f32>(view.xyz / view.w, 1.0)).xyz;
+}
+
+fn uv_to_ndc(uv: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+}
+
+@fragment
+fn fs_background(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let ndc = uv_to_ndc(in.uv);
+    let origin = unproject(ndc, 0.0);
+    let dir = normalize(unproject(ndc, 1.0) - origin);
+    let equirect_uv = vec2<f32>(atan2(dir.z, dir.x) / (2.0 * PI) + 0.5, acos(clamp(dir.y, -1.0, 1.0)) / PI);
+    let equirect = textureSampleLevel(t_background, s_background, equirect_uv, 0.0);
+
+    if (helpers.background_mode == BACKGROUND_SOLID) {
+        return vec4<f32>(helpers.background_top.rgb, 1.0);
+    }
+    if (helpers.background_mode == BACKGROUND_GRADIENT) {
+        let t = dir.y * 0.5 + 0.5;
+        return vec4<f32>(mix(helpers.background_bottom.rgb, helpers.background_top.rgb, t), 1.0);
+    }
+    return vec4<f32>(equirect.rgb, 1.0);
+}
+
+//Anti aliased lines every `spacing`, 0 between lines and 1 on them
+fn grid_lines(coord: vec2<f32>) -> f32 {
+    let derivative = fwidth(coord);
+    let dist = abs(fract(coord - 0.5) - 0.5) / max(derivative, vec2<f32>(0.0001));
+    return 1.0 - min(min(dist.x, dist.y), 1.0);
+}
+
+//Infinite grid on the plane y = grid_height, ray cast per pixel
+@fragment
+fn fs_grid(in: FullscreenOutput) -> GridOutput {
+    var out: GridOutput;
+    let ndc = uv_to_ndc(in.uv);
+    let origin = unproject(ndc, 0.0);
+    let dir = normalize(unproject(ndc, 1.0) - origin);
+    let t = (helpers.grid_height - origin.y) / dir.y;
+    let p = origin + dir * t;
+
+    let coord = p.xz / helpers.grid_spacing;
+    let minor = grid_lines(coord);
+    let major = grid_lines(coord / 10.0);
+    //Width of one pixel on the plane, for the axis lines
+    let pixel = fwidth(p.xz);
+
+    var color = vec3<f32>(0.35);
+    var alpha = max(minor * 0.3, major * 0.6);
+    if (abs(p.z) < pixel.y * 1.5) {
+        color = vec3<f32>(1.0, 0.15, 0.15);
+        alpha = 1.0;
+    }
+    if (abs(p.x) < pixel.x * 1.5) {
+        color = vec3<f32>(0.15, 0.15, 1.0);
+        alpha = 1.0;
+    }
+    alpha *= 1.0 - smoothstep(0.0, helpers.grid_fade_distance, t);
+    if (t <= 0.0 || alpha <= 0.0) {
+        discard;
+    }
+
+    let clip = camera_projection._mat * camera_view._mat * vec4<f32>(p, 1.0);
+    out.color = vec4<f32>(color, alpha);
+    out.depth = clamp(clip.z / clip.w, 0.0, 1.0);
+    return out;
+}
+
+@vertex
+fn vs_lines(model: LineInput) -> LineOutput {
+    var out: LineOutput;
+    out.clip_position = camera_projection._mat * camera_view._mat * vec4<f32>(model.position, 1.0);
+    out.color = model.color;
+    return out;
+}
+
+@fragment
+fn fs_lines(in: LineOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(in.color, 1.0);
+}
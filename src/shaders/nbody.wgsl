@@ -0,0 +1,153 @@
+//Appended to sim.wgsl for Behaviour::NBody. Bodies are the first body_count particles,
+//every particle is pulled by them, either summed directly a tile at a time or through the
+//Barnes-Hut tree built in nbody.rs. See NBody.
+
+//See NBodySettings in nbody.rs
+struct NBodyParameters {
+    gravitational_constant: f32,
+    softening: f32,
+    particle_mass: f32,
+    mass_spread: f32,
+    body_count: u32,
+    //0 until a tree is built
+    node_count: u32,
+    theta: f32,
+    time_step: f32,
+};
+
+//See TreeNode in nbody.rs. Depth first, an internal node's first child follows it and
+//next skips past its children.
+struct TreeNode {
+    //Mass in w
+    center_of_mass: vec4<f32>,
+    //Side length in w
+    center: vec4<f32>,
+    next: u32,
+    first: u32,
+    //0 for internal nodes
+    count: u32,
+    _padding: u32,
+};
+
+struct TreeNodes {
+    nodes: array<TreeNode>,
+};
+
+struct TreeIndices {
+    indices: array<u32>,
+};
+
+@group(3) @binding(0) var<uniform> nbody : NBodyParameters;
+@group(3) @binding(1) var<storage, read> tree_nodes : TreeNodes;
+@group(3) @binding(2) var<storage, read> tree_indices : TreeIndices;
+
+//Same as body_hash in nbody.rs
+fn body_hash(x: u32) -> u32 {
+    let state = x * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn body_mass(index: u32) -> f32 {
+    if (nbody.mass_spread == 0.0) {
+        return nbody.particle_mass;
+    }
+    let u = f32(body_hash(index)) / 4294967296.0;
+    return nbody.particle_mass * exp(nbody.mass_spread * (2.0 * u - 1.0));
+}
+
+//Softened pull of a body of mass at d away, without the gravitational constant
+fn body_pull(d: vec3<f32>, mass: f32) -> vec3<f32> {
+    let r2 = dot(d, d) + nbody.softening * nbody.softening;
+    return d * (mass * inverseSqrt(r2 * r2 * r2));
+}
+
+//Kick then drift, as NBodySettings::check_energy integrates
+fn nbody_advance(index: u32, gravity: vec3<f32>) {
+    var part = particles_src.particles[index];
+    let p = part.position.xyz;
+    let velocity = part.velocity.xyz;
+    let dt = nbody.time_step;
+    let new_velocity = velocity + (gravity * nbody.gravitational_constant + force_acceleration(p, velocity)) * dt;
+    let collision = collide(p + new_velocity * dt, new_velocity);
+    record_trail(index, collision.position, false);
+
+    part.position = vec4<f32>(collision.position, 1.0);
+    //w is the particle's age, used to pick flipbook frames
+    part.velocity = vec4<f32>(collision.velocity, part.velocity.w + dt);
+    particles_dst.particles[index] = part;
+}
+
+//Bodies shared by a workgroup, position and mass
+var<workgroup> body_tile: array<vec4<f32>, 64>;
+
+@compute @workgroup_size(64)
+fn nbody_tiled(
+  @builtin(global_invocation_id) global_id : vec3<u32>,
+  @builtin(local_invocation_id) local_id : vec3<u32>,
+) {
+    let index: u32 = global_id.x;
+    let total = arrayLength(&particles_src.particles);
+    //Every invocation helps load the tiles, even past the last particle
+    var p = vec3<f32>(0.0);
+    if (index < total) {
+        p = particles_src.particles[index].position.xyz;
+    }
+    var gravity = vec3<f32>(0.0);
+    for (var tile_start = 0u; tile_start < nbody.body_count; tile_start = tile_start + 64u) {
+        let body = tile_start + local_id.x;
+        //Missing bodies weigh nothing
+        body_tile[local_id.x] = vec4<f32>(0.0);
+        if (body < nbody.body_count) {
+            body_tile[local_id.x] = vec4<f32>(particles_src.particles[body].position.xyz, body_mass(body));
+        }
+        workgroupBarrier();
+        for (var k = 0u; k < 64u; k = k + 1u) {
+            let other = body_tile[k];
+            //A particle's own pull is 0, softening keeps it finite
+            gravity = gravity + body_pull(other.xyz - p, other.w);
+        }
+        workgroupBarrier();
+    }
+    if (index >= total) {
+        return;
+    }
+    nbody_advance(index, gravity);
+}
+
+@compute @workgroup_size(64)
+fn nbody_tree(
+  @builtin(global_invocation_id) global_id : vec3<u32>,
+) {
+    let index: u32 = global_id.x;
+    let total = arrayLength(&particles_src.particles);
+    if (index >= total) {
+        return;
+    }
+    let p = particles_src.particles[index].position.xyz;
+    var gravity = vec3<f32>(0.0);
+    var i = 0u;
+    loop {
+        if (i >= nbody.node_count) {
+            break;
+        }
+        let node = tree_nodes.nodes[i];
+        let d = node.center_of_mass.xyz - p;
+        let size = node.center.w;
+        if (size * size < nbody.theta * nbody.theta * dot(d, d)) {
+            //Far enough to pull as one, from where the bodies were when the tree was built
+            gravity = gravity + body_pull(d, node.center_of_mass.w);
+            i = node.next;
+        } else if (node.count > 0u) {
+            //Close leaves use where their bodies are now
+            for (var k = node.first; k < node.first + node.count; k = k + 1u) {
+                let body = tree_indices.indices[k];
+                gravity = gravity + body_pull(particles_src.particles[body].position.xyz - p, body_mass(body));
+            }
+            i = node.next;
+        } else {
+            i = i + 1u;
+        }
+    }
+    nbody_advance(index, gravity);
+}
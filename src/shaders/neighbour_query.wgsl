@@ -0,0 +1,55 @@
+//Appended to spatial_hash_common.wgsl and spatial_hash.wgsl. Writes the neighbour lists of
+//a few particles, for SpatialHash::validate to check against a brute force search.
+
+struct Particle {
+    position: vec4<f32>,
+    velocity: vec4<f32>,
+    color: vec4<f32>,
+};
+
+struct Particles {
+    particles: array<Particle>,
+};
+
+struct NeighbourQuery {
+    radius: f32,
+    query_count: u32,
+    max_neighbours: u32,
+    _padding: u32,
+};
+
+struct Indices {
+    indices: array<u32>,
+};
+
+@group(0) @binding(0) var<storage, read> particles_src : Particles;
+@group(0) @binding(1) var<uniform> query : NeighbourQuery;
+@group(0) @binding(2) var<storage, read> query_indices : Indices;
+//max_neighbours + 1 per query: the neighbour count, then as many neighbours as fit
+@group(0) @binding(3) var<storage, read_write> neighbour_lists : Indices;
+
+@compute @workgroup_size(64)
+fn query_neighbours(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if (global_id.x >= query.query_count) {
+        return;
+    }
+    let index = query_indices.indices[global_id.x];
+    let p = particles_src.particles[index].position.xyz;
+    let base = global_id.x * (query.max_neighbours + 1u);
+    var found = 0u;
+    var cells = neighbour_cells(p);
+    for (var c = 0u; c < cells.count; c = c + 1u) {
+        let range = cell_range(cells.hashes[c]);
+        for (var k = range.x; k < range.y; k = k + 1u) {
+            let j = hash_sorted.cells[k];
+            let d = particles_src.particles[j].position.xyz - p;
+            if (j != index && dot(d, d) <= query.radius * query.radius) {
+                if (found < query.max_neighbours) {
+                    neighbour_lists.indices[base + 1u + found] = j;
+                }
+                found = found + 1u;
+            }
+        }
+    }
+    neighbour_lists.indices[base] = found;
+}
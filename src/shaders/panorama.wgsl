@@ -0,0 +1,86 @@
+struct Basis {
+    forward: vec4<f32>,
+    up: vec4<f32>,
+    right: vec4<f32>,
+};
+
+struct PanoramaParameters {
+    //Orientation of the whole panorama, forward is the image centre
+    rig: Basis,
+    faces: array<Basis, 6>,
+    format: u32,
+    fisheye_fov: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var t_faces: texture_2d_array<f32>;
+@group(0) @binding(1)
+var s_faces: sampler;
+@group(0) @binding(2)
+var<uniform> params: PanoramaParameters;
+
+let FORMAT_EQUIRECT: u32 = 1u;
+let PI: f32 = 3.14159265;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+//Looks dir up in the cube face it points at most directly. Faces were rendered with a
+//square 90 degree projection, so the face plane spans -1..1 in both directions.
+fn sample_faces(dir: vec3<f32>) -> vec3<f32> {
+    var face = 0;
+    var best = -2.0;
+    for (var i = 0; i < 6; i = i + 1) {
+        let d = dot(dir, params.faces[i].forward.xyz);
+        if (d > best) {
+            best = d;
+            face = i;
+        }
+    }
+    let basis = params.faces[face];
+    let plane = vec2<f32>(dot(dir, basis.right.xyz), dot(dir, basis.up.xyz)) / best;
+    let uv = vec2<f32>(plane.x * 0.5 + 0.5, 0.5 - plane.y * 0.5);
+    return textureSampleLevel(t_faces, s_faces, uv, face, 0.0).rgb;
+}
+
+//Longitude across, latitude down, the rig forward in the middle
+fn equirect_direction(uv: vec2<f32>) -> vec3<f32> {
+    let longitude = (uv.x - 0.5) * 2.0 * PI;
+    let latitude = (0.5 - uv.y) * PI;
+    return cos(latitude) * sin(longitude) * params.rig.right.xyz
+        + sin(latitude) * params.rig.up.xyz
+        + cos(latitude) * cos(longitude) * params.rig.forward.xyz;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    if (params.format == FORMAT_EQUIRECT) {
+        return vec4<f32>(sample_faces(equirect_direction(in.uv)), 1.0);
+    }
+
+    //Dome master: angular fisheye with the zenith in the centre and the rig forward at
+    //the bottom edge, the way planetarium domes are usually oriented
+    let p = vec2<f32>(in.uv.x * 2.0 - 1.0, 1.0 - in.uv.y * 2.0);
+    let r = length(p);
+    if (r > 1.0) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+    let theta = r * radians(params.fisheye_fov) * 0.5;
+    var around = vec3<f32>(0.0);
+    if (r > 0.0) {
+        around = (p.x * params.rig.right.xyz - p.y * params.rig.forward.xyz) / r;
+    }
+    let dir = cos(theta) * params.rig.up.xyz + sin(theta) * around;
+    return vec4<f32>(sample_faces(dir), 1.0);
+}
@@ -11,6 +11,10 @@ struct VertexOutput {
     @location(0) velocity: vec4<f32>,
     @location(1) color: vec4<f32>,
     @location(2) tex_coords: vec2<f32>,
+    //Distance in front of the camera, for soft particles
+    @location(3) view_depth: f32,
+    //Atlas frame picked for the particle
+    @location(4) @interpolate(flat) frame: u32,
 };
 
 struct CameraUniform {
@@ -33,44 +37,222 @@ fn vel2col(v: vec4<f32>) -> vec4<f32> {
     return vec4<f32>(r,g,b,0.8);
 }
 
+struct RenderParameters {
+    particle_size: f32,
+    quad_mode: u32,
+    shutter_time: f32,
+    min_length: f32,
+    max_length: f32,
+    soft_fade_distance: f32,
+    sprite_mode: u32,
+    atlas_columns: u32,
+    atlas_rows: u32,
+    frame_source: u32,
+    frame_rate: f32,
+    frame_speed_max: f32,
+    clip: ClipParameters,
+};
+
+let QUAD_BILLBOARD: u32 = 0u;
+let QUAD_VELOCITY_ALIGNED: u32 = 1u;
+
+let SPRITE_TEXTURE: u32 = 0u;
+let SPRITE_SOFT_GAUSSIAN: u32 = 1u;
+let SPRITE_RING: u32 = 2u;
+let SPRITE_HARD_DISC: u32 = 3u;
+
+let FRAME_FROM_ID: u32 = 0u;
+let FRAME_FROM_AGE: u32 = 1u;
+
+@group(0) @binding(2)
+var<uniform> render_params: RenderParameters;
+
 @group(1) @binding(0) // 1.
 var<uniform> camera_view: CameraUniform;
 @group(1) @binding(1) // 1.
 var<uniform> camera_projection: CameraUniform;
 @group(1) @binding(2) // 1.
 var<uniform> camera_view_inv: CameraUniform;
+@group(1) @binding(3)
+var<uniform> camera_projection_inv: CameraUniform;
 
-@vertex
-fn vs_main(
-    model: VertexInput,
+//Age lives in velocity.w
+fn sprite_frame(id: u32, velocity: vec4<f32>) -> u32 {
+    let frames = render_params.atlas_columns * render_params.atlas_rows;
+    if (render_params.frame_source == FRAME_FROM_ID) {
+        return id % frames;
+    }
+    if (render_params.frame_source == FRAME_FROM_AGE) {
+        return u32(velocity.w * render_params.frame_rate) % frames;
+    }
+    let t = clamp(length(velocity.xyz) / render_params.frame_speed_max, 0.0, 1.0);
+    return min(u32(t * f32(frames)), frames - 1u);
+}
+
+fn particle_vertex(
+    id: u32,
+    particle_position: vec4<f32>,
+    particle_velocity: vec4<f32>,
+    quad_vertex_position: vec4<f32>,
+    quad_tex_coords: vec2<f32>,
 ) -> VertexOutput {
     var out: VertexOutput;
-   
-    //Calculate corners of quad using inverse camera view matrix
-    let billboard_pos = camera_view_inv._mat * model.quad_vertex_position;
-    let part_pos = model.particle_position + billboard_pos;
-    //Clip position calculated using view matrix, projection matrix, and and particle position
-    out.clip_position = camera_projection._mat * camera_view._mat * part_pos;
-    out.velocity = model.particle_velocity;
-
-    let col = vel2col(model.particle_velocity);
-    
+    let visibility = clip_visibility(render_params.clip, particle_position.xyz);
+    if (visibility <= 0.0) {
+        //All four corners in one spot, so the quad has no area to draw
+        out.clip_position = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+        return out;
+    }
+
+    //Quad corners are built in view space so they always face the camera
+    let view_pos = camera_view._mat * vec4<f32>(particle_position.xyz, 1.0);
+    let size = render_params.particle_size;
+    var corner = quad_vertex_position.xy * size;
+
+    if (render_params.quad_mode == QUAD_VELOCITY_ALIGNED) {
+        //Screen space direction of motion, scaled back to world units at the particle's depth
+        let view_vel = (camera_view._mat * vec4<f32>(particle_velocity.xyz, 0.0)).xyz;
+        var screen_vel = view_vel.xy;
+        //Under perspective moving in depth also moves across the screen, orthographic has w = 1
+        if (camera_projection._mat[2][3] != 0.0) {
+            screen_vel = screen_vel + view_pos.xy * view_vel.z / max(-view_pos.z, 0.0001);
+        }
+        let speed = length(screen_vel);
+        if (speed > 0.00001) {
+            let axis = screen_vel / speed;
+            let side = vec2<f32>(-axis.y, axis.x);
+            let streak = clamp(speed * render_params.shutter_time, render_params.min_length, render_params.max_length);
+            corner = axis * quad_vertex_position.x * (size + streak) + side * quad_vertex_position.y * size;
+        }
+    }
+
+    //Clip position calculated using projection matrix and the offset view space position
+    out.clip_position = camera_projection._mat * vec4<f32>(view_pos.xyz + vec3<f32>(corner, 0.0), 1.0);
+    out.velocity = particle_velocity;
+    out.view_depth = -view_pos.z;
+
+    var col = vel2col(particle_velocity);
+    col.a *= visibility;
     out.color = col;
-    out.tex_coords = model.quad_tex_coords;
+    out.tex_coords = quad_tex_coords;
+    out.frame = sprite_frame(id, particle_velocity);
     return out;
 }
 
+@vertex
+fn vs_main(
+    model: VertexInput,
+    @builtin(instance_index) instance: u32,
+) -> VertexOutput {
+    return particle_vertex(
+        instance,
+        model.particle_position,
+        model.particle_velocity,
+        model.quad_vertex_position,
+        model.quad_tex_coords,
+    );
+}
+
+struct Particle {
+    position: vec4<f32>,
+    velocity: vec4<f32>,
+    color: vec4<f32>,
+};
+
+struct Particles {
+    particles: array<Particle>,
+};
+
+struct SortEntry {
+    depth: f32,
+    index: u32,
+};
+
+struct SortEntries {
+    entries: array<SortEntry>,
+};
+
+struct QuadInput {
+    @location(3) quad_vertex_position: vec4<f32>,
+    @location(4) quad_tex_coords: vec2<f32>,
+};
+
+@group(2) @binding(0)
+var<storage, read> particles: Particles;
+@group(2) @binding(1)
+var<storage, read> sort_entries: SortEntries;
+
+//Draws instances back to front through the depth sorted index list
+@vertex
+fn vs_sorted(
+    quad: QuadInput,
+    @builtin(instance_index) instance: u32,
+) -> VertexOutput {
+    let index = sort_entries.entries[instance].index;
+    let particle = particles.particles[index];
+    return particle_vertex(
+        index,
+        particle.position,
+        particle.velocity,
+        quad.quad_vertex_position,
+        quad.quad_tex_coords,
+    );
+}
+
 @group(0) @binding(0)
 var t_diffuse: texture_2d<f32>;
 @group(0)@binding(1)
 var s_diffuse: sampler;
+@group(0) @binding(3)
+var t_scene_depth: texture_depth_2d;
 
+//View space distance of the opaque scene behind this fragment
+fn scene_view_depth(frag_coord: vec4<f32>) -> f32 {
+    let pixel = vec2<i32>(frag_coord.xy);
+    let depth = textureLoad(t_scene_depth, pixel, 0);
+    let size = vec2<f32>(textureDimensions(t_scene_depth));
+    let ndc = vec2<f32>(frag_coord.x / size.x * 2.0 - 1.0, 1.0 - frag_coord.y / size.y * 2.0);
+    let view = camera_projection_inv._mat * vec4<f32>(ndc, depth, 1.0);
+    return -view.z / view.w;
+}
+
+//1 well in front of the scene, fading to 0 where the particle meets it and behind it
+fn soft_fade(in: VertexOutput) -> f32 {
+    let gap = scene_view_depth(in.clip_position) - in.view_depth;
+    if (render_params.soft_fade_distance <= 0.0) {
+        return step(0.0, gap);
+    }
+    return clamp(gap / render_params.soft_fade_distance, 0.0, 1.0);
+}
+
+//Procedural sprites are white with the shape in alpha
+fn sprite_color(in: VertexOutput) -> vec4<f32> {
+    let columns = render_params.atlas_columns;
+    let cell = vec2<f32>(f32(in.frame % columns), f32(in.frame / columns));
+    let atlas_uv = (in.tex_coords + cell) / vec2<f32>(f32(columns), f32(render_params.atlas_rows));
+    let tex_color = textureSample(t_diffuse, s_diffuse, atlas_uv);
+
+    let d = length(in.tex_coords * 2.0 - 1.0);
+    let edge = fwidth(d);
+    var shape = 0.0;
+    if (render_params.sprite_mode == SPRITE_TEXTURE) {
+        return tex_color;
+    } else if (render_params.sprite_mode == SPRITE_SOFT_GAUSSIAN) {
+        shape = exp(-4.0 * d * d) * (1.0 - smoothstep(0.9, 1.0, d));
+    } else if (render_params.sprite_mode == SPRITE_RING) {
+        shape = smoothstep(0.55, 0.7, d) * (1.0 - smoothstep(0.85, 1.0, d));
+    } else {
+        shape = 1.0 - smoothstep(1.0 - edge, 1.0, d);
+    }
+    return vec4<f32>(1.0, 1.0, 1.0, shape);
+}
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
   
-    let tex_color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let tex_color = sprite_color(in);
     let p_color = in.color;
-    let n_color = tex_color.rgba * p_color.rgba;
+    var n_color = tex_color.rgba * p_color.rgba;
+    n_color.a *= soft_fade(in);
    return n_color;
 }
\ No newline at end of file
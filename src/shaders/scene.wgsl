@@ -0,0 +1,37 @@
+struct CameraUniform {
+    _mat: mat4x4<f32>,
+};
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) color: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) normal: vec3<f32>,
+    @location(1) color: vec3<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera_view: CameraUniform;
+@group(0) @binding(1)
+var<uniform> camera_projection: CameraUniform;
+
+let LIGHT_DIRECTION: vec3<f32> = vec3<f32>(0.3, 0.9, 0.3);
+
+@vertex
+fn vs_main(model: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera_projection._mat * camera_view._mat * vec4<f32>(model.position, 1.0);
+    out.normal = model.normal;
+    out.color = model.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let diffuse = max(dot(normalize(in.normal), normalize(LIGHT_DIRECTION)), 0.0);
+    return vec4<f32>(in.color * (0.2 + 0.8 * diffuse), 1.0);
+}
@@ -1,10 +1,5 @@
 
 //Parameters
-let noise_scale: f32 = 0.008;
-let speed_multiplier: f32 = 40.0;
-let curl_multiplier: f32 = 40.0;
-let constant_force: vec3<f32> = vec3<f32>(0.0,0.0,0.0);
-let v_curl_mix: f32 = 1.0;
 let max_extent: f32 = 300.0;
 
 //Constants
@@ -14,16 +9,74 @@ let rot2: mat3x3<f32> = mat3x3<f32>(vec3<f32>(-0.55,-0.39, 0.74),vec3<f32>( 0.33
 let rot3: mat3x3<f32> = mat3x3<f32>(vec3<f32>(-0.71, 0.52,-0.47),vec3<f32>(-0.08,-0.72,-0.68),vec3<f32>(-0.7,-0.45,0.56));
 
 
+//Field parameters, see FieldParameters
 struct ParticleSystemParameters {
+    p_constant_force: vec4<f32>,
     p_noise_scale: f32,
     p_speed_multiplier: f32,
     p_curl_multiplier: f32,
-    p_constant_force: vec3<f32>,
     p_potential_curl_mix: f32,
-    p_elapsed_time: f32,
-    p_time_multiplier: f32,
+    //Elapsed time scaled by the evolution speed, wrapped at FIELD_PERIOD in field.rs
+    p_field_time: f32,
+    p_octaves: u32,
+    //Frequency and amplitude factors from one octave to the next
+    p_lacunarity: f32,
+    p_gain: f32,
+    //FRACTAL_STANDARD, FRACTAL_BILLOW or FRACTAL_RIDGED
+    p_fractal_mode: u32,
+    p_rotate_octaves: u32,
+    //Displacement of the sample positions by another noise, in noise space
+    p_warp_strength: f32,
+    p_warp_scale: f32,
+    //How quickly particle velocities follow the flow, per second. 1 / DT follows it exactly.
+    p_field_response: f32,
+    //Distance from the analytic colliders the potential ramps down over, 0 ignores them
+    p_boundary_width: f32,
 }
 
+//See Force in forces.rs
+struct Force {
+    //Strength in w
+    position: vec4<f32>,
+    //Radius in w
+    axis: vec4<f32>,
+    //Falloff in w
+    region_size: vec4<f32>,
+    rotation: vec4<f32>,
+    scale: vec4<f32>,
+    kind: u32,
+    region: u32,
+};
+
+struct Forces {
+    count: u32,
+    forces: array<Force>,
+};
+
+@group(1) @binding(0) var<uniform> parameters : ParticleSystemParameters;
+@group(1) @binding(1) var<storage, read> force_stack : Forces;
+
+//See Collider in colliders.rs
+struct Collider {
+    position: vec4<f32>,
+    axis: vec4<f32>,
+    size: vec4<f32>,
+    shape: u32,
+    operation: u32,
+};
+
+struct Colliders {
+    count: u32,
+    margin: f32,
+    restitution: f32,
+    //0 until a mesh is baked
+    mesh_resolution: u32,
+    colliders: array<Collider>,
+};
+
+@group(1) @binding(2) var<storage, read> collider_stack : Colliders;
+@group(1) @binding(3) var mesh_sdf : texture_3d<f32>;
+
 struct Particle {
     position: vec4<f32>,
     velocity: vec4<f32>,
@@ -49,6 +102,21 @@ fn random3(c: vec3<f32>) -> vec3<f32> {
 	return r-vec3<f32>(0.5);
 }
 
+//Flow noise: every corner's gradient spins around its own random axis as the field time
+//advances, so the pattern evolves in place instead of scrolling. It is still a constant
+//gradient per corner, so the analytic derivatives hold.
+//Each corner turns at its own speed, between half and one and a half radians per unit of
+//field time, in steps that make every corner do a whole number of turns over
+//FIELD_PERIOD in field.rs. The field only repeats after that long, and the time wraps there.
+let SPIN_STEPS: f32 = 64.0;
+fn corner_gradient(c: vec3<f32>) -> vec3<f32> {
+    let g = random3(c);
+    let turns = floor(SPIN_STEPS * (1.0 + random3(c + 71.0).x));
+    let angle = parameters.p_field_time * turns / SPIN_STEPS;
+    let axis = normalize(random3(c + 113.0) + vec3<f32>(0.0001));
+    return g*cos(angle) + cross(axis, g)*sin(angle) + axis*dot(axis, g)*(1.0 - cos(angle));
+}
+
 /* skew constants for 3d simplex functions */
 let F3: f32 =  0.3333333;
 let G3: f32 =  0.1666667;
@@ -87,10 +155,10 @@ fn simplex3d(p: vec3<f32>) -> f32 {
 	 w = max(vec4<f32>(0.6) - w, vec4<f32>(0.0));
 	 
 	 /* calculate surflet components */
-	 d.x = dot(random3(s), x);
-	 d.y = dot(random3(s + i1), x1);
-	 d.z = dot(random3(s + i2), x2);
-	 d.w = dot(random3(s + 1.0), x3);
+	 d.x = dot(corner_gradient(s), x);
+	 d.y = dot(corner_gradient(s + i1), x1);
+	 d.z = dot(corner_gradient(s + i2), x2);
+	 d.w = dot(corner_gradient(s + 1.0), x3);
 	 
 	 /* multiply d by w^4 */
 	 w *= w;
@@ -101,37 +169,291 @@ fn simplex3d(p: vec3<f32>) -> f32 {
 	 return dot(d, vec4<f32>(52.0));
 }
 
+/* 3d simplex noise with its analytic gradient, gradient in xyz and the value in w */
+fn simplex3d_grad(p: vec3<f32>) -> vec4<f32> {
+	 /* same tetrahedron and surflets as simplex3d */
+	 let s: vec3<f32> = floor(p + dot(p, vec3<f32>(F3)));
+	 let x: vec3<f32> = p - s + dot(s, vec3<f32>(G3));
+	 let e : vec3<f32> = step(vec3<f32>(0.0), x - x.yzx);
+	 let i1: vec3<f32> = e*(1.0 - e.zxy);
+	 let i2: vec3<f32> = 1.0 - e.zxy*(1.0 - e);
+	 let x1: vec3<f32> = x - i1 + G3;
+	 let x2: vec3<f32> = x - i2 + 2.0*G3;
+	 let x3: vec3<f32> = x - 1.0 + 3.0*G3;
+
+	 let g0 = corner_gradient(s);
+	 let g1 = corner_gradient(s + i1);
+	 let g2 = corner_gradient(s + i2);
+	 let g3 = corner_gradient(s + 1.0);
+
+	 let w = max(vec4<f32>(0.6) - vec4<f32>(dot(x, x), dot(x1, x1), dot(x2, x2), dot(x3, x3)), vec4<f32>(0.0));
+	 let d = vec4<f32>(dot(g0, x), dot(g1, x1), dot(g2, x2), dot(g3, x3));
+	 let w2 = w*w;
+	 let w3 = w2*w;
+	 let w4 = w2*w2;
+
+	 /* the gradient of w^4*dot(g, x) is w^4*g - 8*w^3*dot(g, x)*x */
+	 let t = 8.0*w3*d;
+	 let grad = g0*w4.x - x*t.x
+	 	+ g1*w4.y - x1*t.y
+	 	+ g2*w4.z - x2*t.z
+	 	+ g3*w4.w - x3*t.w;
+	 return vec4<f32>(grad, dot(d*w4, vec4<f32>(1.0)))*52.0;
+}
+
+let FRACTAL_STANDARD: u32 = 0u;
+let FRACTAL_BILLOW: u32 = 1u;
+let FRACTAL_RIDGED: u32 = 2u;
+
 /* directional artifacts can be reduced by rotating each octave */
-fn simplex3d_fractal(m: vec3<f32>) -> f32 {
-    return 0.5333333*simplex3d(m*rot1)
-			+0.2666667*simplex3d(2.0*m*rot2)
-			+0.1333333*simplex3d(4.0*m*rot3)
-			+0.0666667*simplex3d(8.0*m);
+fn octave_rotation(octave: u32) -> mat3x3<f32> {
+    if (octave == 0u || parameters.p_rotate_octaves == 0u) {
+        return mat3x3<f32>(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(0.0, 0.0, 1.0));
+    }
+    let i = (octave - 1u) % 3u;
+    if (i == 0u) {
+        return rot1;
+    }
+    if (i == 1u) {
+        return rot2;
+    }
+    return rot3;
+}
+
+//Billow and ridged shapes of one octave, value and gradient laid out like simplex3d_grad
+fn shape_octave(n: vec4<f32>) -> vec4<f32> {
+    let sign_n = select(-1.0, 1.0, n.w >= 0.0);
+    if (parameters.p_fractal_mode == FRACTAL_BILLOW) {
+        return vec4<f32>(n.xyz * sign_n, abs(n.w));
+    }
+    if (parameters.p_fractal_mode == FRACTAL_RIDGED) {
+        let ridge = 1.0 - abs(n.w);
+        return vec4<f32>(n.xyz * (-2.0 * ridge * sign_n), ridge * ridge);
+    }
+    return n;
+}
+
+//Fractal sum of simplex3d_grad octaves, normalised by the total amplitude. Each octave is
+//sampled at m*rotation*frequency, so its gradient goes back through rotation*frequency.
+fn fractal_grad(m: vec3<f32>) -> vec4<f32> {
+    var sum = vec4<f32>(0.0);
+    var total = 0.0;
+    var amplitude = 1.0;
+    var frequency = 1.0;
+    for (var octave = 0u; octave < max(parameters.p_octaves, 1u); octave = octave + 1u) {
+        let rotation = octave_rotation(octave);
+        let n = shape_octave(simplex3d_grad(m*rotation*frequency));
+        sum = sum + vec4<f32>(rotation*n.xyz*frequency, n.w)*amplitude;
+        total = total + amplitude;
+        amplitude = amplitude*parameters.p_gain;
+        frequency = frequency*parameters.p_lacunarity;
+    }
+    return sum / total;
+}
+
+//fractal_grad without the gradient
+fn fractal(m: vec3<f32>) -> f32 {
+    var sum = 0.0;
+    var total = 0.0;
+    var amplitude = 1.0;
+    var frequency = 1.0;
+    for (var octave = 0u; octave < max(parameters.p_octaves, 1u); octave = octave + 1u) {
+        let n = simplex3d(m*octave_rotation(octave)*frequency);
+        var shaped = n;
+        if (parameters.p_fractal_mode == FRACTAL_BILLOW) {
+            shaped = abs(n);
+        } else if (parameters.p_fractal_mode == FRACTAL_RIDGED) {
+            shaped = (1.0 - abs(n))*(1.0 - abs(n));
+        }
+        sum = sum + shaped*amplitude;
+        total = total + amplitude;
+        amplitude = amplitude*parameters.p_gain;
+        frequency = frequency*parameters.p_lacunarity;
+    }
+    return sum / total;
+}
+
+//Noise space position displaced by another noise. gradient_transform takes a gradient at
+//the displaced position back to one at the original position, its columns are the rows of
+//the displacement's jacobian.
+struct DomainWarp {
+    position: vec3<f32>,
+    gradient_transform: mat3x3<f32>,
+};
+
+fn warp_domain(pos: vec3<f32>) -> DomainWarp {
+    var warp: DomainWarp;
+    warp.position = pos;
+    warp.gradient_transform = mat3x3<f32>(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(0.0, 0.0, 1.0));
+    if (parameters.p_warp_strength == 0.0) {
+        return warp;
+    }
+    let q = pos*parameters.p_warp_scale;
+    let wx = simplex3d_grad(q + vec3<f32>(0.0, 1000.0, 0.0));
+    let wy = simplex3d_grad(q + vec3<f32>(0.0, 2000.0, 0.0));
+    let wz = simplex3d_grad(q + vec3<f32>(0.0, 0.0, 1000.0));
+    let strength = parameters.p_warp_strength;
+    warp.position = pos + vec3<f32>(wx.w, wy.w, wz.w)*strength;
+    let k = strength*parameters.p_warp_scale;
+    warp.gradient_transform = mat3x3<f32>(
+        vec3<f32>(1.0, 0.0, 0.0) + wx.xyz*k,
+        vec3<f32>(0.0, 1.0, 0.0) + wy.xyz*k,
+        vec3<f32>(0.0, 0.0, 1.0) + wz.xyz*k
+    );
+    return warp;
 }
 
 fn vector_field(p: vec3<f32>, scale: f32) -> vec3<f32> {
-    let pos: vec3<f32> = p * scale;
+    let pos: vec3<f32> = warp_domain(p * scale).position;
 
     let x_p = pos;
     let y_p = pos + vec3<f32>(1000.0,0.0,0.0);
     let z_p = pos + vec3<f32>(2000.0,0.0,0.0);
 
-    let x_n = simplex3d(x_p);
-    let y_n = simplex3d(y_p);
-    let z_n = simplex3d(z_p);
+    let x_n = fractal(x_p);
+    let y_n = fractal(y_p);
+    let z_n = fractal(z_p);
     let direction = vec3<f32>(x_n,y_n,z_n);
     return direction;
 }
 
 fn potential(pos: vec3<f32>) -> vec3<f32> {
-    var vf = vector_field(pos,noise_scale);
-    vf = vf + constant_force;
-    return (vf * speed_multiplier);
+    var vf = vector_field(pos,parameters.p_noise_scale);
+    vf = vf + parameters.p_constant_force.xyz;
+    return (vf * parameters.p_speed_multiplier);
+}
+
+
+
+fn safe_normalize(v: vec3<f32>) -> vec3<f32> {
+    if (dot(v, v) > 1e-12) {
+        return normalize(v);
+    }
+    return vec3<f32>(0.0);
+}
+
+fn clamp_length(v: vec3<f32>, max_length: f32) -> vec3<f32> {
+    let l = length(v);
+    if (l > max_length) {
+        return v * (max_length / l);
+    }
+    return v;
 }
 
+//Distance to the analytic colliders combined in order in w, its gradient in xyz. Baked
+//meshes are left out, the field needs exact gradients. Like obstacle_distance in
+//colliders.rs, w is 1000000 when there's nothing.
+fn obstacle_distance(p: vec3<f32>) -> vec4<f32> {
+    var nearest = vec4<f32>(0.0, 1.0, 0.0, 1000000.0);
+    for (var i = 0u; i < collider_stack.count; i = i + 1u) {
+        let collider = collider_stack.colliders[i];
+        let d = p - collider.position.xyz;
+        let axis = collider.axis.xyz;
+        let size = collider.size.xyz;
+        var shape_sample = vec4<f32>(axis, 1000000.0);
+        switch (collider.shape) {
+            //Sphere
+            case 0u: {
+                shape_sample = vec4<f32>(safe_normalize(d), length(d) - size.x);
+            }
+            //Box
+            case 1u: {
+                let q = abs(d) - size;
+                let s = sign(d);
+                let inside = max(q.x, max(q.y, q.z));
+                if (inside > 0.0) {
+                    let outside = max(q, vec3<f32>(0.0));
+                    shape_sample = vec4<f32>(s * outside / length(outside), length(outside));
+                } else if (q.x >= q.y && q.x >= q.z) {
+                    shape_sample = vec4<f32>(s.x, 0.0, 0.0, inside);
+                } else if (q.y >= q.z) {
+                    shape_sample = vec4<f32>(0.0, s.y, 0.0, inside);
+                } else {
+                    shape_sample = vec4<f32>(0.0, 0.0, s.z, inside);
+                }
+            }
+            //Capsule
+            case 2u: {
+                let from_core = d - axis * clamp(dot(d, axis), -size.y, size.y);
+                shape_sample = vec4<f32>(safe_normalize(from_core), length(from_core) - size.x);
+            }
+            //Torus
+            case 3u: {
+                let from_core = d - safe_normalize(d - axis * dot(d, axis)) * size.x;
+                shape_sample = vec4<f32>(safe_normalize(from_core), length(from_core) - size.y);
+            }
+            //Plane
+            case 4u: {
+                shape_sample = vec4<f32>(axis, dot(d, axis));
+            }
+            default: {}
+        }
+        if (collider.operation == 0u) {
+            if (shape_sample.w < nearest.w) {
+                nearest = shape_sample;
+            }
+        } else if (-shape_sample.w > nearest.w) {
+            nearest = -shape_sample;
+        }
+    }
+    return nearest;
+}
 
+//Bridson's smooth ramp from -1 to 1 over -1 < r < 1 in x, its derivative in y
+fn boundary_ramp(r: f32) -> vec2<f32> {
+    if (abs(r) >= 1.0) {
+        return vec2<f32>(sign(r), 0.0);
+    }
+    let r2 = r * r;
+    return vec2<f32>(r * (15.0 - 10.0 * r2 + 3.0 * r2 * r2) / 8.0, 15.0 / 8.0 * (1.0 - r2) * (1.0 - r2));
+}
+
+//Potential and its curl, from one set of noise samples
+struct FieldSample {
+    potential: vec3<f32>,
+    curl: vec3<f32>,
+};
+
+//Curl noise after Bridson et al. The curl comes straight from the analytic gradients of
+//the three noise components, so it is divergence free up to rounding and needs no extra
+//noise evaluations for derivatives.
+fn sample_field(p: vec3<f32>) -> FieldSample {
+    let warp = warp_domain(p * parameters.p_noise_scale);
+    let nx = fractal_grad(warp.position);
+    let ny = fractal_grad(warp.position + vec3<f32>(1000.0,0.0,0.0));
+    let nz = fractal_grad(warp.position + vec3<f32>(2000.0,0.0,0.0));
+    let gx = warp.gradient_transform*nx.xyz;
+    let gy = warp.gradient_transform*ny.xyz;
+    let gz = warp.gradient_transform*nz.xyz;
+
+    var field_sample: FieldSample;
+    field_sample.potential = (vec3<f32>(nx.w, ny.w, nz.w) + parameters.p_constant_force.xyz) * parameters.p_speed_multiplier;
+    //Chain rule through the scaling in potential. Negated, the way the forward differences
+    //always had it, so the flow keeps its direction.
+    let derivative_scale = parameters.p_noise_scale * parameters.p_speed_multiplier * parameters.p_curl_multiplier;
+    field_sample.curl = vec3<f32>(gy.z - gz.y, gz.x - gx.z, gx.y - gy.x) * derivative_scale;
+
+    //Ramping the potential down to 0 on the obstacles leaves only the ramp gradient crossed
+    //with the potential there, which runs along the surface. Still a curl, so still
+    //divergence free.
+    if (parameters.p_boundary_width > 0.0) {
+        let obstacle = obstacle_distance(p);
+        let ramp = boundary_ramp(obstacle.w / parameters.p_boundary_width);
+        let ramp_gradient = obstacle.xyz * (ramp.y / parameters.p_boundary_width);
+        let curled = field_sample.potential * parameters.p_curl_multiplier;
+        field_sample.curl = field_sample.curl * ramp.x - cross(ramp_gradient, curled);
+    }
+    return field_sample;
+}
 
 fn curl(p: vec3<f32>) -> vec3<f32> {
+    return sample_field(p).curl;
+}
+
+//The previous forward difference curl, seven potential evaluations. Only kept to benchmark
+//sample_field against. It subtracts the offset samples from the centre one, so it comes out
+//negated, like curl.
+fn curl_finite_difference(p: vec3<f32>) -> vec3<f32> {
     let pot = potential(p);
     let epsilon = 0.0001;
     // Partial derivatives of different components of the potential
@@ -141,15 +463,222 @@ fn curl(p: vec3<f32>) -> vec3<f32> {
     let dp3_dx = (pot.z - potential(vec3<f32>(p.x + epsilon, p.y, p.z))).z / epsilon;
     let dp2_dx = (pot.y - potential(vec3<f32>(p.x + epsilon, p.y, p.z))).y / epsilon;
     let dp1_dy = (pot.x - potential(vec3<f32>(p.x, p.y + epsilon, p.z))).x / epsilon;
+    return vec3<f32>(dp3_dy - dp2_dz, dp1_dz - dp3_dx, dp2_dx - dp1_dy) * parameters.p_curl_multiplier;
+}
+
+//Velocity a particle at p moves with
+fn field_velocity(p: vec3<f32>) -> vec3<f32> {
+    let field_sample = sample_field(p);
+    return mix(field_sample.potential, field_sample.curl, parameters.p_potential_curl_mix);
+}
+
+let REGION_BOX: u32 = 1u;
+let REGION_SPHERE: u32 = 2u;
+
+//How quickly wind drags particles to its velocity, per second
+let WIND_COUPLING: f32 = 1.0;
+
+fn force_falloff(distance_from: f32, radius: f32, falloff: f32) -> f32 {
+    return 1.0 / (1.0 + pow(distance_from / max(radius, 0.0001), falloff));
+}
 
-    // vel = nabla x potential
-    // Since this vecotor field has only a vector potential component
-    // it is divergent free and hence contains no sources
-    return vec3<f32>(dp3_dy - dp2_dz, dp1_dz - dp3_dx, dp2_dx - dp1_dy) * curl_multiplier;
+//Rotates v by the unit quaternion q
+fn rotate_by(q: vec4<f32>, v: vec3<f32>) -> vec3<f32> {
+    return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
 }
 
+//Acceleration from one force of the stack, with d and v in the force's own frame
+fn local_force(force: Force, d: vec3<f32>, v: vec3<f32>) -> vec3<f32> {
+    if (force.region == REGION_BOX && any(abs(d) > force.region_size.xyz)) {
+        return vec3<f32>(0.0);
+    }
+    if (force.region == REGION_SPHERE && length(d) > force.region_size.x) {
+        return vec3<f32>(0.0);
+    }
+    let strength = force.position.w;
+    let axis = force.axis.xyz;
+    let radius = force.axis.w;
+    let falloff = force.region_size.w;
+    switch (force.kind) {
+        //Attractor
+        case 0u: {
+            return -safe_normalize(d) * strength * force_falloff(length(d), radius, falloff);
+        }
+        //Line vortex
+        case 1u: {
+            let radial = d - axis * dot(d, axis);
+            return safe_normalize(cross(axis, radial)) * strength * force_falloff(length(radial), radius, falloff);
+        }
+        //Toroidal vortex
+        case 2u: {
+            let ring = safe_normalize(d - axis * dot(d, axis)) * radius;
+            let from_core = d - ring;
+            let tangent = safe_normalize(cross(axis, ring));
+            //The core's own falloff distance is half the ring radius
+            return safe_normalize(cross(tangent, from_core)) * strength * force_falloff(length(from_core), radius * 0.5, falloff);
+        }
+        //Wind
+        case 3u: {
+            return (axis * strength - v) * WIND_COUPLING;
+        }
+        //Drag
+        case 4u: {
+            return -v * strength;
+        }
+        //Gravity
+        case 5u: {
+            return axis * strength;
+        }
+        //Turbulence
+        case 6u: {
+            let q = d / max(radius, 0.0001);
+            let gx = simplex3d_grad(q).xyz;
+            let gy = simplex3d_grad(q + vec3<f32>(1000.0, 0.0, 0.0)).xyz;
+            let gz = simplex3d_grad(q + vec3<f32>(2000.0, 0.0, 0.0)).xyz;
+            return vec3<f32>(gz.y - gy.z, gx.z - gz.x, gy.x - gx.y) * strength;
+        }
+        //Ray attractor
+        case 7u: {
+            let to_ray = axis * max(dot(d, axis), 0.0) - d;
+            return safe_normalize(to_ray) * strength * force_falloff(length(to_ray), radius, falloff);
+        }
+        default: {
+            return vec3<f32>(0.0);
+        }
+    }
+}
 
+//Acceleration from one force of the stack, see Force in forces.rs
+fn single_force(force: Force, p: vec3<f32>, v: vec3<f32>) -> vec3<f32> {
+    let inverse = force.rotation * vec4<f32>(-1.0, -1.0, -1.0, 1.0);
+    let d = rotate_by(inverse, p - force.position.xyz) / force.scale.xyz;
+    return rotate_by(force.rotation, local_force(force, d, rotate_by(inverse, v)));
+}
 
+//Summed acceleration of the force stack on a particle at p moving with v
+fn force_acceleration(p: vec3<f32>, v: vec3<f32>) -> vec3<f32> {
+    var acceleration = vec3<f32>(0.0);
+    for (var i = 0u; i < force_stack.count; i = i + 1u) {
+        acceleration = acceleration + single_force(force_stack.forces[i], p, v);
+    }
+    return acceleration;
+}
+
+let COLLIDER_GRADIENT_STEP: f32 = 0.25;
+
+//r32float can't be filtered, so the trilinear weights are done by hand. Like
+//MeshSdf::distance, outside the grid the distance to it is added on.
+fn sample_mesh_sdf(p: vec3<f32>) -> f32 {
+    let res = i32(collider_stack.mesh_resolution);
+    if (res == 0) {
+        return 1000000.0;
+    }
+    let clamped = clamp(p, vec3<f32>(-1.0), vec3<f32>(1.0));
+    let g = (clamped + 1.0) * 0.5 * f32(res) - 0.5;
+    let base = floor(g);
+    let t = g - base;
+    var sum = 0.0;
+    for (var corner = 0; corner < 8; corner = corner + 1) {
+        let offset = vec3<i32>(corner & 1, (corner >> 1u) & 1, (corner >> 2u) & 1);
+        let cell = clamp(vec3<i32>(base) + offset, vec3<i32>(0), vec3<i32>(res - 1));
+        let w = mix(1.0 - t, t, vec3<f32>(offset));
+        sum = sum + textureLoad(mesh_sdf, cell, 0).x * w.x * w.y * w.z;
+    }
+    return sum + length(p - clamped);
+}
+
+fn collider_distance(collider: Collider, p: vec3<f32>) -> f32 {
+    let d = p - collider.position.xyz;
+    let axis = collider.axis.xyz;
+    let size = collider.size.xyz;
+    switch (collider.shape) {
+        //Sphere
+        case 0u: {
+            return length(d) - size.x;
+        }
+        //Box
+        case 1u: {
+            let q = abs(d) - size;
+            return length(max(q, vec3<f32>(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0);
+        }
+        //Capsule
+        case 2u: {
+            let t = clamp(dot(d, axis), -size.y, size.y);
+            return length(d - axis * t) - size.x;
+        }
+        //Torus
+        case 3u: {
+            let height = dot(d, axis);
+            let radial = length(d - axis * height);
+            return length(vec2<f32>(radial - size.x, height)) - size.y;
+        }
+        //Plane
+        case 4u: {
+            return dot(d, axis);
+        }
+        //Mesh
+        case 5u: {
+            return sample_mesh_sdf(d / size.x) * size.x;
+        }
+        default: {
+            return 1000000.0;
+        }
+    }
+}
+
+//Signed distance to the colliders combined in order, positive outside
+fn scene_distance(p: vec3<f32>) -> f32 {
+    var distance = 1000000.0;
+    for (var i = 0u; i < collider_stack.count; i = i + 1u) {
+        let collider = collider_stack.colliders[i];
+        let d = collider_distance(collider, p);
+        if (collider.operation == 0u) {
+            distance = min(distance, d);
+        } else {
+            distance = max(distance, -d);
+        }
+    }
+    return distance;
+}
+
+//Tetrahedral differences, four distance samples instead of six
+fn scene_normal(p: vec3<f32>) -> vec3<f32> {
+    let h = COLLIDER_GRADIENT_STEP;
+    let k = vec2<f32>(1.0, -1.0);
+    let gradient = k.xyy * scene_distance(p + k.xyy * h)
+        + k.yyx * scene_distance(p + k.yyx * h)
+        + k.yxy * scene_distance(p + k.yxy * h)
+        + k.xxx * scene_distance(p + k.xxx * h);
+    if (dot(gradient, gradient) < 0.000000000001) {
+        return vec3<f32>(0.0, 1.0, 0.0);
+    }
+    return normalize(gradient);
+}
+
+struct Collision {
+    position: vec3<f32>,
+    velocity: vec3<f32>,
+};
+
+//Pushes particles inside or touching a collider back out to the margin, and takes away or
+//reflects the velocity going into the surface
+fn collide(p: vec3<f32>, v: vec3<f32>) -> Collision {
+    if (collider_stack.count == 0u) {
+        return Collision(p, v);
+    }
+    let distance = scene_distance(p);
+    if (distance >= collider_stack.margin) {
+        return Collision(p, v);
+    }
+    let normal = scene_normal(p);
+    let position = p + normal * (collider_stack.margin - distance);
+    let into = dot(v, normal);
+    var velocity = v;
+    if (into < 0.0) {
+        velocity = v - normal * into * (1.0 + collider_stack.restitution);
+    }
+    return Collision(position, velocity);
+}
 
 fn clamp_position(pos: vec3<f32>) -> vec3<f32> {
     var p = pos;
@@ -177,9 +706,66 @@ fn clamp_position(pos: vec3<f32>) -> vec3<f32> {
     return p;
 }
 
+struct TrailParameters {
+    length: u32,
+    count: u32,
+    head: u32,
+    reset: u32,
+    width: f32,
+    alpha: f32,
+};
+
+struct TrailHistory {
+    points: array<vec4<f32>>,
+};
+
 @group(0) @binding(0) var<storage, read> particles_src : Particles;
 @group(0) @binding(1) var<storage, read_write> particles_dst : Particles;
-@group(1) @binding(0) var<storage, read> parameters : ParticleSystemParameters;
+@group(2) @binding(0) var<storage, read_write> trail_history : TrailHistory;
+@group(2) @binding(1) var<uniform> trail : TrailParameters;
+
+//Writes this step's position into the particle's slot of the trail ring buffer.
+//A reset (new allocation, or a jump from wrapping at the boundary) fills the whole trail
+//so no ribbon gets stretched across the domain.
+fn record_trail(index: u32, position: vec3<f32>, reset: bool) {
+    if (index >= trail.count || trail.length == 0u) {
+        return;
+    }
+    let base = index * trail.length;
+    let trail_point = vec4<f32>(position, 1.0);
+    if (reset || trail.reset != 0u) {
+        for (var i: u32 = 0u; i < trail.length; i = i + 1u) {
+            trail_history.points[base + i] = trail_point;
+        }
+    } else {
+        trail_history.points[base + trail.head] = trail_point;
+    }
+}
+
+//Velocity relaxed towards the flow
+fn follow_field(p: vec3<f32>, velocity: vec3<f32>) -> vec3<f32> {
+    let flow_blend = clamp(parameters.p_field_response * DT, 0.0, 1.0);
+    return mix(velocity, field_velocity(p), flow_blend);
+}
+
+//Moves the particle by the new velocity, bounces it off the colliders, wraps it at the
+//boundary and writes it out
+fn advance_particle(index: u32, particle: Particle, velocity: vec3<f32>) {
+    var part = particle;
+    let p = part.position.xyz;
+    let collision = collide(p + velocity * DT, velocity);
+    let new_velocity = collision.velocity;
+    let moved_position = collision.position;
+    let new_position = clamp_position(moved_position);
+    let wrapped = any(new_position != moved_position);
+    record_trail(index, new_position, wrapped);
+
+    part.position = vec4<f32>(new_position,1.0);
+    //w is the particle's age, used to pick flipbook frames
+    part.velocity = vec4<f32>(new_velocity,part.velocity.w + DT);
+    
+    particles_dst.particles[index] = part;
+}
 
 @compute @workgroup_size(64)
 fn main(
@@ -191,20 +777,11 @@ fn main(
     if (index >= total) {
         return;
     }
-    var part = particles_src.particles[index];
+    let part = particles_src.particles[index];
     let p = part.position.xyz;
     
-    let potential_velocity = potential(p);
-    let curl_velocity = curl(p);
-    let new_velocity = mix(potential_velocity,curl_velocity,v_curl_mix);
-
-    var new_position = p + new_velocity * DT;
-    new_position = clamp_position(new_position);
-
-    part.position = vec4<f32>(new_position,1.0);
-    part.velocity = vec4<f32>(new_velocity,0.0);
-    
-    particles_dst.particles[index] = part;
+    //Velocity relaxes towards the flow, the force stack accelerates it on top
+    let velocity = part.velocity.xyz;
+    let accelerated = follow_field(p, velocity) + force_acceleration(p, velocity) * DT;
+    advance_particle(index, part, accelerated);
 }
-
-
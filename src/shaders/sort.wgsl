@@ -0,0 +1,75 @@
+struct Particle {
+    position: vec4<f32>,
+    velocity: vec4<f32>,
+    color: vec4<f32>,
+};
+
+struct Particles {
+    particles: array<Particle>,
+};
+
+struct SortEntry {
+    depth: f32,
+    index: u32,
+};
+
+struct SortEntries {
+    entries: array<SortEntry>,
+};
+
+struct CameraUniform {
+    _mat: mat4x4<f32>,
+};
+
+struct SortStep {
+    j: u32,
+    k: u32,
+};
+
+@group(0) @binding(0) var<storage, read> particles_src : Particles;
+@group(0) @binding(1) var<storage, read_write> sort_entries : SortEntries;
+@group(1) @binding(0) var<uniform> camera_view: CameraUniform;
+@group(2) @binding(0) var<uniform> step: SortStep;
+
+//Padding entries get the smallest depth so they end up behind the real particles
+let PADDING_DEPTH: f32 = -3.4e38;
+
+@compute @workgroup_size(256)
+fn compute_keys(
+  @builtin(global_invocation_id) global_id : vec3<u32>,
+) {
+    let index: u32 = global_id.x;
+    if (index >= arrayLength(&sort_entries.entries)) {
+        return;
+    }
+
+    var entry: SortEntry;
+    entry.index = index;
+    entry.depth = PADDING_DEPTH;
+    if (index < arrayLength(&particles_src.particles)) {
+        let view_pos = camera_view._mat * vec4<f32>(particles_src.particles[index].position.xyz, 1.0);
+        //Camera looks down -z, so distance from the camera is -z
+        entry.depth = -view_pos.z;
+    }
+    sort_entries.entries[index] = entry;
+}
+
+//One compare and swap step of a bitonic sort. Sorts by depth, farthest first.
+@compute @workgroup_size(256)
+fn bitonic_step(
+  @builtin(global_invocation_id) global_id : vec3<u32>,
+) {
+    let i: u32 = global_id.x;
+    let l: u32 = i ^ step.j;
+    if (l <= i || l >= arrayLength(&sort_entries.entries)) {
+        return;
+    }
+
+    let a = sort_entries.entries[i];
+    let b = sort_entries.entries[l];
+    let descending = (i & step.k) == 0u;
+    if ((descending && a.depth < b.depth) || (!descending && a.depth > b.depth)) {
+        sort_entries.entries[i] = b;
+        sort_entries.entries[l] = a;
+    }
+}
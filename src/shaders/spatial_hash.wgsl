@@ -0,0 +1,57 @@
+//Appended to spatial_hash_common.wgsl by shaders that look up neighbours, with the built
+//hash bound at group 3. Visiting every particle within cell_size of p goes
+//
+//    var cells = neighbour_cells(p);
+//    for (var c = 0u; c < cells.count; c = c + 1u) {
+//        let range = cell_range(cells.hashes[c]);
+//        for (var k = range.x; k < range.y; k = k + 1u) {
+//            let j = hash_sorted.cells[k];
+//            ...
+//        }
+//    }
+//
+//Hash collisions put far away particles in the same cells, so the distance still needs
+//checking.
+
+struct HashCells {
+    cells: array<u32>,
+};
+
+@group(3) @binding(0) var<uniform> hash : SpatialHashParameters;
+//Every cell's particle count, then where each cell's particles start in hash_sorted. One
+//binding keeps the pipelines that query the hash under the storage buffer limit.
+@group(3) @binding(1) var<storage, read> hash_cells : HashCells;
+@group(3) @binding(2) var<storage, read> hash_sorted : HashCells;
+
+struct NeighbourCells {
+    hashes: array<u32, 27>,
+    count: u32,
+};
+
+//Hashes of the 27 cells around p, each only once even when two of them collide
+fn neighbour_cells(p: vec3<f32>) -> NeighbourCells {
+    var cells: NeighbourCells;
+    cells.count = 0u;
+    let center = hash_cell_coord(p, hash.cell_size);
+    for (var i = 0; i < 27; i = i + 1) {
+        let offset = vec3<i32>(i % 3, (i / 3) % 3, i / 9) - 1;
+        let h = hash_cell(center + offset, hash.table_size);
+        var seen = false;
+        for (var j = 0u; j < cells.count; j = j + 1u) {
+            if (cells.hashes[j] == h) {
+                seen = true;
+            }
+        }
+        if (!seen) {
+            cells.hashes[cells.count] = h;
+            cells.count = cells.count + 1u;
+        }
+    }
+    return cells;
+}
+
+//Range of sorted_indices holding the particles hashed to cell, end exclusive
+fn cell_range(cell: u32) -> vec2<u32> {
+    let start = hash_cells.cells[hash.table_size + cell];
+    return vec2<u32>(start, start + hash_cells.cells[cell]);
+}
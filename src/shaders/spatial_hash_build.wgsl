@@ -0,0 +1,131 @@
+//Appended to spatial_hash_common.wgsl. Counting sort of the particles by cell hash:
+//clear_cells, count_particles, scan_blocks, scan_block_sums, add_block_offsets and
+//scatter_particles in that order.
+
+struct Particle {
+    position: vec4<f32>,
+    velocity: vec4<f32>,
+    color: vec4<f32>,
+};
+
+struct Particles {
+    particles: array<Particle>,
+};
+
+struct AtomicCells {
+    cells: array<atomic<u32>>,
+};
+
+struct Cells {
+    cells: array<u32>,
+};
+
+@group(0) @binding(0) var<storage, read> particles_src : Particles;
+
+@group(1) @binding(0) var<uniform> hash : SpatialHashParameters;
+@group(1) @binding(1) var<storage, read_write> cell_counts : AtomicCells;
+//Exclusive prefix sum of cell_counts, where each cell's particles start in sorted_indices
+@group(1) @binding(2) var<storage, read_write> cell_starts : Cells;
+@group(1) @binding(3) var<storage, read_write> block_sums : Cells;
+@group(1) @binding(4) var<storage, read_write> particle_cells : Cells;
+//Each particle's slot among the particles of its cell
+@group(1) @binding(5) var<storage, read_write> particle_ranks : Cells;
+@group(1) @binding(6) var<storage, read_write> sorted_indices : Cells;
+
+var<workgroup> scan_buffer: array<u32, 256>;
+var<workgroup> scan_carry: u32;
+
+@compute @workgroup_size(256)
+fn clear_cells(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if (global_id.x < hash.table_size) {
+        atomicStore(&cell_counts.cells[global_id.x], 0u);
+    }
+}
+
+@compute @workgroup_size(256)
+fn count_particles(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let index = global_id.x;
+    if (index >= hash.particle_count) {
+        return;
+    }
+    let p = particles_src.particles[index].position.xyz;
+    let cell = hash_cell(hash_cell_coord(p, hash.cell_size), hash.table_size);
+    particle_cells.cells[index] = cell;
+    particle_ranks.cells[index] = atomicAdd(&cell_counts.cells[cell], 1u);
+}
+
+//Hillis Steele inclusive scan of scan_buffer, every invocation of the workgroup has to call it
+fn scan_workgroup(local: u32) {
+    for (var offset = 1u; offset < HASH_SCAN_BLOCK; offset = offset * 2u) {
+        var add = 0u;
+        if (local >= offset) {
+            add = scan_buffer[local - offset];
+        }
+        workgroupBarrier();
+        scan_buffer[local] = scan_buffer[local] + add;
+        workgroupBarrier();
+    }
+}
+
+//Exclusive scan within each block of HASH_SCAN_BLOCK cells, block totals into block_sums
+@compute @workgroup_size(256)
+fn scan_blocks(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(workgroup_id) group_id: vec3<u32>,
+) {
+    let local = local_id.x;
+    let count = atomicLoad(&cell_counts.cells[global_id.x]);
+    scan_buffer[local] = count;
+    workgroupBarrier();
+    scan_workgroup(local);
+    cell_starts.cells[global_id.x] = scan_buffer[local] - count;
+    if (local == HASH_SCAN_BLOCK - 1u) {
+        block_sums.cells[group_id.x] = scan_buffer[local];
+    }
+}
+
+//Exclusive scan of the block totals in place, by a single workgroup a block at a time
+@compute @workgroup_size(256)
+fn scan_block_sums(@builtin(local_invocation_id) local_id: vec3<u32>) {
+    let local = local_id.x;
+    let blocks = hash.table_size / HASH_SCAN_BLOCK;
+    if (local == 0u) {
+        scan_carry = 0u;
+    }
+    for (var base = 0u; base < blocks; base = base + HASH_SCAN_BLOCK) {
+        var total = 0u;
+        if (base + local < blocks) {
+            total = block_sums.cells[base + local];
+        }
+        scan_buffer[local] = total;
+        workgroupBarrier();
+        scan_workgroup(local);
+        let carry = scan_carry;
+        if (base + local < blocks) {
+            block_sums.cells[base + local] = carry + scan_buffer[local] - total;
+        }
+        workgroupBarrier();
+        if (local == HASH_SCAN_BLOCK - 1u) {
+            scan_carry = carry + scan_buffer[local];
+        }
+        workgroupBarrier();
+    }
+}
+
+@compute @workgroup_size(256)
+fn add_block_offsets(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if (global_id.x < hash.table_size) {
+        cell_starts.cells[global_id.x] = cell_starts.cells[global_id.x] + block_sums.cells[global_id.x / HASH_SCAN_BLOCK];
+    }
+}
+
+@compute @workgroup_size(256)
+fn scatter_particles(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let index = global_id.x;
+    if (index >= hash.particle_count) {
+        return;
+    }
+    let cell = particle_cells.cells[index];
+    sorted_indices.cells[cell_starts.cells[cell] + particle_ranks.cells[index]] = index;
+}
@@ -0,0 +1,22 @@
+//Shared by the spatial hash build passes and the neighbour queries. Particles are binned
+//into cubes of cell_size, and the cubes hashed into a table of table_size counters.
+
+struct SpatialHashParameters {
+    cell_size: f32,
+    //A power of two, at least HASH_SCAN_BLOCK
+    table_size: u32,
+    particle_count: u32,
+    _padding: u32,
+};
+
+let HASH_SCAN_BLOCK: u32 = 256u;
+
+fn hash_cell_coord(p: vec3<f32>, cell_size: f32) -> vec3<i32> {
+    return vec3<i32>(floor(p / cell_size));
+}
+
+//Large primes from Teschner et al., wrapping multiplies
+fn hash_cell(coord: vec3<i32>, table_size: u32) -> u32 {
+    let c = vec3<u32>(coord);
+    return ((c.x * 73856093u) ^ (c.y * 19349663u) ^ (c.z * 83492791u)) & (table_size - 1u);
+}
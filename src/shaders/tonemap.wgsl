@@ -0,0 +1,120 @@
+struct TonemapParameters {
+    exposure: f32,
+    bloom_intensity: f32,
+    mode: u32,
+    output_srgb: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var t_hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var s_hdr: sampler;
+@group(0) @binding(2)
+var t_bloom: texture_2d<f32>;
+@group(0) @binding(3)
+var<uniform> params: TonemapParameters;
+
+let TONEMAP_ACES: u32 = 0u;
+let TONEMAP_REINHARD: u32 = 1u;
+let TONEMAP_AGX: u32 = 2u;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn luminance(c: vec3<f32>) -> f32 {
+    return dot(c, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+//ACES fitted curve by Stephen Hill
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let input_mat = mat3x3<f32>(
+        vec3<f32>(0.59719, 0.07600, 0.02840),
+        vec3<f32>(0.35458, 0.90834, 0.13383),
+        vec3<f32>(0.04823, 0.01566, 0.83777)
+    );
+    let output_mat = mat3x3<f32>(
+        vec3<f32>(1.60475, -0.10208, -0.00327),
+        vec3<f32>(-0.53108, 1.10813, -0.07276),
+        vec3<f32>(-0.07367, -0.00605, 1.07602)
+    );
+    let v = input_mat * color;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return clamp(output_mat * (a / b), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+//Reinhard on luminance so hue is preserved
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    let white = 4.0;
+    let l = luminance(color);
+    let mapped = l * (1.0 + l / (white * white)) / (1.0 + l);
+    return clamp(color * (mapped / max(l, 0.0001)), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+//Minimal AgX fit by Benjamin Wrensch
+fn agx_contrast(x: vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+fn agx(color: vec3<f32>) -> vec3<f32> {
+    let agx_mat = mat3x3<f32>(
+        vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104)
+    );
+    let agx_mat_inv = mat3x3<f32>(
+        vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116)
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    var v = agx_mat * color;
+    v = clamp(log2(max(v, vec3<f32>(1e-10))), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    v = (v - min_ev) / (max_ev - min_ev);
+    v = agx_contrast(v);
+    //Back to linear so the sRGB swapchain encodes it like the other operators
+    v = agx_mat_inv * v;
+    return pow(clamp(v, vec3<f32>(0.0), vec3<f32>(1.0)), vec3<f32>(2.2));
+}
+
+fn linear_to_srgb(c: vec3<f32>) -> vec3<f32> {
+    let low = c * 12.92;
+    let high = 1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, c <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let hdr = textureSample(t_hdr, s_hdr, in.uv).rgb;
+    let bloom = textureSample(t_bloom, s_hdr, in.uv).rgb;
+    let color = (hdr + bloom * params.bloom_intensity) * params.exposure;
+
+    var mapped: vec3<f32>;
+    if (params.mode == TONEMAP_REINHARD) {
+        mapped = reinhard(color);
+    } else if (params.mode == TONEMAP_AGX) {
+        mapped = agx(color);
+    } else {
+        mapped = aces(color);
+    }
+
+    if (params.output_srgb == 0u) {
+        mapped = linear_to_srgb(mapped);
+    }
+    return vec4<f32>(mapped, 1.0);
+}
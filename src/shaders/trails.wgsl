@@ -0,0 +1,100 @@
+struct TrailParameters {
+    length: u32,
+    count: u32,
+    head: u32,
+    reset: u32,
+    width: f32,
+    alpha: f32,
+    clip: ClipParameters,
+};
+
+struct TrailHistory {
+    points: array<vec4<f32>>,
+};
+
+struct CameraUniform {
+    _mat: mat4x4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<storage, read> history: TrailHistory;
+@group(0) @binding(1)
+var<uniform> trail: TrailParameters;
+
+@group(1) @binding(0)
+var<uniform> camera_view: CameraUniform;
+@group(1) @binding(1)
+var<uniform> camera_projection: CameraUniform;
+@group(1) @binding(2)
+var<uniform> camera_view_inv: CameraUniform;
+
+//Point i of a particle's trail, 0 is the newest
+fn trail_point(particle: u32, i: u32) -> vec3<f32> {
+    let slot = (trail.head + trail.length - i) % trail.length;
+    return history.points[particle * trail.length + slot].xyz;
+}
+
+//1 at the head of the trail, fading to 0 at the tail
+fn taper(i: u32) -> f32 {
+    return 1.0 - f32(i) / f32(max(trail.length - 1u, 1u));
+}
+
+fn trail_color(i: u32) -> vec4<f32> {
+    return vec4<f32>(0.5, 0.5, 0.5, trail.alpha * taper(i));
+}
+
+//Camera facing ribbon, two vertices per trail point drawn as a triangle strip
+@vertex
+fn vs_ribbon(
+    @builtin(vertex_index) vertex: u32,
+    @builtin(instance_index) particle: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+    let i = vertex / 2u;
+    let side = f32(vertex % 2u) * 2.0 - 1.0;
+
+    let p = trail_point(particle, i);
+    let newer = trail_point(particle, max(i, 1u) - 1u);
+    let older = trail_point(particle, min(i + 1u, trail.length - 1u));
+    let tangent = newer - older;
+
+    //Face the camera, or the view direction when orthographic
+    var to_camera = camera_view_inv._mat[3].xyz - p;
+    if (camera_projection._mat[2][3] == 0.0) {
+        to_camera = camera_view_inv._mat[2].xyz;
+    }
+    let across = cross(tangent, to_camera);
+    var offset = vec3<f32>(0.0);
+    //Points collapse onto each other right after a reset, leave those ribbons degenerate
+    if (length(across) > 0.000001) {
+        offset = normalize(across) * side * trail.width * 0.5 * taper(i);
+    }
+
+    out.clip_position = camera_projection._mat * camera_view._mat * vec4<f32>(p + offset, 1.0);
+    out.color = trail_color(i);
+    out.color.a *= clip_visibility(trail.clip, p);
+    return out;
+}
+
+@vertex
+fn vs_line(
+    @builtin(vertex_index) vertex: u32,
+    @builtin(instance_index) particle: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+    let p = trail_point(particle, vertex);
+    out.clip_position = camera_projection._mat * camera_view._mat * vec4<f32>(p, 1.0);
+    out.color = trail_color(vertex);
+    out.color.a *= clip_visibility(trail.clip, p);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
@@ -0,0 +1,100 @@
+struct VolumeParameters {
+    color_low: vec4<f32>,
+    color_high: vec4<f32>,
+    resolution: u32,
+    steps: u32,
+    use_velocity: u32,
+    num_particles: u32,
+    extent: f32,
+    density_scale: f32,
+    absorption: f32,
+    emission: f32,
+    speed_scale: f32,
+    clip: ClipParameters,
+};
+
+struct CameraUniform {
+    _mat: mat4x4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@group(0) @binding(0) var volume_texture: texture_3d<f32>;
+@group(0) @binding(1) var volume_sampler: sampler;
+@group(0) @binding(2) var<uniform> volume: VolumeParameters;
+
+@group(1) @binding(0)
+var<uniform> camera_view: CameraUniform;
+@group(1) @binding(1)
+var<uniform> camera_projection: CameraUniform;
+@group(1) @binding(2)
+var<uniform> camera_view_inv: CameraUniform;
+@group(1) @binding(3)
+var<uniform> camera_projection_inv: CameraUniform;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+//Point on the near (z = 0) or far (z = 1) plane under a pixel, in world space
+fn unproject(ndc: vec2<f32>, z: f32) -> vec3<f32> {
+    let view = camera_projection_inv._mat * vec4<f32>(ndc, z, 1.0);
+    return (camera_view_inv._mat * vec4<f32>(view.xyz / view.w, 1.0)).xyz;
+}
+
+//Interleaved gradient noise, jitters the first step to hide slicing artifacts
+fn jitter(pixel: vec2<f32>) -> f32 {
+    return fract(52.9829189 * fract(dot(pixel, vec2<f32>(0.06711056, 0.00583715))));
+}
+
+fn transfer(density: f32, speed: f32) -> vec3<f32> {
+    var t = clamp(log2(1.0 + density) / 10.0, 0.0, 1.0);
+    if (volume.use_velocity != 0u) {
+        t = clamp(speed * volume.speed_scale, 0.0, 1.0);
+    }
+    return mix(volume.color_low.rgb, volume.color_high.rgb, t);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let ndc = vec2<f32>(in.uv.x * 2.0 - 1.0, 1.0 - in.uv.y * 2.0);
+    let origin = unproject(ndc, 0.0);
+    let dir = normalize(unproject(ndc, 1.0) - origin);
+
+    //Slab test against the simulation bounds
+    let inv_dir = 1.0 / dir;
+    let t0 = (vec3<f32>(-volume.extent) - origin) * inv_dir;
+    let t1 = (vec3<f32>(volume.extent) - origin) * inv_dir;
+    let t_min = min(t0, t1);
+    let t_max = max(t0, t1);
+    let t_near = max(max(max(t_min.x, t_min.y), t_min.z), 0.0);
+    let t_far = min(min(t_max.x, t_max.y), t_max.z);
+    if (t_far <= t_near) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+
+    let step_length = (t_far - t_near) / f32(volume.steps);
+    var t = t_near + step_length * jitter(in.clip_position.xy);
+    var radiance = vec3<f32>(0.0);
+    var transmittance = 1.0;
+    for (var i = 0u; i < volume.steps; i += 1u) {
+        let p = origin + dir * t;
+        let cell = textureSampleLevel(volume_texture, volume_sampler, p / volume.extent * 0.5 + 0.5, 0.0);
+        let sigma = cell.r * volume.density_scale;
+        radiance += transmittance * transfer(cell.r, cell.g) * sigma * volume.emission * step_length;
+        transmittance *= exp(-sigma * volume.absorption * step_length);
+        if (transmittance < 0.005) {
+            break;
+        }
+        t += step_length;
+    }
+    return vec4<f32>(radiance, 1.0);
+}
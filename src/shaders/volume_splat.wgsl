@@ -0,0 +1,93 @@
+struct Particle {
+    position: vec4<f32>,
+    velocity: vec4<f32>,
+    color: vec4<f32>,
+};
+
+struct Particles {
+    particles: array<Particle>,
+};
+
+struct Grid {
+    cells: array<atomic<u32>>,
+};
+
+struct VolumeParameters {
+    color_low: vec4<f32>,
+    color_high: vec4<f32>,
+    resolution: u32,
+    steps: u32,
+    use_velocity: u32,
+    num_particles: u32,
+    extent: f32,
+    density_scale: f32,
+    absorption: f32,
+    emission: f32,
+    speed_scale: f32,
+    clip: ClipParameters,
+};
+
+@group(0) @binding(0) var<storage, read> particles_src: Particles;
+@group(0) @binding(1) var<storage, read_write> density: Grid;
+@group(0) @binding(2) var<storage, read_write> speed: Grid;
+@group(0) @binding(3) var<uniform> volume: VolumeParameters;
+@group(0) @binding(4) var volume_texture: texture_storage_3d<rgba16float, write>;
+
+//Fixed point scales for the atomic accumulation. A whole particle adds DENSITY_ONE
+//to the cells around it, so a u32 holds the full particle count without overflowing.
+let DENSITY_ONE: f32 = 256.0;
+let SPEED_ONE: f32 = 4.0;
+let MAX_SPEED: f32 = 1000.0;
+
+fn cell_index(cell: vec3<u32>) -> u32 {
+    return cell.x + volume.resolution * (cell.y + volume.resolution * cell.z);
+}
+
+//Trilinear splat of every particle into the 8 cells around it
+@compute @workgroup_size(256)
+fn splat(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let index = global_id.x;
+    if (index >= volume.num_particles) {
+        return;
+    }
+    let particle = particles_src.particles[index];
+    let res = f32(volume.resolution);
+    let grid_pos = (particle.position.xyz / volume.extent * 0.5 + 0.5) * res - 0.5;
+    let base = floor(grid_pos);
+    let f = grid_pos - base;
+    let particle_speed = min(length(particle.velocity.xyz), MAX_SPEED);
+    //Clipped particles add less density, like they're drawn fainter
+    let visibility = clip_visibility(volume.clip, particle.position.xyz);
+
+    for (var corner = 0u; corner < 8u; corner += 1u) {
+        let offset = vec3<u32>(corner & 1u, (corner >> 1u) & 1u, (corner >> 2u) & 1u);
+        let cell = base + vec3<f32>(offset);
+        if (any(cell < vec3<f32>(0.0)) || any(cell >= vec3<f32>(res))) {
+            continue;
+        }
+        let w3 = select(1.0 - f, f, offset == vec3<u32>(1u));
+        let w = w3.x * w3.y * w3.z * visibility;
+        let i = cell_index(vec3<u32>(cell));
+        atomicAdd(&density.cells[i], u32(w * DENSITY_ONE));
+        if (volume.use_velocity != 0u) {
+            atomicAdd(&speed.cells[i], u32(w * particle_speed * SPEED_ONE));
+        }
+    }
+}
+
+//Converts the fixed point sums into the sampled volume texture and clears them for the next frame.
+//r is particles per cell, g the average speed in the cell.
+@compute @workgroup_size(4, 4, 4)
+fn resolve(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if (any(global_id >= vec3<u32>(volume.resolution))) {
+        return;
+    }
+    let i = cell_index(global_id);
+    let count = f32(atomicLoad(&density.cells[i])) / DENSITY_ONE;
+    let speed_sum = f32(atomicLoad(&speed.cells[i])) / SPEED_ONE;
+    atomicStore(&density.cells[i], 0u);
+    atomicStore(&speed.cells[i], 0u);
+
+    let average_speed = speed_sum / max(count, 0.0001);
+    textureStore(volume_texture, vec3<i32>(global_id), vec4<f32>(count, average_speed, 0.0, 1.0));
+}